@@ -0,0 +1,184 @@
+//! Detection of the charset of text response bodies.
+//!
+//! Follows the order browsers use: a byte order mark wins, then the `charset`
+//! parameter of `Content-Type`, then a declaration inside HTML (`<meta>`) or
+//! XML (`<?xml encoding?>`) documents. Labels are resolved as in the WHATWG
+//! Encoding standard, so e.g. `ISO-8859-1` decodes as `windows-1252`.
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// How far into an HTML document a `<meta>` charset declaration is looked for.
+const META_PRESCAN_LIMIT: usize = 1024;
+
+/// Returns the charset of the body, if the response declares one.
+pub fn detect_charset(content_type: Option<&str>, body: &[u8]) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return Some(encoding);
+    }
+    if let Some(encoding) = content_type
+        .and_then(charset_parameter)
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+    {
+        return Some(encoding);
+    }
+
+    let essence = content_type
+        .map(|ct| {
+            ct.split(';')
+                .next()
+                .unwrap_or(ct)
+                .trim()
+                .to_ascii_lowercase()
+        })
+        .unwrap_or_default();
+    let is_html = essence.is_empty() || essence == "text/html";
+    let is_xml = essence.is_empty() || essence.ends_with("/xml") || essence.ends_with("+xml");
+    let declared = if is_xml && body.starts_with(b"<?xml") {
+        xml_declaration(body)
+    } else if is_html {
+        meta_charset(body)
+    } else {
+        None
+    };
+    declared
+        .and_then(Encoding::for_label)
+        // A document can't declare itself UTF-16 in ASCII, the declaration lies
+        .map(|encoding| {
+            if encoding == UTF_16LE || encoding == UTF_16BE {
+                UTF_8
+            } else {
+                encoding
+            }
+        })
+}
+
+/// Decodes the body to text, dropping a byte order mark. Malformed sequences
+/// are replaced with U+FFFD.
+pub fn decode_text(encoding: &'static Encoding, body: &[u8]) -> String {
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+/// Whether bytes in the encoding are expected to include ones that would
+/// otherwise mark a body as binary.
+pub fn is_wide(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+/// The `charset` parameter of a `Content-Type` value.
+fn charset_parameter(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// The charset of an HTML `<meta charset>` or
+/// `<meta http-equiv="Content-Type" content="...; charset=...">` tag.
+fn meta_charset(body: &[u8]) -> Option<&[u8]> {
+    let head = &body[..body.len().min(META_PRESCAN_LIMIT)];
+    let mut rest = head;
+    while let Some(start) = find_ignore_case(rest, b"<meta") {
+        let tag = &rest[start..];
+        let tag = &tag[..tag.iter().position(|&b| b == b'>').unwrap_or(tag.len())];
+        if let Some(charset) = attribute_value(tag, b"charset") {
+            return Some(charset);
+        }
+        rest = &rest[start + tag.len()..];
+    }
+    None
+}
+
+/// The `encoding` of an XML declaration.
+fn xml_declaration(body: &[u8]) -> Option<&[u8]> {
+    let end = find_ignore_case(body, b"?>")?;
+    attribute_value(&body[..end], b"encoding")
+}
+
+/// The value following `name=` in the tag, with optional quotes removed.
+fn attribute_value<'a>(tag: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    let start = find_ignore_case(tag, name)? + name.len();
+    let rest = trim_start(&tag[start..]);
+    let rest = trim_start(rest.strip_prefix(b"=")?);
+    let rest = rest
+        .strip_prefix(b"\"")
+        .or_else(|| rest.strip_prefix(b"'"))
+        .unwrap_or(rest);
+    let end = rest
+        .iter()
+        .position(|b| matches!(b, b'"' | b'\'' | b';' | b'/' | b'>') || b.is_ascii_whitespace())
+        .unwrap_or(rest.len());
+    (end > 0).then(|| &rest[..end])
+}
+
+fn find_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle))
+}
+
+fn trim_start(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+    #[test]
+    fn test_content_type_charset() {
+        let detect = |ct| detect_charset(Some(ct), b"body").map(Encoding::name);
+        assert_eq!(detect("text/plain; charset=Shift_JIS"), Some("Shift_JIS"));
+        assert_eq!(detect("text/plain;charset=\"utf-8\""), Some("UTF-8"));
+        assert_eq!(
+            detect("text/plain; CHARSET=iso-8859-1"),
+            Some("windows-1252")
+        );
+        assert_eq!(detect("application/json"), None);
+        assert_eq!(detect("text/plain; charset=made-up"), None);
+    }
+
+    #[test]
+    fn test_byte_order_mark_wins() {
+        let body = b"\xff\xfeh\x00i\x00";
+        let encoding = detect_charset(Some("text/plain; charset=windows-1252"), body).unwrap();
+        assert_eq!(encoding, UTF_16LE);
+        assert_eq!(decode_text(encoding, body), "hi");
+        assert!(is_wide(encoding));
+    }
+
+    #[test]
+    fn test_html_meta_declarations() {
+        let html = b"<!doctype html><html><head><META Charset='windows-1252'></head>";
+        assert_eq!(detect_charset(Some("text/html"), html), Some(WINDOWS_1252));
+
+        let html = br#"<meta http-equiv="Content-Type" content="text/html; charset=Shift_JIS">"#;
+        assert_eq!(detect_charset(None, html), Some(SHIFT_JIS));
+
+        // UTF-16 can't be declared from inside an ASCII document
+        assert_eq!(detect_charset(None, b"<meta charset=utf-16>"), Some(UTF_8));
+        // Only HTML and XML documents declare their own charset
+        assert_eq!(
+            detect_charset(Some("text/plain"), b"<meta charset=shift_jis>"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_xml_declaration() {
+        let xml = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>caf\xe9</a>";
+        let encoding = detect_charset(Some("application/atom+xml"), xml).unwrap();
+        assert_eq!(encoding, WINDOWS_1252);
+        assert_eq!(
+            decode_text(encoding, xml),
+            "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>café</a>"
+        );
+    }
+}
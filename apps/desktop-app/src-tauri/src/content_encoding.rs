@@ -0,0 +1,491 @@
+//! Decoding of `Content-Encoding` compressed response bodies, whole or as
+//! they stream in.
+
+use anyhow::{anyhow, Result};
+use std::io::{self, Read, Write};
+
+/// Codings the relay advertises in `Accept-Encoding` and can decode.
+pub const SUPPORTED_ENCODINGS: &str = "gzip, deflate, br, zstd";
+
+/// Most bytes a body may decode to unless the request sets its own limit,
+/// so a small compressed body can't expand to gigabytes in memory.
+pub const DEFAULT_DECODED_LIMIT: usize = 256 * 1024 * 1024;
+
+/// Undoes the codings listed in a `Content-Encoding` header, which are
+/// applied in order and therefore removed in reverse. Bodies with a coding
+/// this relay doesn't know are returned as received. Fails once a coding
+/// decodes to more than `limit` bytes.
+pub fn decode_body(content_encoding: Option<&str>, body: Vec<u8>, limit: usize) -> Result<Vec<u8>> {
+    let codings = codings(content_encoding);
+    if codings.is_empty() || body.is_empty() || !codings.iter().all(|coding| is_supported(coding)) {
+        return Ok(body);
+    }
+
+    codings.iter().rev().try_fold(body, |body, coding| {
+        let decoded = decode(coding, &body, limit)
+            .map_err(|e| anyhow!("Failed to decode {} response body: {}", coding, e))?;
+        if decoded.len() > limit {
+            return Err(anyhow!(
+                "Decoded {} response body exceeds the limit of {} bytes",
+                coding,
+                limit
+            ));
+        }
+        Ok(decoded)
+    })
+}
+
+/// Decodes a body chunk by chunk as it arrives, for responses streamed to
+/// the frontend before they are complete.
+pub struct StreamDecoder {
+    /// One per coding, in the order they are removed
+    stages: Vec<(String, Stage)>,
+    limit: usize,
+}
+
+impl StreamDecoder {
+    /// A decoder for the codings of a `Content-Encoding` header, or `None`
+    /// when there is nothing to decode or a coding isn't supported, in which
+    /// case the body is passed on as received. Each chunk may decode to at
+    /// most `limit` bytes.
+    pub fn new(content_encoding: Option<&str>, limit: usize) -> Result<Option<Self>> {
+        let codings = codings(content_encoding);
+        if codings.is_empty() || !codings.iter().all(|coding| is_supported(coding)) {
+            return Ok(None);
+        }
+        let stages = codings
+            .into_iter()
+            .rev()
+            .map(|coding| {
+                let stage = Stage::new(&coding, limit)
+                    .map_err(|e| anyhow!("Failed to decode {} response body: {}", coding, e))?;
+                Ok((coding, stage))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(Self { stages, limit }))
+    }
+
+    /// Decodes the next chunk of the body, returning what could be decoded
+    /// so far. The rest is held back until more of the body arrives.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        let mut data = chunk.to_vec();
+        for (coding, stage) in &mut self.stages {
+            data = stage
+                .push(&data)
+                .map_err(|e| stage_error(coding, self.limit, e))?;
+        }
+        Ok(data)
+    }
+
+    /// Decodes what is left once the whole body was pushed.
+    pub fn finish(self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for (coding, mut stage) in self.stages {
+            let mut decoded = stage
+                .push(&data)
+                .map_err(|e| stage_error(&coding, self.limit, e))?;
+            decoded.extend(
+                stage
+                    .finish()
+                    .map_err(|e| stage_error(&coding, self.limit, e))?,
+            );
+            data = decoded;
+        }
+        Ok(data)
+    }
+}
+
+fn stage_error(coding: &str, limit: usize, error: io::Error) -> anyhow::Error {
+    if error.kind() == io::ErrorKind::OutOfMemory {
+        return anyhow!(
+            "Decoded {} response body chunk exceeds the limit of {} bytes",
+            coding,
+            limit
+        );
+    }
+    anyhow!("Failed to decode {} response body: {}", coding, error)
+}
+
+/// A decoder of one coding, writing decoded bytes to a buffer that is taken
+/// after each chunk.
+enum Stage {
+    Gzip(flate2::write::MultiGzDecoder<Output>),
+    /// Zlib or raw deflate is decided once the first two bytes are known
+    Deflate {
+        pending: Vec<u8>,
+        limit: usize,
+    },
+    Zlib(flate2::write::ZlibDecoder<Output>),
+    RawDeflate(flate2::write::DeflateDecoder<Output>),
+    Brotli(Box<brotli::DecompressorWriter<Output>>),
+    Zstd(zstd::stream::write::Decoder<'static, Output>),
+}
+
+impl Stage {
+    fn new(coding: &str, limit: usize) -> io::Result<Self> {
+        let output = Output::new(limit);
+        Ok(match coding {
+            "gzip" | "x-gzip" => Self::Gzip(flate2::write::MultiGzDecoder::new(output)),
+            "deflate" => Self::Deflate {
+                pending: Vec::new(),
+                limit,
+            },
+            "br" => Self::Brotli(Box::new(brotli::DecompressorWriter::new(output, 4096))),
+            "zstd" => Self::Zstd(zstd::stream::write::Decoder::new(output)?),
+            _ => unreachable!("unsupported codings are never decoded"),
+        })
+    }
+
+    fn push(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        if let Self::Deflate { pending, limit } = self {
+            pending.extend_from_slice(data);
+            if pending.len() < 2 {
+                return Ok(Vec::new());
+            }
+            let pending = std::mem::take(pending);
+            let output = Output::new(*limit);
+            *self = if is_zlib_header(&pending) {
+                Self::Zlib(flate2::write::ZlibDecoder::new(output))
+            } else {
+                // "deflate" should be zlib wrapped, but some servers send raw deflate
+                Self::RawDeflate(flate2::write::DeflateDecoder::new(output))
+            };
+            return self.push(&pending);
+        }
+        if data.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        match self {
+            Self::Gzip(decoder) => write_chunk(decoder, data),
+            Self::Zlib(decoder) => write_chunk(decoder, data),
+            Self::RawDeflate(decoder) => write_chunk(decoder, data),
+            Self::Brotli(decoder) => write_chunk(decoder.as_mut(), data),
+            Self::Zstd(decoder) => write_chunk(decoder, data),
+            Self::Deflate { .. } => unreachable!("handled above"),
+        }
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(decoder) => Ok(decoder.finish()?.data),
+            Self::Zlib(decoder) => Ok(decoder.finish()?.data),
+            Self::RawDeflate(decoder) => Ok(decoder.finish()?.data),
+            Self::Brotli(mut decoder) => {
+                decoder.close()?;
+                Ok(std::mem::take(decoder.output()))
+            }
+            Self::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner().data)
+            }
+            // A body of less than two bytes can't be deflate encoded
+            Self::Deflate { pending, .. } if pending.is_empty() => Ok(pending),
+            Self::Deflate { .. } => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "body ended before the deflate header",
+            )),
+        }
+    }
+}
+
+/// The buffer a stage decodes into. Writes that would grow it past `limit`
+/// bytes fail, so one small chunk can't expand to gigabytes in memory.
+struct Output {
+    data: Vec<u8>,
+    limit: usize,
+}
+
+impl Output {
+    fn new(limit: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            limit,
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.data.len() + buf.len() > self.limit {
+            return Err(io::ErrorKind::OutOfMemory.into());
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a chunk to a decoder and takes what it decoded.
+fn write_chunk<W: Write + WriterRef>(decoder: &mut W, data: &[u8]) -> io::Result<Vec<u8>> {
+    decoder.write_all(data)?;
+    decoder.flush()?;
+    Ok(std::mem::take(decoder.output()))
+}
+
+/// Access to the buffer a write-based decoder writes to.
+trait WriterRef {
+    fn output(&mut self) -> &mut Vec<u8>;
+}
+
+impl WriterRef for flate2::write::MultiGzDecoder<Output> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        &mut self.get_mut().data
+    }
+}
+
+impl WriterRef for flate2::write::ZlibDecoder<Output> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        &mut self.get_mut().data
+    }
+}
+
+impl WriterRef for flate2::write::DeflateDecoder<Output> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        &mut self.get_mut().data
+    }
+}
+
+impl WriterRef for brotli::DecompressorWriter<Output> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        &mut self.get_mut().data
+    }
+}
+
+impl WriterRef for zstd::stream::write::Decoder<'static, Output> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        &mut self.get_mut().data
+    }
+}
+
+/// Whether two bytes start a zlib stream (RFC 1950): deflate compression and
+/// a header checksum that is a multiple of 31.
+fn is_zlib_header(data: &[u8]) -> bool {
+    data[0] & 0x0f == 8 && (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 == 0
+}
+
+fn codings(content_encoding: Option<&str>) -> Vec<String> {
+    content_encoding
+        .unwrap_or_default()
+        .split(',')
+        .map(|coding| coding.trim().to_ascii_lowercase())
+        .filter(|coding| !coding.is_empty() && coding != "identity")
+        .collect()
+}
+
+fn is_supported(coding: &str) -> bool {
+    matches!(coding, "gzip" | "x-gzip" | "deflate" | "br" | "zstd")
+}
+
+/// Decodes at most one byte past `limit`, enough to tell that the limit
+/// was exceeded.
+fn decode(coding: &str, body: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let take = limit as u64 + 1;
+    let mut decoded = Vec::new();
+    match coding {
+        "gzip" | "x-gzip" => {
+            flate2::read::MultiGzDecoder::new(body)
+                .take(take)
+                .read_to_end(&mut decoded)?;
+        }
+        "deflate" => {
+            // "deflate" should be zlib wrapped, but some servers send raw deflate
+            if flate2::read::ZlibDecoder::new(body)
+                .take(take)
+                .read_to_end(&mut decoded)
+                .is_err()
+            {
+                decoded.clear();
+                flate2::read::DeflateDecoder::new(body)
+                    .take(take)
+                    .read_to_end(&mut decoded)?;
+            }
+        }
+        "br" => {
+            brotli::Decompressor::new(body, 4096)
+                .take(take)
+                .read_to_end(&mut decoded)?;
+        }
+        "zstd" => {
+            zstd::stream::read::Decoder::new(body)?
+                .take(take)
+                .read_to_end(&mut decoded)?;
+        }
+        _ => unreachable!("unsupported codings are never decoded"),
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const TEXT: &[u8] = b"Hello, compressed world! Hello, compressed world!";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decodes_each_supported_coding() {
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(TEXT).unwrap();
+        let mut raw_deflate =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw_deflate.write_all(TEXT).unwrap();
+        let mut br = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        br.write_all(TEXT).unwrap();
+
+        let cases = [
+            ("gzip", gzip(TEXT)),
+            ("deflate", zlib.finish().unwrap()),
+            ("deflate", raw_deflate.finish().unwrap()),
+            ("br", br.into_inner()),
+            ("zstd", zstd::stream::encode_all(TEXT, 3).unwrap()),
+        ];
+        for (coding, encoded) in cases {
+            assert_ne!(encoded, TEXT);
+            let decoded = decode_body(Some(coding), encoded, DEFAULT_DECODED_LIMIT).unwrap();
+            assert_eq!(decoded, TEXT, "{} was not decoded", coding);
+        }
+    }
+
+    #[test]
+    fn test_stacked_codings_are_removed_in_reverse() {
+        let encoded = zstd::stream::encode_all(gzip(TEXT).as_slice(), 3).unwrap();
+        let decoded = decode_body(Some("gzip, zstd"), encoded, DEFAULT_DECODED_LIMIT).unwrap();
+        assert_eq!(decoded, TEXT);
+    }
+
+    #[test]
+    fn test_identity_and_unknown_codings_pass_through() {
+        assert_eq!(
+            decode_body(None, TEXT.to_vec(), DEFAULT_DECODED_LIMIT).unwrap(),
+            TEXT
+        );
+        assert_eq!(
+            decode_body(Some("identity"), TEXT.to_vec(), DEFAULT_DECODED_LIMIT).unwrap(),
+            TEXT
+        );
+        assert_eq!(
+            decode_body(Some("gzip, compress"), TEXT.to_vec(), DEFAULT_DECODED_LIMIT).unwrap(),
+            TEXT
+        );
+        assert!(decode_body(Some("gzip"), Vec::new(), DEFAULT_DECODED_LIMIT)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_decoded_size_is_limited() {
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        assert!(bomb.len() < 4096);
+
+        let error = decode_body(Some("gzip"), bomb.clone(), 64 * 1024).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Decoded gzip response body exceeds the limit of 65536 bytes"
+        );
+
+        let decoded = decode_body(Some("gzip"), bomb, 1024 * 1024).unwrap();
+        assert_eq!(decoded.len(), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_stream_decoder_decodes_chunk_by_chunk() {
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        zlib.write_all(TEXT).unwrap();
+        let mut raw_deflate =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw_deflate.write_all(TEXT).unwrap();
+        let mut br = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+        br.write_all(TEXT).unwrap();
+
+        let cases = [
+            ("gzip", gzip(TEXT)),
+            ("deflate", zlib.finish().unwrap()),
+            ("deflate", raw_deflate.finish().unwrap()),
+            ("br", br.into_inner()),
+            ("zstd", zstd::stream::encode_all(TEXT, 3).unwrap()),
+            (
+                "gzip, zstd",
+                zstd::stream::encode_all(gzip(TEXT).as_slice(), 3).unwrap(),
+            ),
+        ];
+        for (coding, encoded) in cases {
+            let mut decoder = StreamDecoder::new(Some(coding), DEFAULT_DECODED_LIMIT)
+                .unwrap()
+                .unwrap();
+            let mut decoded = Vec::new();
+            for chunk in encoded.chunks(1) {
+                decoded.extend(decoder.push(chunk).unwrap());
+            }
+            decoded.extend(decoder.finish().unwrap());
+            assert_eq!(decoded, TEXT, "{} was not decoded", coding);
+        }
+    }
+
+    #[test]
+    fn test_stream_decoder_limits_each_chunk() {
+        let bomb = gzip(&vec![0; 1024 * 1024]);
+        for limit in [1024, 64 * 1024] {
+            let mut decoder = StreamDecoder::new(Some("gzip"), limit).unwrap().unwrap();
+            let error = decoder.push(&bomb).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "Decoded gzip response body chunk exceeds the limit of {} bytes",
+                    limit
+                )
+            );
+        }
+
+        // The limit applies to each chunk, not to the whole body
+        let mut decoder = StreamDecoder::new(Some("gzip"), 64 * 1024)
+            .unwrap()
+            .unwrap();
+        let mut decoded = 0;
+        for chunk in bomb.chunks(16) {
+            decoded += decoder.push(chunk).unwrap().len();
+        }
+        decoded += decoder.finish().unwrap().len();
+        assert_eq!(decoded, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_stream_decoder_skips_identity_and_unknown_codings() {
+        assert!(StreamDecoder::new(None, DEFAULT_DECODED_LIMIT)
+            .unwrap()
+            .is_none());
+        assert!(StreamDecoder::new(Some("identity"), DEFAULT_DECODED_LIMIT)
+            .unwrap()
+            .is_none());
+        assert!(
+            StreamDecoder::new(Some("gzip, compress"), DEFAULT_DECODED_LIMIT)
+                .unwrap()
+                .is_none()
+        );
+
+        let mut decoder = StreamDecoder::new(Some("gzip"), DEFAULT_DECODED_LIMIT)
+            .unwrap()
+            .unwrap();
+        let error = decoder.push(TEXT).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Failed to decode gzip response body"));
+    }
+
+    #[test]
+    fn test_corrupt_body_is_an_error() {
+        let error = decode_body(Some("gzip"), TEXT.to_vec(), DEFAULT_DECODED_LIMIT).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Failed to decode gzip response body"));
+    }
+}
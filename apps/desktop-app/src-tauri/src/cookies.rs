@@ -0,0 +1,866 @@
+//! Cookie jars (RFC 6265).
+//!
+//! Each workspace has a jar of its own. Cookies set by responses are stored
+//! following the storage model of section 5.3 and sent back with the requests
+//! they match (section 5.4). There is no public suffix list, so a `Domain`
+//! attribute is only refused when it has no dot at all, like `com`.
+//!
+//! Jars are saved as JSON after every change, on a thread of their own so
+//! requests never wait for the disk, and can be exchanged with other tools in
+//! the Netscape `cookies.txt` format used by curl and wget. Expired cookies
+//! are dropped whenever a jar changes. Unlike section 5.3 asks, session
+//! cookies are saved too and outlive a restart, like the login they usually
+//! stand for; they can be removed or cleared like any other cookie.
+
+use crate::types::Cookie;
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc};
+use hyper::header::{HeaderMap, SET_COOKIE};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use url::{Host, Url};
+
+/// Receives the message of a jar file that couldn't be read or saved.
+pub type ErrorCallback = Arc<dyn Fn(String) + Send + Sync>;
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Parses a `Set-Cookie` header received from `url`. Returns `None` for
+/// headers that must be ignored, e.g. for a domain `url` isn't on.
+pub fn parse_set_cookie(header: &str, url: &Url, now_millis: i64) -> Option<Cookie> {
+    let host = url.host_str()?.to_ascii_lowercase();
+    let mut attributes = header.split(';');
+    let (name, value) = attributes.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut cookie = Cookie {
+        name: name.to_string(),
+        value: value.trim().to_string(),
+        domain: host.clone(),
+        host_only: true,
+        path: default_path(url),
+        secure: false,
+        http_only: false,
+        same_site: None,
+        expires: None,
+        created_at: now_millis,
+    };
+    let mut max_age = None;
+    let mut expires = None;
+    for attribute in attributes {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "expires" => expires = parse_cookie_date(value).or(expires),
+            "max-age" => {
+                let digits = value.strip_prefix('-').unwrap_or(value);
+                if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                    // Negative values expire right away however long they
+                    // are, values too large to represent never expire
+                    max_age = Some(if value.starts_with('-') {
+                        0
+                    } else {
+                        value.parse::<i64>().unwrap_or(i64::MAX)
+                    });
+                }
+            }
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if !domain_matches(&host, url.host(), &domain)
+                    || !domain.contains('.') && domain != host
+                {
+                    return None;
+                }
+                cookie.host_only =
+                    domain == host && matches!(url.host(), Some(Host::Ipv4(_) | Host::Ipv6(_)));
+                cookie.domain = domain;
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "samesite" if !value.is_empty() => cookie.same_site = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    // Max-Age wins over Expires; a value of zero or less expires right away
+    cookie.expires = match max_age {
+        Some(seconds) => Some((now_millis / 1000).saturating_add(seconds)),
+        None => expires,
+    };
+    Some(cookie)
+}
+
+/// The directory of the request path, where cookies without a `Path`
+/// attribute apply (section 5.1.4).
+fn default_path(url: &Url) -> String {
+    let path = url.path();
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(last) => path[..last].to_string(),
+    }
+}
+
+/// Whether a request to `host` is on `domain` (section 5.1.3). IP addresses
+/// only match themselves.
+fn domain_matches(host: &str, parsed: Option<Host<&str>>, domain: &str) -> bool {
+    host == domain
+        || matches!(parsed, Some(Host::Domain(_)))
+            && host
+                .strip_suffix(domain)
+                .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+/// Whether a request to `path` is within `cookie_path` (section 5.1.4).
+fn path_matches(path: &str, cookie_path: &str) -> bool {
+    path.strip_prefix(cookie_path)
+        .is_some_and(|rest| cookie_path.ends_with('/') || rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Parses a date in any of the formats found in `Expires` attributes
+/// (section 5.1.1), e.g. `Wed, 21 Oct 2015 07:28:00 GMT` or
+/// `Wednesday, 21-Oct-15 07:28:00 GMT`. Returns seconds since the epoch.
+pub fn parse_cookie_date(value: &str) -> Option<i64> {
+    let is_delimiter = |c: char| matches!(c, '\t' | ' '..='/' | ';'..='@' | '['..='`' | '{'..='~');
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in value.split(is_delimiter).filter(|token| !token.is_empty()) {
+        let leading_digits = |max: usize| {
+            let digits = token.bytes().take_while(u8::is_ascii_digit).count();
+            (1..=max)
+                .contains(&digits)
+                .then(|| token[..digits].parse::<u32>().ok())
+                .flatten()
+        };
+        if time.is_none() {
+            let parts: Vec<_> = token.splitn(3, ':').collect();
+            if parts.len() == 3 {
+                let field = |part: &str| {
+                    let digits = part.bytes().take_while(u8::is_ascii_digit).count();
+                    (1..=2)
+                        .contains(&digits)
+                        .then(|| part[..digits].parse::<u32>().ok())
+                        .flatten()
+                };
+                if let (Some(h), Some(m), Some(s)) =
+                    (field(parts[0]), field(parts[1]), field(parts[2]))
+                {
+                    time = Some((h, m, s));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() {
+            if let Some(found) = leading_digits(2) {
+                day = Some(found);
+                continue;
+            }
+        }
+        if month.is_none() && token.len() >= 3 {
+            let prefix = token[..3].to_ascii_lowercase();
+            if let Some(index) = MONTHS.iter().position(|name| *name == prefix) {
+                month = Some(index as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() {
+            if let Some(found) = leading_digits(4)
+                .filter(|_| token.bytes().take_while(u8::is_ascii_digit).count() >= 2)
+            {
+                year = Some(found);
+                continue;
+            }
+        }
+    }
+
+    let year = match year? {
+        year @ 70..=99 => year + 1900,
+        year @ 0..=69 => year + 2000,
+        year => year,
+    };
+    let (hour, minute, second) = time?;
+    if year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let date = NaiveDate::from_ymd_opt(year as i32, month?, day?)?;
+    Some(
+        date.and_hms_opt(hour, minute, second)?
+            .and_utc()
+            .timestamp(),
+    )
+}
+
+fn is_expired(cookie: &Cookie, now_seconds: i64) -> bool {
+    cookie.expires.is_some_and(|expires| expires <= now_seconds)
+}
+
+/// Whether `cookie` is sent with a request to `url` (section 5.4).
+fn applies_to(cookie: &Cookie, url: &Url, now_seconds: i64) -> bool {
+    let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+        return false;
+    };
+    let domain_ok = if cookie.host_only {
+        host == cookie.domain
+    } else {
+        domain_matches(&host, url.host(), &cookie.domain)
+    };
+    domain_ok
+        && path_matches(url.path(), &cookie.path)
+        && (!cookie.secure || matches!(url.scheme(), "https" | "wss"))
+        && !is_expired(cookie, now_seconds)
+}
+
+/// Adds `cookie` to `jar`, replacing the one with the same name, domain and
+/// path. Expired cookies only remove the one they replace.
+fn upsert(jar: &mut Vec<Cookie>, mut cookie: Cookie, now_seconds: i64) {
+    if let Some(index) = jar.iter().position(|existing| {
+        existing.name == cookie.name
+            && existing.domain == cookie.domain
+            && existing.path == cookie.path
+    }) {
+        cookie.created_at = jar.remove(index).created_at;
+    }
+    if !is_expired(&cookie, now_seconds) {
+        jar.push(cookie);
+    }
+}
+
+/// The jars of a store. The jar of requests without a workspace is kept
+/// apart from the workspace jars, so no workspace id can stand for it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Jars {
+    #[serde(default)]
+    default: Vec<Cookie>,
+    #[serde(default)]
+    workspaces: HashMap<String, Vec<Cookie>>,
+}
+
+impl Jars {
+    fn get(&self, jar: Option<&str>) -> Option<&Vec<Cookie>> {
+        match jar {
+            Some(workspace) => self.workspaces.get(workspace),
+            None => Some(&self.default),
+        }
+    }
+
+    fn get_mut(&mut self, jar: Option<&str>) -> &mut Vec<Cookie> {
+        match jar {
+            Some(workspace) => self.workspaces.entry(workspace.to_string()).or_default(),
+            None => &mut self.default,
+        }
+    }
+
+    /// Drops the cookies expired by `now_seconds`, and the workspace jars
+    /// left empty.
+    fn remove_expired(&mut self, now_seconds: i64) {
+        self.default
+            .retain(|cookie| !is_expired(cookie, now_seconds));
+        for cookies in self.workspaces.values_mut() {
+            cookies.retain(|cookie| !is_expired(cookie, now_seconds));
+        }
+        self.workspaces.retain(|_, cookies| !cookies.is_empty());
+    }
+}
+
+enum SaveRequest {
+    Save,
+    /// Answered once every change made before it was saved
+    Flush(mpsc::Sender<()>),
+}
+
+/// Starts the thread saving `jars` to `path`, reporting failures to
+/// `on_error`. Changes made while a save is running are written together by
+/// the next one. The thread ends once every sender is dropped.
+fn spawn_saver(
+    jars: Arc<Mutex<Jars>>,
+    path: PathBuf,
+    on_error: ErrorCallback,
+) -> Result<mpsc::Sender<SaveRequest>> {
+    let (requests, received) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("cookie-saver".to_string())
+        .spawn(move || {
+            while let Ok(request) = received.recv() {
+                let mut save = false;
+                let mut flushes = Vec::new();
+                for request in std::iter::once(request).chain(received.try_iter()) {
+                    match request {
+                        SaveRequest::Save => save = true,
+                        SaveRequest::Flush(done) => flushes.push(done),
+                    }
+                }
+                if save {
+                    // Cloned so changes don't wait for the disk
+                    let mut snapshot = jars.lock().unwrap().clone();
+                    snapshot.remove_expired(Utc::now().timestamp());
+                    if let Err(e) = save_jars(&snapshot, &path) {
+                        on_error(e.to_string());
+                    }
+                }
+                for done in flushes {
+                    let _ = done.send(());
+                }
+            }
+        })
+        .map_err(|e| anyhow!("Failed to start the cookie saver: {}", e))?;
+    Ok(requests)
+}
+
+/// The jars saved at `path`, none if there is no such file.
+fn read_jars(path: &Path) -> Result<Jars> {
+    match std::fs::read(path) {
+        Ok(saved) => serde_json::from_slice(&saved)
+            .map_err(|e| anyhow!("Invalid cookie file {}: {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Jars::default()),
+        Err(e) => Err(anyhow!("Failed to read {}: {}", path.display(), e)),
+    }
+}
+
+fn save_jars(jars: &Jars, path: &Path) -> Result<()> {
+    let saved = serde_json::to_vec_pretty(jars)?;
+    // Written next to the file first, so a crash can't leave half a file
+    let partial = path.with_extension("json.partial");
+    path.parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&partial, saved))
+        .and_then(|_| std::fs::rename(&partial, path))
+        .map_err(|e| anyhow!("Failed to save cookies to {}: {}", path.display(), e))
+}
+
+/// Cookie jars by workspace, shared between clones and saved to disk when
+/// loaded from a file.
+#[derive(Clone, Default)]
+pub struct CookieStore {
+    jars: Arc<Mutex<Jars>>,
+    saver: Option<mpsc::Sender<SaveRequest>>,
+}
+
+impl CookieStore {
+    /// A store saved to `path`, starting out with the jars saved there.
+    /// Saves that fail are reported to `on_error`.
+    pub fn load(path: PathBuf, on_error: ErrorCallback) -> Result<Self> {
+        let jars = read_jars(&path)?;
+        Self::saved_to(jars, path, on_error)
+    }
+
+    /// Like [`Self::load`], but a file that can't be read is moved aside to
+    /// `<path>.bak` and the store starts out empty, so a corrupt file can't
+    /// keep the app from starting. The reset is reported to `on_error`.
+    pub fn load_or_reset(path: PathBuf, on_error: ErrorCallback) -> Result<Self> {
+        let jars = read_jars(&path).unwrap_or_else(|e| {
+            let mut backup = path.clone().into_os_string();
+            backup.push(".bak");
+            let backup = PathBuf::from(backup);
+            match std::fs::rename(&path, &backup) {
+                Ok(()) => on_error(format!("{}; moved it to {}", e, backup.display())),
+                Err(move_error) => on_error(format!(
+                    "{}; failed to move it to {}: {}",
+                    e,
+                    backup.display(),
+                    move_error
+                )),
+            }
+            Jars::default()
+        });
+        Self::saved_to(jars, path, on_error)
+    }
+
+    fn saved_to(jars: Jars, path: PathBuf, on_error: ErrorCallback) -> Result<Self> {
+        let jars = Arc::new(Mutex::new(jars));
+        Ok(Self {
+            saver: Some(spawn_saver(jars.clone(), path, on_error)?),
+            jars,
+        })
+    }
+
+    /// Waits until every change made so far is saved.
+    pub fn flush(&self) {
+        let Some(saver) = &self.saver else {
+            return;
+        };
+        let (done, saved) = mpsc::channel();
+        if saver.send(SaveRequest::Flush(done)).is_ok() {
+            let _ = saved.recv();
+        }
+    }
+
+    /// The `Cookie` header for a request to `url`: longer paths first, then
+    /// older cookies first.
+    pub fn header(&self, jar: Option<&str>, url: &Url) -> Option<String> {
+        let now = Utc::now().timestamp();
+        let jars = self.jars.lock().unwrap();
+        let mut cookies: Vec<_> = jars
+            .get(jar)?
+            .iter()
+            .filter(|cookie| applies_to(cookie, url, now))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        cookies.sort_by(|a, b| {
+            b.path
+                .len()
+                .cmp(&a.path.len())
+                .then(a.created_at.cmp(&b.created_at))
+        });
+        let pairs: Vec<_> = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+        Some(pairs.join("; "))
+    }
+
+    /// Stores the cookies set by a response from `url`.
+    pub fn store(&self, jar: Option<&str>, url: &Url, headers: &HeaderMap) {
+        let now = Utc::now();
+        let cookies: Vec<_> = headers
+            .get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|header| header.to_str().ok())
+            .filter_map(|header| parse_set_cookie(header, url, now.timestamp_millis()))
+            .collect();
+        if cookies.is_empty() {
+            return;
+        }
+        self.update(jar, |cookies_in_jar| {
+            for cookie in cookies {
+                upsert(cookies_in_jar, cookie, now.timestamp());
+            }
+        });
+    }
+
+    /// The unexpired cookies of a jar.
+    pub fn list(&self, jar: Option<&str>) -> Vec<Cookie> {
+        let now = Utc::now().timestamp();
+        self.jars
+            .lock()
+            .unwrap()
+            .get(jar)
+            .map(|cookies| {
+                cookies
+                    .iter()
+                    .filter(|cookie| !is_expired(cookie, now))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Adds a cookie or replaces the one with the same name, domain and path.
+    pub fn set(&self, jar: Option<&str>, mut cookie: Cookie) -> Result<()> {
+        cookie.domain = cookie.domain.trim_start_matches('.').to_ascii_lowercase();
+        if cookie.name.is_empty() || cookie.name.contains(['=', ';']) {
+            return Err(anyhow!("Invalid cookie name {:?}", cookie.name));
+        }
+        if cookie.value.contains(';') {
+            return Err(anyhow!("Cookie values can't contain ';'"));
+        }
+        if cookie.domain.is_empty() {
+            return Err(anyhow!("Cookies need a domain"));
+        }
+        if !cookie.path.starts_with('/') {
+            return Err(anyhow!("Cookie paths must start with '/'"));
+        }
+        let now = Utc::now();
+        if cookie.created_at == 0 {
+            cookie.created_at = now.timestamp_millis();
+        }
+        self.update(jar, |cookies| upsert(cookies, cookie, now.timestamp()));
+        Ok(())
+    }
+
+    /// Removes a cookie, returning whether there was one. The domain is
+    /// matched as [`Self::set`] stores it.
+    pub fn remove(&self, jar: Option<&str>, name: &str, domain: &str, path: &str) -> bool {
+        let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+        let mut removed = false;
+        self.update(jar, |cookies| {
+            let before = cookies.len();
+            cookies.retain(|cookie| {
+                cookie.name != name || cookie.domain != domain || cookie.path != path
+            });
+            removed = cookies.len() < before;
+        });
+        removed
+    }
+
+    pub fn clear(&self, jar: Option<&str>) {
+        self.update(jar, Vec::clear);
+    }
+
+    /// Adds the cookies of a Netscape `cookies.txt` file, returning how many
+    /// there were.
+    pub fn import_netscape(&self, jar: Option<&str>, contents: &str) -> Result<usize> {
+        let now = Utc::now();
+        let mut imported = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            // curl marks HttpOnly cookies with a prefix that looks like a comment
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<_> = line.split('\t').collect();
+            let [domain, include_subdomains, path, secure, expires, name, value] = fields[..]
+            else {
+                return Err(anyhow!(
+                    "Line {}: expected 7 tab-separated fields, found {}",
+                    index + 1,
+                    fields.len()
+                ));
+            };
+            let expires: i64 = expires
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Line {}: invalid expiry {:?}", index + 1, expires))?;
+            imported.push(Cookie {
+                name: name.to_string(),
+                value: value.trim_end_matches('\r').to_string(),
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                host_only: !include_subdomains.eq_ignore_ascii_case("TRUE"),
+                path: path.to_string(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                http_only,
+                same_site: None,
+                expires: (expires != 0).then_some(expires),
+                created_at: now.timestamp_millis(),
+            });
+        }
+
+        let count = imported.len();
+        self.update(jar, |cookies| {
+            for cookie in imported {
+                upsert(cookies, cookie, now.timestamp());
+            }
+        });
+        Ok(count)
+    }
+
+    /// The unexpired cookies of a jar as a Netscape `cookies.txt` file.
+    /// Session cookies are written with an expiry of `0`.
+    pub fn export_netscape(&self, jar: Option<&str>) -> String {
+        let mut contents = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.list(jar) {
+            let flag = |set: bool| if set { "TRUE" } else { "FALSE" };
+            contents.push_str(&format!(
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only { "#HttpOnly_" } else { "" },
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                flag(!cookie.host_only),
+                cookie.path,
+                flag(cookie.secure),
+                cookie.expires.unwrap_or(0),
+                cookie.name,
+                cookie.value,
+            ));
+        }
+        contents
+    }
+
+    /// Changes a jar, drops expired cookies and has the store saved.
+    fn update(&self, jar: Option<&str>, change: impl FnOnce(&mut Vec<Cookie>)) {
+        let mut jars = self.jars.lock().unwrap();
+        change(jars.get_mut(jar));
+        jars.remove_expired(Utc::now().timestamp());
+        drop(jars);
+        if let Some(saver) = &self.saver {
+            let _ = saver.send(SaveRequest::Save);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    /// A callback collecting the errors it receives.
+    fn collect_errors() -> (ErrorCallback, Arc<Mutex<Vec<String>>>) {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let collected = errors.clone();
+        let callback: ErrorCallback = Arc::new(move |e| collected.lock().unwrap().push(e));
+        (callback, errors)
+    }
+
+    fn parse(header: &str, from: &str) -> Option<Cookie> {
+        parse_set_cookie(header, &url(from), NOW * 1000)
+    }
+
+    #[test]
+    fn test_set_cookie_attributes() {
+        let cookie = parse(
+            "sid=abc123; Domain=.Example.com; Path=/api; Secure; HttpOnly; SameSite=Lax; Max-Age=60",
+            "https://www.example.com/login",
+        )
+        .unwrap();
+        assert_eq!(cookie.name, "sid");
+        assert_eq!(cookie.value, "abc123");
+        assert_eq!(cookie.domain, "example.com");
+        assert!(!cookie.host_only);
+        assert_eq!(cookie.path, "/api");
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(cookie.same_site.as_deref(), Some("Lax"));
+        assert_eq!(cookie.expires, Some(NOW + 60));
+
+        // Without attributes: host-only, default path, session cookie
+        let cookie = parse("theme=dark", "http://example.com/docs/guide").unwrap();
+        assert!(cookie.host_only);
+        assert_eq!(cookie.domain, "example.com");
+        assert_eq!(cookie.path, "/docs");
+        assert_eq!(cookie.expires, None);
+        assert_eq!(
+            parse("a=b; Path=relative", "http://example.com/")
+                .unwrap()
+                .path,
+            "/"
+        );
+
+        // Max-Age wins over Expires, whatever the order
+        let cookie = parse(
+            "a=b; Max-Age=0; Expires=Wed, 21 Oct 2037 07:28:00 GMT",
+            "http://example.com/",
+        )
+        .unwrap();
+        assert_eq!(cookie.expires, Some(NOW));
+        let cookie = parse(
+            "a=b; Expires=Wed, 21 Oct 2037 07:28:00 GMT",
+            "http://example.com/",
+        )
+        .unwrap();
+        assert_eq!(cookie.expires, Some(2139722880));
+
+        // Huge Max-Age values saturate; negative ones of any length expire
+        let max_age = |value: &str| {
+            parse(&format!("a=b; Max-Age={}", value), "http://example.com/")
+                .unwrap()
+                .expires
+        };
+        assert_eq!(max_age("9223372036854775807"), Some(i64::MAX));
+        assert_eq!(max_age("99999999999999999999"), Some(i64::MAX));
+        assert_eq!(max_age("-1"), Some(NOW));
+        assert_eq!(max_age("-99999999999999999999"), Some(NOW));
+        assert_eq!(max_age("1e3"), None);
+
+        // Cookies for other domains, top-level domains and nameless ones are ignored
+        assert_eq!(parse("a=b; Domain=other.com", "http://example.com/"), None);
+        assert_eq!(parse("a=b; Domain=com", "http://example.com/"), None);
+        assert_eq!(parse("a=b; Domain=1.1", "http://127.0.0.1/"), None);
+        assert_eq!(parse("=b", "http://example.com/"), None);
+        assert_eq!(parse("no-equals-sign", "http://example.com/"), None);
+        assert!(parse("a=b; Domain=localhost", "http://localhost/").is_some());
+    }
+
+    #[test]
+    fn test_cookie_dates() {
+        for date in [
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            "Wednesday, 21-Oct-15 07:28:00 GMT",
+            "Wed Oct 21 07:28:00 2015",
+            "21 oct 2015 7:28:0",
+        ] {
+            assert_eq!(parse_cookie_date(date), Some(1445412480), "{}", date);
+        }
+        assert_eq!(parse_cookie_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_cookie_date("Wed, 31 Feb 2015 07:28:00 GMT"), None);
+        assert_eq!(parse_cookie_date("Wed, 21 Oct 2015 25:28:00 GMT"), None);
+        assert_eq!(parse_cookie_date("tomorrow"), None);
+    }
+
+    #[test]
+    fn test_matching_and_order() {
+        let store = CookieStore::default();
+        let mut headers = HeaderMap::new();
+        for set_cookie in [
+            "root=1; Path=/",
+            "api=2; Path=/api",
+            "shared=3; Domain=example.com; Path=/",
+            "secure=4; Secure; Path=/",
+            "gone=5; Max-Age=0",
+        ] {
+            headers.append(SET_COOKIE, set_cookie.parse().unwrap());
+        }
+        store.store(None, &url("https://example.com/login"), &headers);
+
+        let header = |jar, to: &str| store.header(jar, &url(to));
+        assert_eq!(
+            header(None, "https://example.com/api/users").as_deref(),
+            Some("api=2; root=1; shared=3; secure=4")
+        );
+        // Path prefixes only match at a `/`
+        assert_eq!(
+            header(None, "http://example.com/apiary").as_deref(),
+            Some("root=1; shared=3")
+        );
+        // Host-only cookies stay on their host
+        assert_eq!(
+            header(None, "http://www.example.com/").as_deref(),
+            Some("shared=3")
+        );
+        assert_eq!(header(None, "http://example.org/"), None);
+        // Each workspace has a jar of its own, even one with the id "default"
+        assert_eq!(header(Some("other"), "https://example.com/"), None);
+        assert_eq!(header(Some("default"), "https://example.com/"), None);
+        store.clear(Some("default"));
+        assert_eq!(store.list(None).len(), 4);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SET_COOKIE, "root=; Max-Age=-1".parse().unwrap());
+        store.store(None, &url("https://example.com/"), &headers);
+        assert_eq!(store.list(None).len(), 3);
+        assert!(store.remove(None, "api", "example.com", "/api"));
+        assert!(!store.remove(None, "api", "example.com", "/api"));
+        // Domains are matched the way they are stored
+        assert!(store.remove(None, "shared", ".Example.COM", "/"));
+        store.clear(None);
+        assert!(store.list(None).is_empty());
+    }
+
+    #[test]
+    fn test_netscape_round_trip_and_persistence() {
+        let path = std::env::temp_dir().join(format!("relay-cookies-{}.json", std::process::id()));
+        let store = CookieStore::load(path.clone(), collect_errors().0).unwrap();
+        let file = "# Netscape HTTP Cookie File\n\
+                    .example.com\tTRUE\t/\tTRUE\t2139722880\tsid\tabc\n\
+                    #HttpOnly_api.example.com\tFALSE\t/v1\tFALSE\t0\ttoken\txyz\n";
+        let with_comments = format!("{}\n# a comment\n", file);
+        assert_eq!(
+            store.import_netscape(Some("work"), &with_comments).unwrap(),
+            2
+        );
+        assert_eq!(store.export_netscape(Some("work")), file);
+
+        // Saved jars are there after a restart
+        store.flush();
+        let reloaded = CookieStore::load(path.clone(), collect_errors().0).unwrap();
+        let cookies = reloaded.list(Some("work"));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(cookies.len(), 2);
+        assert!(!cookies[0].host_only && cookies[0].secure);
+        assert!(cookies[1].host_only && cookies[1].http_only);
+        assert_eq!(cookies[1].expires, None);
+
+        let error = store
+            .import_netscape(None, "example.com\tFALSE\t/\n")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Line 1: expected 7 tab-separated fields, found 3"
+        );
+        let error = store
+            .set(
+                None,
+                Cookie {
+                    path: "api".to_string(),
+                    ..cookies[0].clone()
+                },
+            )
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Cookie paths must start with '/'");
+    }
+
+    #[test]
+    fn test_corrupt_file_is_moved_aside() {
+        let path = std::env::temp_dir().join(format!("relay-corrupt-{}.json", std::process::id()));
+        let backup = path.with_extension("json.bak");
+        std::fs::write(&path, "{ not json").unwrap();
+        assert!(CookieStore::load(path.clone(), collect_errors().0)
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Invalid cookie file"));
+
+        let (on_error, errors) = collect_errors();
+        let store = CookieStore::load_or_reset(path.clone(), on_error).unwrap();
+        assert!(store.list(None).is_empty());
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "{ not json");
+        let errors = errors.lock().unwrap().clone();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Invalid cookie file"));
+        assert!(errors[0].ends_with(&format!("moved it to {}", backup.display())));
+
+        // The empty store is saved in place of the corrupt file
+        store.clear(None);
+        store.flush();
+        assert!(CookieStore::load(path.clone(), collect_errors().0).is_ok());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&backup).unwrap();
+    }
+
+    #[test]
+    fn test_expired_cookies_are_dropped() {
+        let path = std::env::temp_dir().join(format!("relay-expired-{}.json", std::process::id()));
+        let now = Utc::now().timestamp();
+        let cookie = |name: &str, expires: Option<i64>| Cookie {
+            name: name.to_string(),
+            value: "1".to_string(),
+            domain: "example.com".to_string(),
+            host_only: true,
+            path: "/".to_string(),
+            secure: false,
+            http_only: false,
+            same_site: None,
+            expires,
+            created_at: 0,
+        };
+        let saved = Jars {
+            default: vec![cookie("stale", Some(now - 60))],
+            workspaces: HashMap::from([(
+                "work".to_string(),
+                vec![
+                    cookie("old", Some(now - 60)),
+                    cookie("session", None),
+                    cookie("fresh", Some(now + 3600)),
+                ],
+            )]),
+        };
+        save_jars(&saved, &path).unwrap();
+
+        // Any change drops the expired cookies of every jar, in memory and
+        // on disk
+        let store = CookieStore::load(path.clone(), collect_errors().0).unwrap();
+        store.set(Some("other"), cookie("new", None)).unwrap();
+        store.flush();
+        let reloaded = read_jars(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        for jars in [store.jars.lock().unwrap().clone(), reloaded] {
+            assert!(jars.default.is_empty());
+            let names: Vec<_> = jars.workspaces["work"]
+                .iter()
+                .map(|cookie| cookie.name.as_str())
+                .collect();
+            assert_eq!(names, ["session", "fresh"]);
+        }
+    }
+
+    #[test]
+    fn test_save_failures_are_reported() {
+        let dir = std::env::temp_dir().join(format!("relay-unsaved-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (on_error, errors) = collect_errors();
+        let store = CookieStore::load(dir.join("cookies.json"), on_error).unwrap();
+
+        // A file where the jar's directory was can't be written into
+        std::fs::remove_dir(&dir).unwrap();
+        std::fs::write(&dir, "").unwrap();
+        store.clear(None);
+        store.flush();
+        std::fs::remove_file(&dir).unwrap();
+        let errors = errors.lock().unwrap().clone();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("Failed to save cookies to"));
+    }
+}
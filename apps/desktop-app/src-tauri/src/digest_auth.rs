@@ -0,0 +1,394 @@
+//! HTTP Digest access authentication (RFC 7616).
+//!
+//! Digest credentials can't be sent up front: the relay first sends the
+//! request without them, and when the server answers `401` with a
+//! `WWW-Authenticate: Digest ...` challenge, the strongest challenge this
+//! client supports is answered with a computed `Authorization` header on a
+//! second request.
+
+use crate::header_parse::{split_outside_quotes, unquote};
+use hyper::header::{HeaderMap, WWW_AUTHENTICATE};
+use md5::Md5;
+use sha2::{Digest, Sha256, Sha512_256};
+use std::cmp::Reverse;
+
+/// Hash algorithms, ordered from weakest to strongest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+    Sha512_256,
+}
+
+impl DigestAlgorithm {
+    /// Parses an `algorithm` param, returning whether the `-sess` variant was
+    /// requested alongside the base algorithm.
+    fn parse(name: &str) -> Option<(Self, bool)> {
+        let name = name.to_ascii_uppercase();
+        let (base, session) = match name.strip_suffix("-SESS") {
+            Some(base) => (base, true),
+            None => (name.as_str(), false),
+        };
+        let algorithm = match base {
+            "MD5" => Self::Md5,
+            "SHA-256" => Self::Sha256,
+            "SHA-512-256" => Self::Sha512_256,
+            _ => return None,
+        };
+        Some((algorithm, session))
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "MD5",
+            Self::Sha256 => "SHA-256",
+            Self::Sha512_256 => "SHA-512-256",
+        }
+    }
+
+    fn hash(self, data: impl AsRef<[u8]>) -> String {
+        match self {
+            Self::Md5 => hex::encode(Md5::digest(data)),
+            Self::Sha256 => hex::encode(Sha256::digest(data)),
+            Self::Sha512_256 => hex::encode(Sha512_256::digest(data)),
+        }
+    }
+}
+
+/// Quality of protection. `auth-int` additionally covers the request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Qop {
+    Auth,
+    AuthInt,
+}
+
+impl Qop {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::AuthInt => "auth-int",
+        }
+    }
+}
+
+/// A Digest challenge from a `WWW-Authenticate` header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    pub algorithm: DigestAlgorithm,
+    /// Whether the `-sess` variant of the algorithm was requested
+    pub session: bool,
+    /// `None` for RFC 2069 servers that don't offer a quality of protection
+    pub qop: Option<Qop>,
+    pub userhash: bool,
+}
+
+impl DigestChallenge {
+    /// Returns the strongest supported Digest challenge across all
+    /// `WWW-Authenticate` headers. Earlier challenges win ties.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(Self::parse_all)
+            .min_by_key(|challenge| Reverse(challenge.algorithm))
+    }
+
+    /// Parses the Digest challenges of one `WWW-Authenticate` value, skipping
+    /// other schemes and challenges this client can't answer.
+    pub fn parse_all(value: &str) -> Vec<Self> {
+        let mut challenges: Vec<(String, Vec<(String, String)>)> = Vec::new();
+
+        for element in split_outside_quotes(value, ',') {
+            let element = element.trim();
+            let word_end = element
+                .find(|c: char| c.is_whitespace() || c == '=')
+                .unwrap_or(element.len());
+            let (word, rest) = (&element[..word_end], element[word_end..].trim_start());
+
+            if let Some(value) = rest.strip_prefix('=') {
+                // An auth-param of the current challenge
+                if let Some((_, params)) = challenges.last_mut() {
+                    params.push((word.to_ascii_lowercase(), unquote(value.trim())));
+                }
+            } else {
+                // A new challenge: the scheme, optionally followed by its first param
+                let mut params = Vec::new();
+                if let Some((key, value)) = rest.split_once('=') {
+                    params.push((key.trim().to_ascii_lowercase(), unquote(value.trim())));
+                }
+                challenges.push((word.to_string(), params));
+            }
+        }
+
+        challenges
+            .into_iter()
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("digest"))
+            .filter_map(|(_, params)| Self::from_params(&params))
+            .collect()
+    }
+
+    fn from_params(params: &[(String, String)]) -> Option<Self> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        let (algorithm, session) = match param("algorithm") {
+            Some(name) => DigestAlgorithm::parse(name)?,
+            None => (DigestAlgorithm::Md5, false),
+        };
+
+        // Prefer plain `auth`, which doesn't need the body to be hashed
+        let qop = match param("qop") {
+            Some(offered) => {
+                let offered: Vec<&str> = offered.split(',').map(str::trim).collect();
+                if offered.iter().any(|q| q.eq_ignore_ascii_case("auth")) {
+                    Some(Qop::Auth)
+                } else if offered.iter().any(|q| q.eq_ignore_ascii_case("auth-int")) {
+                    Some(Qop::AuthInt)
+                } else {
+                    return None;
+                }
+            }
+            None => None,
+        };
+
+        Some(Self {
+            realm: param("realm")?.to_string(),
+            nonce: param("nonce")?.to_string(),
+            opaque: param("opaque").map(str::to_string),
+            algorithm,
+            session,
+            qop,
+            userhash: param("userhash").is_some_and(|v| v.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// Computes the `Authorization` header value answering this challenge.
+    /// `uri` is the request target (path and query); `body` is only hashed
+    /// for `auth-int`.
+    pub fn authorization(
+        &self,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+        body: &[u8],
+    ) -> String {
+        let cnonce = hex::encode(rand::random::<[u8; 16]>());
+        self.authorization_with_cnonce(username, password, method, uri, body, &cnonce)
+    }
+
+    fn authorization_with_cnonce(
+        &self,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+        body: &[u8],
+        cnonce: &str,
+    ) -> String {
+        let hash = |data: String| self.algorithm.hash(data);
+        // Every challenge is answered once, so the nonce count is always 1
+        let nc = "00000001";
+
+        let mut ha1 = hash(format!("{}:{}:{}", username, self.realm, password));
+        if self.session {
+            ha1 = hash(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+
+        let ha2 = match self.qop {
+            Some(Qop::AuthInt) => hash(format!("{}:{}:{}", method, uri, self.algorithm.hash(body))),
+            _ => hash(format!("{}:{}", method, uri)),
+        };
+
+        let response = match self.qop {
+            Some(qop) => hash(format!(
+                "{}:{}:{}:{}:{}:{}",
+                ha1,
+                self.nonce,
+                nc,
+                cnonce,
+                qop.name(),
+                ha2
+            )),
+            None => hash(format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+
+        let username = if self.userhash {
+            hash(format!("{}:{}", username, self.realm))
+        } else {
+            username.to_string()
+        };
+
+        let algorithm = if self.session {
+            format!("{}-sess", self.algorithm.name())
+        } else {
+            self.algorithm.name().to_string()
+        };
+
+        let mut fields = vec![
+            format!("username={}", quote(&username)),
+            format!("realm={}", quote(&self.realm)),
+            format!("uri={}", quote(uri)),
+            format!("algorithm={}", algorithm),
+            format!("nonce={}", quote(&self.nonce)),
+        ];
+        if let Some(qop) = self.qop {
+            fields.push(format!("nc={}", nc));
+            fields.push(format!("cnonce={}", quote(cnonce)));
+            fields.push(format!("qop={}", qop.name()));
+        }
+        fields.push(format!("response={}", quote(&response)));
+        if let Some(opaque) = &self.opaque {
+            fields.push(format!("opaque={}", quote(opaque)));
+        }
+        if self.userhash {
+            fields.push("userhash=true".to_string());
+        }
+
+        format!("Digest {}", fields.join(", "))
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    /// Challenge from the examples in RFC 7616 section 3.9.1.
+    fn rfc7616_challenge(algorithm: DigestAlgorithm) -> DigestChallenge {
+        DigestChallenge {
+            realm: "http-auth@example.org".to_string(),
+            nonce: "7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v".to_string(),
+            opaque: Some("FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS".to_string()),
+            algorithm,
+            session: false,
+            qop: Some(Qop::Auth),
+            userhash: false,
+        }
+    }
+
+    fn respond(challenge: &DigestChallenge, body: &[u8]) -> String {
+        challenge.authorization_with_cnonce(
+            "Mufasa",
+            "Circle of Life",
+            "GET",
+            "/dir/index.html",
+            body,
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+        )
+    }
+
+    #[test]
+    fn test_rfc7616_md5_and_sha256_examples() {
+        let md5 = respond(&rfc7616_challenge(DigestAlgorithm::Md5), b"");
+        assert!(md5.starts_with("Digest username=\"Mufasa\", realm=\"http-auth@example.org\""));
+        assert!(md5.contains("response=\"8ca523f5e9506fed4657c9700eebdbec\""));
+        assert!(md5.contains("qop=auth, "));
+        assert!(md5.contains("opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""));
+
+        let sha256 = respond(&rfc7616_challenge(DigestAlgorithm::Sha256), b"");
+        assert!(sha256.contains("algorithm=SHA-256"));
+        assert!(sha256.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+    }
+
+    #[test]
+    fn test_rfc2617_example() {
+        let challenge = DigestChallenge::parse_all(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .remove(0);
+        assert_eq!(challenge.qop, Some(Qop::Auth));
+
+        let header = challenge.authorization_with_cnonce(
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            b"",
+            "0a4f113b",
+        );
+        assert!(header.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+    }
+
+    #[test]
+    fn test_auth_int_hashes_body() {
+        let mut challenge = rfc7616_challenge(DigestAlgorithm::Md5);
+        challenge.qop = Some(Qop::AuthInt);
+
+        let md5 = |data: &str| hex::encode(Md5::digest(data));
+        let ha1 = md5("Mufasa:http-auth@example.org:Circle of Life");
+        let ha2 = md5(&format!("GET:/dir/index.html:{}", md5("payload")));
+        let expected = md5(&format!(
+            "{}:{}:00000001:{}:auth-int:{}",
+            ha1, challenge.nonce, "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ", ha2
+        ));
+
+        let header = respond(&challenge, b"payload");
+        assert!(header.contains("qop=auth-int"));
+        assert!(header.contains(&format!("response=\"{}\"", expected)));
+        assert_ne!(header, respond(&challenge, b"tampered"));
+    }
+
+    #[test]
+    fn test_legacy_challenge_without_qop() {
+        let challenge = DigestChallenge::parse_all(r#"Digest realm="r", nonce="n""#).remove(0);
+        assert_eq!(challenge.qop, None);
+        assert_eq!(challenge.algorithm, DigestAlgorithm::Md5);
+
+        let md5 = |data: &str| hex::encode(Md5::digest(data));
+        let expected = md5(&format!("{}:n:{}", md5("u:r:p"), md5("GET:/")));
+        let header = challenge.authorization_with_cnonce("u", "p", "GET", "/", b"", "c");
+        assert!(header.contains(&format!("response=\"{}\"", expected)));
+        assert!(!header.contains("cnonce"));
+    }
+
+    #[test]
+    fn test_picks_strongest_supported_challenge() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Basic realm="basic""#),
+        );
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static(
+                r#"Digest realm="r", qop="auth,auth-int", algorithm=MD5, nonce="n1", Digest realm = "r", algorithm=SHA-256-sess, nonce="n2", qop="auth-int", userhash=true, Digest realm="r", nonce="n3", algorithm=SHA-1"#,
+            ),
+        );
+
+        let challenge = DigestChallenge::from_headers(&headers).unwrap();
+        assert_eq!(challenge.nonce, "n2");
+        assert_eq!(challenge.algorithm, DigestAlgorithm::Sha256);
+        assert!(challenge.session);
+        assert!(challenge.userhash);
+        assert_eq!(challenge.qop, Some(Qop::AuthInt));
+
+        let header = challenge.authorization("u", "p", "GET", "/", b"");
+        assert!(header.contains("algorithm=SHA-256-sess"));
+        assert!(header.contains(&format!(
+            "username=\"{}\"",
+            DigestAlgorithm::Sha256.hash("u:r")
+        )));
+
+        let mut headers = HeaderMap::new();
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Bearer realm="api""#),
+        );
+        assert!(DigestChallenge::from_headers(&headers).is_none());
+    }
+}
@@ -0,0 +1,315 @@
+//! GraphQL over HTTP.
+//!
+//! Operations are sent as a JSON body or, for `GET`, as query string
+//! parameters, and responses are split into `data` and `errors`. Introspected
+//! schemas are cached per endpoint URL, since fetching them is slow and they
+//! rarely change while someone is writing queries.
+
+use crate::types::{
+    GraphQLMethod, GraphQLRequest, GraphQLResult, GraphQLSchema, Request, RequestBody,
+    RequestMethod,
+};
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Prefers the GraphQL media type, which lets servers report request errors
+/// with a status code, but accepts plain JSON from older servers.
+const ACCEPT: &str = "application/graphql-response+json, application/json;q=0.9";
+
+/// The introspection query of graphql-js, without the fields added after the
+/// October 2021 spec (e.g. `isRepeatable`) that older servers reject.
+pub const INTROSPECTION_QUERY: &str = r#"
+query IntrospectionQuery {
+  __schema {
+    queryType { name }
+    mutationType { name }
+    subscriptionType { name }
+    types { ...FullType }
+    directives {
+      name
+      description
+      locations
+      args { ...InputValue }
+    }
+  }
+}
+
+fragment FullType on __Type {
+  kind
+  name
+  description
+  fields(includeDeprecated: true) {
+    name
+    description
+    args { ...InputValue }
+    type { ...TypeRef }
+    isDeprecated
+    deprecationReason
+  }
+  inputFields { ...InputValue }
+  interfaces { ...TypeRef }
+  enumValues(includeDeprecated: true) {
+    name
+    description
+    isDeprecated
+    deprecationReason
+  }
+  possibleTypes { ...TypeRef }
+}
+
+fragment InputValue on __InputValue {
+  name
+  description
+  type { ...TypeRef }
+  defaultValue
+}
+
+fragment TypeRef on __Type {
+  kind
+  name
+  ofType {
+    kind
+    name
+    ofType {
+      kind
+      name
+      ofType {
+        kind
+        name
+        ofType {
+          kind
+          name
+          ofType {
+            kind
+            name
+            ofType {
+              kind
+              name
+              ofType {
+                kind
+                name
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+/// The HTTP request carrying a GraphQL operation.
+pub fn http_request(request: &GraphQLRequest) -> Result<Request> {
+    if request.query.trim().is_empty() {
+        return Err(anyhow!("A GraphQL request needs a query"));
+    }
+    let variables = match &request.variables {
+        None | Some(Value::Null) => None,
+        Some(variables @ Value::Object(_)) => Some(variables),
+        Some(_) => return Err(anyhow!("GraphQL variables must be an object")),
+    };
+
+    let mut headers = request.headers.clone();
+    if !headers
+        .keys()
+        .any(|name| name.eq_ignore_ascii_case("accept"))
+    {
+        headers.insert("Accept".to_string(), Value::String(ACCEPT.to_string()));
+    }
+    let mut http = Request {
+        url: request.url.clone(),
+        headers,
+        id: request.id.clone(),
+        timeouts: request.timeouts,
+        authorization: request.authorization.clone(),
+        ..Default::default()
+    };
+
+    match request.method {
+        GraphQLMethod::POST => {
+            let mut body = Map::new();
+            body.insert("query".to_string(), Value::String(request.query.clone()));
+            if let Some(variables) = variables {
+                body.insert("variables".to_string(), variables.clone());
+            }
+            if let Some(operation_name) = &request.operation_name {
+                body.insert(
+                    "operationName".to_string(),
+                    Value::String(operation_name.clone()),
+                );
+            }
+            http.method = RequestMethod::POST;
+            http.body = RequestBody {
+                content_type: Some("application/json".to_string()),
+                content: Some(Value::Object(body).to_string()),
+                ..Default::default()
+            };
+        }
+        GraphQLMethod::GET => {
+            // Variables are sent as JSON text in a parameter of their own
+            http.method = RequestMethod::GET;
+            http.params
+                .insert("query".to_string(), Value::String(request.query.clone()));
+            if let Some(variables) = variables {
+                http.params.insert(
+                    "variables".to_string(),
+                    Value::String(variables.to_string()),
+                );
+            }
+            if let Some(operation_name) = &request.operation_name {
+                http.params.insert(
+                    "operationName".to_string(),
+                    Value::String(operation_name.clone()),
+                );
+            }
+        }
+    }
+    Ok(http)
+}
+
+/// Splits a response body into `data` and `errors`. Bodies that aren't a
+/// GraphQL response, e.g. the error page of a proxy, have no result.
+pub fn parse_result(content: &str) -> Option<GraphQLResult> {
+    let body: Map<String, Value> = serde_json::from_str(content).ok()?;
+    if !body.contains_key("data") && !body.contains_key("errors") {
+        return None;
+    }
+    serde_json::from_value(Value::Object(body)).ok()
+}
+
+/// The request fetching the schema of the endpoint `request` is sent to.
+pub fn introspection_request(request: &GraphQLRequest) -> GraphQLRequest {
+    GraphQLRequest {
+        query: INTROSPECTION_QUERY.to_string(),
+        variables: None,
+        operation_name: Some("IntrospectionQuery".to_string()),
+        ..request.clone()
+    }
+}
+
+/// Introspected schemas by endpoint URL, shared between clones.
+#[derive(Clone, Default)]
+pub struct GraphQLSchemas {
+    entries: Arc<RwLock<HashMap<String, GraphQLSchema>>>,
+}
+
+impl GraphQLSchemas {
+    pub fn get(&self, url: &str) -> Option<GraphQLSchema> {
+        self.entries.read().unwrap().get(url).cloned()
+    }
+
+    pub fn insert(&self, schema: GraphQLSchema) {
+        self.entries
+            .write()
+            .unwrap()
+            .insert(schema.url.clone(), schema);
+    }
+
+    /// Forgets every cached schema.
+    pub fn clear(&self) {
+        self.entries.write().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::GraphQLLocation;
+    use serde_json::json;
+
+    fn request(method: GraphQLMethod) -> GraphQLRequest {
+        GraphQLRequest {
+            url: "https://api.example.com/graphql".to_string(),
+            query: "query Book($id: ID!) { book(id: $id) { title } }".to_string(),
+            variables: Some(json!({ "id": "42" })),
+            operation_name: Some("Book".to_string()),
+            method,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_post_and_get_serialization() {
+        let post = http_request(&request(GraphQLMethod::POST)).unwrap();
+        assert!(matches!(post.method, RequestMethod::POST));
+        assert_eq!(post.body.content_type.as_deref(), Some("application/json"));
+        let body: Value = serde_json::from_str(post.body.content.as_deref().unwrap()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "query": "query Book($id: ID!) { book(id: $id) { title } }",
+                "variables": { "id": "42" },
+                "operationName": "Book"
+            })
+        );
+        assert_eq!(post.headers["Accept"], ACCEPT);
+        assert!(post.params.is_empty());
+
+        let get = http_request(&request(GraphQLMethod::GET)).unwrap();
+        assert!(matches!(get.method, RequestMethod::GET));
+        assert_eq!(get.body.content, None);
+        assert_eq!(get.params["variables"], r#"{"id":"42"}"#);
+        assert_eq!(get.params["operationName"], "Book");
+
+        // Unset variables and operation names are left out
+        let mut minimal = request(GraphQLMethod::POST);
+        minimal.variables = Some(Value::Null);
+        minimal.operation_name = None;
+        minimal
+            .headers
+            .insert("accept".to_string(), json!("application/json"));
+        let post = http_request(&minimal).unwrap();
+        let body: Value = serde_json::from_str(post.body.content.as_deref().unwrap()).unwrap();
+        assert_eq!(body.as_object().unwrap().len(), 1);
+        assert_eq!(post.headers.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_requests() {
+        let error = |request: GraphQLRequest| http_request(&request).unwrap_err().to_string();
+        let mut blank = request(GraphQLMethod::POST);
+        blank.query = "  ".to_string();
+        assert_eq!(error(blank), "A GraphQL request needs a query");
+        let mut list = request(GraphQLMethod::GET);
+        list.variables = Some(json!([1, 2]));
+        assert_eq!(error(list), "GraphQL variables must be an object");
+    }
+
+    #[test]
+    fn test_results_split_into_data_and_errors() {
+        let result = parse_result(
+            r#"{
+                "data": { "book": null },
+                "errors": [{
+                    "message": "Book 42 not found",
+                    "locations": [{ "line": 1, "column": 25 }],
+                    "path": ["book"],
+                    "extensions": { "code": "NOT_FOUND" }
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(result.data, Some(json!({ "book": null })));
+        let error = &result.errors[0];
+        assert_eq!(error.message, "Book 42 not found");
+        assert_eq!(
+            error.locations,
+            [GraphQLLocation {
+                line: 1,
+                column: 25
+            }]
+        );
+        assert_eq!(error.path, [json!("book")]);
+        assert_eq!(error.extensions, Some(json!({ "code": "NOT_FOUND" })));
+
+        // Request errors come without data
+        let result = parse_result(r#"{"errors":[{"message":"Syntax Error"}]}"#).unwrap();
+        assert_eq!(result.data, None);
+        assert_eq!(result.errors.len(), 1);
+
+        assert_eq!(parse_result("<html>Bad Gateway</html>"), None);
+        assert_eq!(parse_result(r#"{"message":"Not Found"}"#), None);
+    }
+}
@@ -0,0 +1,392 @@
+//! gRPC over HTTP/2 (PROTOCOL-HTTP2 in the gRPC repository).
+//!
+//! Messages travel in the HTTP body with a five-byte prefix: a compression
+//! flag and a big-endian length. The call's outcome arrives in the
+//! `grpc-status` and `grpc-message` trailers, or in the headers of a
+//! "trailers-only" response that carries no messages at all.
+
+use crate::content_encoding::{decode_body, DEFAULT_DECODED_LIMIT};
+use crate::timeouts::with_timeout;
+use crate::types::{GrpcMethodSchema, GrpcServiceSchema, RelayErrorKind};
+use anyhow::{anyhow, Result};
+use http_body_util::BodyExt;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::HeaderMap;
+use hyper::{Response as HyperResponse, StatusCode};
+use percent_encoding::percent_decode_str;
+use prost_reflect::{DescriptorPool, DynamicMessage, SerializeOptions};
+use std::fmt;
+use std::time::Duration;
+
+/// Status code names, indexed by code.
+const STATUS_NAMES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+pub const OK: u32 = 0;
+pub const UNKNOWN: u32 = 2;
+pub const UNIMPLEMENTED: u32 = 12;
+pub const INTERNAL: u32 = 13;
+
+/// Length of the prefix in front of each message.
+const PREFIX_LEN: usize = 5;
+
+/// The name of a status code, e.g. `NOT_FOUND`.
+pub fn status_name(code: u32) -> String {
+    STATUS_NAMES
+        .get(code as usize)
+        .map(|name| name.to_string())
+        .unwrap_or_else(|| format!("CODE_{}", code))
+}
+
+/// Prefixes each message for the request body. Messages are sent
+/// uncompressed.
+pub fn encode_frames(messages: &[Vec<u8>]) -> Bytes {
+    let mut body = Vec::new();
+    for message in messages {
+        body.push(0);
+        body.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        body.extend_from_slice(message);
+    }
+    body.into()
+}
+
+/// Splits the response body into messages, however it was chunked.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The next complete message and whether it is compressed.
+    pub fn next_frame(&mut self) -> Option<(bool, Bytes)> {
+        if self.buffer.len() < PREFIX_LEN {
+            return None;
+        }
+        let length = u32::from_be_bytes(self.buffer[1..PREFIX_LEN].try_into().unwrap()) as usize;
+        if self.buffer.len() < PREFIX_LEN + length {
+            return None;
+        }
+        let compressed = self.buffer[0] & 1 == 1;
+        let rest = self.buffer.split_off(PREFIX_LEN + length);
+        let frame = std::mem::replace(&mut self.buffer, rest);
+        Some((compressed, Bytes::from(frame).slice(PREFIX_LEN..)))
+    }
+
+    /// Whether no partial message is left over.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+/// The outcome of a call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcStatus {
+    pub code: u32,
+    pub message: String,
+}
+
+impl GrpcStatus {
+    pub fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The status in `grpc-status` and `grpc-message`, if there is one.
+    pub fn from_metadata(metadata: &HeaderMap) -> Option<Self> {
+        let code = metadata
+            .get("grpc-status")?
+            .to_str()
+            .ok()?
+            .trim()
+            .parse()
+            .ok()?;
+        let message = metadata
+            .get("grpc-message")
+            .and_then(|message| message.to_str().ok())
+            .map(|message| percent_decode_str(message).decode_utf8_lossy().into_owned())
+            .unwrap_or_default();
+        Some(Self { code, message })
+    }
+
+    /// The status implied by an HTTP response that isn't a gRPC one, as
+    /// mapped in "HTTP to gRPC Status Code Mapping".
+    pub fn from_http(status: StatusCode) -> Self {
+        let code = match status.as_u16() {
+            400 => INTERNAL,
+            401 => 16,
+            403 => 7,
+            404 => UNIMPLEMENTED,
+            429 | 502 | 503 | 504 => 14,
+            _ => UNKNOWN,
+        };
+        Self::new(code, format!("Received HTTP status {}", status.as_u16()))
+    }
+}
+
+impl fmt::Display for GrpcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.message.is_empty() {
+            write!(f, "{}", status_name(self.code))
+        } else {
+            write!(f, "{}: {}", status_name(self.code), self.message)
+        }
+    }
+}
+
+impl std::error::Error for GrpcStatus {}
+
+/// The `grpc-timeout` header for a deadline. Values have at most eight
+/// digits, so long deadlines are sent in seconds.
+pub fn timeout_header(millis: u64) -> String {
+    if millis < 100_000_000 {
+        format!("{}m", millis)
+    } else {
+        format!("{}S", millis.div_ceil(1000))
+    }
+}
+
+/// The messages of a response as they arrive, followed by its status.
+pub struct ResponseStream {
+    status_code: StatusCode,
+    headers: HeaderMap,
+    trailers: HeaderMap,
+    body: Option<Incoming>,
+    decoder: FrameDecoder,
+    status: Option<GrpcStatus>,
+    idle: Option<Duration>,
+}
+
+impl ResponseStream {
+    /// Reads the body with at most `idle` between two chunks.
+    pub fn new(response: HyperResponse<Incoming>, idle: Option<Duration>) -> Self {
+        let (parts, body) = response.into_parts();
+        // Trailers-only responses and non-gRPC errors carry no messages
+        let status = if parts.status != StatusCode::OK {
+            Some(GrpcStatus::from_http(parts.status))
+        } else {
+            GrpcStatus::from_metadata(&parts.headers)
+        };
+        Self {
+            status_code: parts.status,
+            body: status.is_none().then_some(body),
+            headers: parts.headers,
+            trailers: HeaderMap::new(),
+            decoder: FrameDecoder::default(),
+            status,
+            idle,
+        }
+    }
+
+    /// The next message, or `None` once the call's status is known.
+    pub async fn message(&mut self) -> Result<Option<Bytes>> {
+        loop {
+            if let Some((compressed, payload)) = self.decoder.next_frame() {
+                return self.decompress(compressed, payload).map(Some);
+            }
+            let Some(body) = self.body.as_mut() else {
+                return Ok(None);
+            };
+            let frame = with_timeout(self.idle, RelayErrorKind::BodyIdleTimeout, body.frame())
+                .await?
+                .transpose()
+                .map_err(|e| anyhow!("Failed to read gRPC response: {}", e))?;
+            match frame {
+                Some(frame) => match frame.into_data() {
+                    Ok(data) => self.decoder.push(&data),
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            self.trailers = trailers;
+                        }
+                    }
+                },
+                None => {
+                    self.body = None;
+                    if !self.decoder.is_empty() {
+                        return Err(anyhow!("gRPC response ended in the middle of a message"));
+                    }
+                    self.status = Some(GrpcStatus::from_metadata(&self.trailers).unwrap_or_else(
+                        || GrpcStatus::new(INTERNAL, "Response ended without a gRPC status"),
+                    ));
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    fn decompress(&self, compressed: bool, payload: Bytes) -> Result<Bytes> {
+        if !compressed {
+            return Ok(payload);
+        }
+        let encoding = self
+            .headers
+            .get("grpc-encoding")
+            .and_then(|encoding| encoding.to_str().ok())
+            .unwrap_or("identity");
+        if encoding != "gzip" {
+            return Err(anyhow!("Unsupported gRPC message encoding {}", encoding));
+        }
+        Ok(decode_body(Some("gzip"), payload.to_vec(), DEFAULT_DECODED_LIMIT)?.into())
+    }
+
+    /// The call's status, known once [`Self::message`] returned `None`.
+    pub fn status(&self) -> Option<&GrpcStatus> {
+        self.status.as_ref()
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        self.status_code
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+}
+
+/// The services in `pool` and their methods, leaving out reflection.
+pub fn describe_services(pool: &DescriptorPool) -> Result<Vec<GrpcServiceSchema>> {
+    let options = SerializeOptions::new().skip_default_fields(false);
+    pool.services()
+        .filter(|service| !service.full_name().starts_with("grpc.reflection."))
+        .map(|service| {
+            let methods = service
+                .methods()
+                .map(|method| {
+                    let template = DynamicMessage::new(method.input())
+                        .serialize_with_options(serde_json::value::Serializer, &options)?;
+                    Ok(GrpcMethodSchema {
+                        name: method.name().to_string(),
+                        client_streaming: method.is_client_streaming(),
+                        server_streaming: method.is_server_streaming(),
+                        input_type: method.input().full_name().to_string(),
+                        output_type: method.output().full_name().to_string(),
+                        request_template: template,
+                    })
+                })
+                .collect::<Result<_>>()?;
+            Ok(GrpcServiceSchema {
+                name: service.full_name().to_string(),
+                methods,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::parse_proto;
+
+    #[test]
+    fn test_frames_across_chunks() {
+        let body = encode_frames(&[b"first".to_vec(), Vec::new(), b"third".to_vec()]);
+        assert_eq!(&body[..PREFIX_LEN], &[0, 0, 0, 0, 5]);
+
+        let mut decoder = FrameDecoder::default();
+        let mut frames = Vec::new();
+        for chunk in body.chunks(3) {
+            decoder.push(chunk);
+            while let Some((compressed, payload)) = decoder.next_frame() {
+                assert!(!compressed);
+                frames.push(payload);
+            }
+        }
+        assert_eq!(frames, ["first".as_bytes(), b"", b"third"]);
+        assert!(decoder.is_empty());
+
+        decoder.push(&[1, 0, 0, 0, 2, b'x']);
+        assert_eq!(decoder.next_frame(), None);
+        decoder.push(b"y");
+        assert_eq!(decoder.next_frame(), Some((true, Bytes::from("xy"))));
+    }
+
+    #[test]
+    fn test_status_mapping() {
+        let mut metadata = HeaderMap::new();
+        assert_eq!(GrpcStatus::from_metadata(&metadata), None);
+        metadata.insert("grpc-status", "5".parse().unwrap());
+        metadata.insert(
+            "grpc-message",
+            "no %22shelf%22 here %F0%9F%93%9A".parse().unwrap(),
+        );
+        let status = GrpcStatus::from_metadata(&metadata).unwrap();
+        assert_eq!(status.message, "no \"shelf\" here 📚");
+        assert_eq!(status.to_string(), "NOT_FOUND: no \"shelf\" here 📚");
+
+        assert_eq!(
+            GrpcStatus::from_http(StatusCode::NOT_FOUND).code,
+            UNIMPLEMENTED
+        );
+        assert_eq!(
+            GrpcStatus::from_http(StatusCode::SERVICE_UNAVAILABLE).code,
+            14
+        );
+        assert_eq!(
+            GrpcStatus::from_http(StatusCode::IM_A_TEAPOT).to_string(),
+            "UNKNOWN: Received HTTP status 418"
+        );
+        assert_eq!(status_name(16), "UNAUTHENTICATED");
+        assert_eq!(status_name(42), "CODE_42");
+
+        assert_eq!(timeout_header(1500), "1500m");
+        assert_eq!(timeout_header(100_000_000), "100000S");
+    }
+
+    #[test]
+    fn test_describe_services() {
+        let file = parse_proto(
+            "library.proto",
+            "syntax = \"proto3\";\npackage library;\n\
+             message Query { string title = 1; repeated string tags = 2; int32 limit = 3; }\n\
+             message Book { string title = 1; }\n\
+             service Library {\n\
+               rpc Find(Query) returns (Book);\n\
+               rpc Watch(Query) returns (stream Book);\n\
+               rpc Shelve(stream Book) returns (Query);\n\
+             }",
+        )
+        .unwrap();
+        let pool = DescriptorPool::from_file_descriptor_set(prost_types::FileDescriptorSet {
+            file: vec![file],
+        })
+        .unwrap();
+
+        let services = describe_services(&pool).unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, "library.Library");
+        let methods = &services[0].methods;
+        assert_eq!(methods[0].input_type, "library.Query");
+        assert_eq!(
+            methods[0].request_template,
+            serde_json::json!({ "title": "", "tags": [], "limit": 0 })
+        );
+        assert!(methods[1].server_streaming && !methods[1].client_streaming);
+        assert!(methods[2].client_streaming && !methods[2].server_streaming);
+    }
+}
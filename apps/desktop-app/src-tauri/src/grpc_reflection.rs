@@ -0,0 +1,178 @@
+//! Loading service descriptors from gRPC server reflection.
+//!
+//! Asks the server for its services, then for the files defining them and
+//! the files those import. Servers implementing only the older `v1alpha`
+//! version of the protocol are supported too.
+
+use crate::grpc::{GrpcStatus, UNIMPLEMENTED};
+use anyhow::{anyhow, Result};
+use hyper::body::Bytes;
+use prost::Message;
+use prost_reflect::DescriptorPool;
+use prost_types::FileDescriptorProto;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+
+/// Reflection methods, newest first.
+const REFLECTION_METHODS: [&str; 2] = [
+    "grpc.reflection.v1.ServerReflection/ServerReflectionInfo",
+    "grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+];
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServerReflectionRequest {
+    #[prost(string, tag = "1")]
+    pub host: String,
+    #[prost(oneof = "MessageRequest", tags = "3, 4, 7")]
+    pub message_request: Option<MessageRequest>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum MessageRequest {
+    #[prost(string, tag = "3")]
+    FileByFilename(String),
+    #[prost(string, tag = "4")]
+    FileContainingSymbol(String),
+    #[prost(string, tag = "7")]
+    ListServices(String),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServerReflectionResponse {
+    #[prost(oneof = "MessageResponse", tags = "4, 6, 7")]
+    pub message_response: Option<MessageResponse>,
+}
+
+// Variant names follow the reflection proto's `message_response` oneof
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum MessageResponse {
+    #[prost(message, tag = "4")]
+    FileDescriptorResponse(FileDescriptorResponse),
+    #[prost(message, tag = "6")]
+    ListServicesResponse(ListServiceResponse),
+    #[prost(message, tag = "7")]
+    ErrorResponse(ErrorResponse),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct FileDescriptorResponse {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub file_descriptor_proto: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ListServiceResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub service: Vec<ServiceResponse>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServiceResponse {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ErrorResponse {
+    #[prost(int32, tag = "1")]
+    pub error_code: i32,
+    #[prost(string, tag = "2")]
+    pub error_message: String,
+}
+
+/// Builds a pool from what the server reflects. `call` makes a
+/// client-streaming call of the given method with one encoded request and
+/// resolves to the encoded responses.
+pub async fn load_from_reflection<F, Fut>(mut call: F) -> Result<DescriptorPool>
+where
+    F: FnMut(&'static str, Vec<u8>) -> Fut,
+    Fut: Future<Output = Result<Vec<Bytes>>>,
+{
+    // Find the version the server speaks by listing its services
+    let mut method = REFLECTION_METHODS[0];
+    let list = MessageRequest::ListServices(String::new());
+    let listed = match reflect(&mut call, method, list.clone()).await {
+        Err(e) if is_unimplemented(&e) => {
+            method = REFLECTION_METHODS[1];
+            reflect(&mut call, method, list).await?
+        }
+        result => result?,
+    };
+    let MessageResponse::ListServicesResponse(listed) = listed else {
+        return Err(anyhow!("Unexpected answer to listing services"));
+    };
+
+    let global = DescriptorPool::global();
+    let mut files: HashMap<String, FileDescriptorProto> = HashMap::new();
+    let mut missing: Vec<String> = Vec::new();
+    let mut requests: Vec<MessageRequest> = listed
+        .service
+        .into_iter()
+        .filter(|service| !service.name.starts_with("grpc.reflection."))
+        .map(|service| MessageRequest::FileContainingSymbol(service.name))
+        .collect();
+    let mut requested = HashSet::new();
+
+    while let Some(request) = requests.pop() {
+        let response = reflect(&mut call, method, request).await?;
+        let MessageResponse::FileDescriptorResponse(response) = response else {
+            return Err(anyhow!("Unexpected answer to a file request"));
+        };
+        // Servers may send a file's dependencies along with it
+        for encoded in response.file_descriptor_proto {
+            let file = FileDescriptorProto::decode(encoded.as_slice())
+                .map_err(|e| anyhow!("Invalid file descriptor from the server: {}", e))?;
+            missing.extend(file.dependency.iter().cloned());
+            files.insert(file.name().to_string(), file);
+        }
+        for dependency in missing.drain(..) {
+            if files.contains_key(&dependency)
+                || global.get_file_by_name(&dependency).is_some()
+                || !requested.insert(dependency.clone())
+            {
+                continue;
+            }
+            requests.push(MessageRequest::FileByFilename(dependency));
+        }
+    }
+
+    let mut pool = global;
+    pool.add_file_descriptor_protos(files.into_values())
+        .map_err(|e| anyhow!("Invalid descriptors from the server: {}", e))?;
+    Ok(pool)
+}
+
+async fn reflect<F, Fut>(
+    call: &mut F,
+    method: &'static str,
+    request: MessageRequest,
+) -> Result<MessageResponse>
+where
+    F: FnMut(&'static str, Vec<u8>) -> Fut,
+    Fut: Future<Output = Result<Vec<Bytes>>>,
+{
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(request),
+    };
+    let responses = call(method, request.encode_to_vec()).await?;
+    let response = responses
+        .first()
+        .ok_or_else(|| anyhow!("The reflection service sent no answer"))?;
+    let response = ServerReflectionResponse::decode(response.as_ref())
+        .map_err(|e| anyhow!("Invalid reflection response: {}", e))?;
+    match response.message_response {
+        Some(MessageResponse::ErrorResponse(error)) => Err(GrpcStatus::new(
+            error.error_code as u32,
+            format!("Reflection failed: {}", error.error_message),
+        )
+        .into()),
+        Some(response) => Ok(response),
+        None => Err(anyhow!("Empty reflection response")),
+    }
+}
+
+fn is_unimplemented(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref::<GrpcStatus>(), Some(status) if status.code == UNIMPLEMENTED)
+}
@@ -0,0 +1,68 @@
+//! Tokenizers shared by the parsers of structured header values such as
+//! `Server-Timing` and `WWW-Authenticate`.
+
+/// Splits `input` on `separator`, ignoring separators inside quoted strings.
+/// Empty parts are dropped.
+pub fn split_outside_quotes(input: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for c in input.chars() {
+        if escaped {
+            escaped = false;
+        } else if in_quotes && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quotes = !in_quotes;
+        } else if c == separator && !in_quotes {
+            parts.push(std::mem::take(&mut current));
+            continue;
+        }
+        current.push(c);
+    }
+    parts.push(current);
+
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// Removes the quotes and backslash escapes of a quoted-string; other values
+/// are returned as is.
+pub fn unquote(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+        return value.to_string();
+    };
+
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(escaped) = chars.next() {
+                result.push(escaped);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_outside_quotes() {
+        let parts = split_outside_quotes(r#"a=1, b="x, \"y\"", , c"#, ',');
+        assert_eq!(parts, vec!["a=1", r#" b="x, \"y\"""#, " c"]);
+    }
+
+    #[test]
+    fn test_unquote() {
+        assert_eq!(unquote(r#""Cache \"hot\"""#), r#"Cache "hot""#);
+        assert_eq!(unquote("token"), "token");
+        assert_eq!(unquote(r#""unterminated"#), r#""unterminated"#);
+    }
+}
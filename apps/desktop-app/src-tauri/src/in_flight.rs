@@ -0,0 +1,111 @@
+//! Registry of in-flight relay requests that can be cancelled by id.
+
+use futures::future::{AbortHandle, AbortRegistration};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Default)]
+struct Entries {
+    next_generation: AtomicU64,
+    handles: Mutex<HashMap<String, (u64, AbortHandle)>>,
+}
+
+/// Tracks the abort handles of requests that carry a client-supplied id.
+///
+/// The registry is shared between clones, so a request registered through one
+/// clone of the relay can be cancelled through another.
+#[derive(Clone, Default)]
+pub struct InFlightRegistry {
+    entries: Arc<Entries>,
+}
+
+impl InFlightRegistry {
+    /// Registers a request under `id` and returns the registration to wrap its
+    /// future with, plus a guard that unregisters it when dropped.
+    ///
+    /// Registering an id that is already in flight replaces the older entry,
+    /// so cancelling targets the most recent request.
+    pub fn register(&self, id: String) -> (AbortRegistration, InFlightGuard) {
+        let (handle, registration) = AbortHandle::new_pair();
+        let generation = self.entries.next_generation.fetch_add(1, Ordering::Relaxed);
+
+        self.entries
+            .handles
+            .lock()
+            .unwrap()
+            .insert(id.clone(), (generation, handle));
+
+        let guard = InFlightGuard {
+            registry: self.clone(),
+            id,
+            generation,
+        };
+        (registration, guard)
+    }
+
+    /// Aborts the request registered under `id`. Returns `false` if no such
+    /// request is in flight.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.entries.handles.lock().unwrap().remove(id) {
+            Some((_, handle)) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Removes a request from the registry once it completes or is dropped.
+pub struct InFlightGuard {
+    registry: InFlightRegistry,
+    id: String,
+    generation: u64,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut handles = self.registry.entries.handles.lock().unwrap();
+        // Leave the entry alone if a newer request re-used the id
+        if matches!(handles.get(&self.id), Some((generation, _)) if *generation == self.generation)
+        {
+            handles.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{pending, Abortable};
+
+    #[tokio::test]
+    async fn test_cancel_aborts_registered_future() {
+        let registry = InFlightRegistry::default();
+        let (registration, _guard) = registry.register("req-1".to_string());
+
+        assert!(registry.cancel("req-1"));
+        assert!(Abortable::new(pending::<()>(), registration).await.is_err());
+        assert!(!registry.cancel("req-1"));
+    }
+
+    #[test]
+    fn test_guard_unregisters_on_drop() {
+        let registry = InFlightRegistry::default();
+        let (_registration, guard) = registry.register("req-1".to_string());
+
+        drop(guard);
+        assert!(!registry.cancel("req-1"));
+    }
+
+    #[test]
+    fn test_stale_guard_keeps_newer_registration() {
+        let registry = InFlightRegistry::default();
+        let (_first, first_guard) = registry.register("req-1".to_string());
+        let (_second, _second_guard) = registry.register("req-1".to_string());
+
+        drop(first_guard);
+        assert!(registry.cancel("req-1"));
+    }
+}
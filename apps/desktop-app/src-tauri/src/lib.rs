@@ -1,14 +1,51 @@
 mod types;
 mod relay;
+mod auth;
+mod aws_sigv4;
+mod charset;
+mod content_encoding;
+mod cookies;
+mod digest_auth;
+mod graphql;
+mod grpc;
+mod grpc_reflection;
+mod header_parse;
+mod in_flight;
+mod mime_sniff;
+mod multipart;
+mod oauth2;
+mod proto;
+mod proxy;
+mod redirect;
+mod request_body;
+mod server_timing;
+mod sse;
+mod stream_credits;
+mod timeouts;
+mod timing;
+mod tls;
+mod websocket;
 
+use cookies::CookieStore;
 use relay::RelayService;
-use types::{Request, RelayResponse};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use stream_credits::{StreamCreditRegistry, StreamCredits};
+use tauri::async_runtime::JoinHandle;
+use tauri::ipc::{Channel, IpcResponse};
+use tauri::{Emitter, Manager, RunEvent, State};
+use tokio::sync::mpsc;
+use types::{
+    Cookie, EventStreamEvent, GraphQLRelayResponse, GraphQLRequest, GraphQLSchema, GrpcEvent,
+    GrpcRequest, GrpcServiceSchema, ProxySetting, Request, RelayResponse, StreamEvent,
+    TimeoutSettings, TlsSettings, WebSocketEvent, WebSocketMessage,
+};
 
-// Global relay service instance
-static RELAY_SERVICE: once_cell::sync::Lazy<Arc<Mutex<RelayService>>> = 
-    once_cell::sync::Lazy::new(|| Arc::new(Mutex::new(RelayService::new())));
+/// Stream events buffered before the relay stops reading the response.
+const STREAM_EVENT_BUFFER: usize = 16;
+
+/// Events of a streamed response the frontend may fall behind by before the
+/// relay stops reading it.
+const STREAM_CREDITS: usize = 16;
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -17,12 +54,275 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
-async fn relay_request(request: Request) -> Result<RelayResponse, String> {
-    let service = RELAY_SERVICE.lock().await;
-    service.relay_http_request(request).await
+async fn relay_request(
+    relay: State<'_, RelayService>,
+    request: Request,
+) -> Result<RelayResponse, String> {
+    relay.relay_http_request(request).await
+        .map_err(|e| e.to_string())
+}
+
+/// Relays a request, pushing its headers, body chunks and final response to
+/// `on_event` as they arrive. When the request has an id, the frontend
+/// acknowledges handled events with `ack_stream_events` and the download
+/// pauses while it lags behind; without one events are pushed unthrottled.
+#[tauri::command]
+async fn relay_request_stream(
+    relay: State<'_, RelayService>,
+    credits: State<'_, StreamCreditRegistry>,
+    request: Request,
+    on_event: Channel<StreamEvent>,
+) -> Result<(), String> {
+    let credits = request
+        .id
+        .clone()
+        .map(|id| Arc::new(credits.open(id, STREAM_CREDITS)));
+    let (events, forwarding) = forward_events(&on_event, credits.clone());
+    let result = relay.relay_http_request_stream(request, events).await;
+    // The response was read, so its buffered events needn't wait for credits
+    if let Some(credits) = &credits {
+        credits.release();
+    }
+    let _ = forwarding.await;
+    let response = result.map_err(|e| e.to_string())?;
+    on_event
+        .send(StreamEvent::Complete(Box::new(response)))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn relay_graphql_request(
+    relay: State<'_, RelayService>,
+    request: GraphQLRequest,
+) -> Result<GraphQLRelayResponse, String> {
+    relay.relay_graphql_request(request).await
+        .map_err(|e| e.to_string())
+}
+
+/// Fetches the schema of a GraphQL endpoint, from the cache unless `refresh`
+/// is set.
+#[tauri::command]
+async fn introspect_graphql_schema(
+    relay: State<'_, RelayService>,
+    request: GraphQLRequest,
+    refresh: Option<bool>,
+) -> Result<GraphQLSchema, String> {
+    relay.introspect_graphql_schema(request, refresh.unwrap_or_default()).await
+        .map_err(|e| e.to_string())
+}
+
+/// Opens a Server-Sent Events stream, pushing each event to `on_event` until
+/// the request is cancelled or the stream fails.
+#[tauri::command]
+async fn relay_event_stream(
+    relay: State<'_, RelayService>,
+    request: Request,
+    on_event: Channel<EventStreamEvent>,
+) -> Result<(), String> {
+    let (events, forwarding) = forward_events(&on_event, None);
+    let result = relay.relay_event_stream(request, events).await;
+    let _ = forwarding.await;
+    let response = result.map_err(|e| e.to_string())?;
+    on_event
+        .send(EventStreamEvent::Closed(Box::new(response)))
+        .map_err(|e| e.to_string())
+}
+
+/// Opens a WebSocket session, pushing received frames to `on_event` until it
+/// closes. Frames are sent on it by the request id.
+#[tauri::command]
+async fn open_websocket(
+    relay: State<'_, RelayService>,
+    request: Request,
+    on_event: Channel<WebSocketEvent>,
+) -> Result<(), String> {
+    let (events, forwarding) = forward_events(&on_event, None);
+    let result = relay.open_websocket(request, events).await;
+    let _ = forwarding.await;
+    let response = result.map_err(|e| e.to_string())?;
+    on_event
+        .send(WebSocketEvent::Closed(Box::new(response)))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn send_websocket_message(
+    relay: State<'_, RelayService>,
+    id: String,
+    message: WebSocketMessage,
+) -> Result<(), String> {
+    relay.send_websocket_message(&id, message).await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn close_websocket(
+    relay: State<'_, RelayService>,
+    id: String,
+    code: Option<u16>,
+    reason: Option<String>,
+) -> Result<(), String> {
+    relay.close_websocket(&id, code, reason.as_deref().unwrap_or_default()).await
         .map_err(|e| e.to_string())
 }
 
+/// Makes a gRPC call, pushing response metadata and messages to `on_event`
+/// as they arrive.
+#[tauri::command]
+async fn relay_grpc_request(
+    relay: State<'_, RelayService>,
+    request: GrpcRequest,
+    on_event: Channel<GrpcEvent>,
+) -> Result<(), String> {
+    let (events, forwarding) = forward_events(&on_event, None);
+    let result = relay.relay_grpc_request(request, events).await;
+    let _ = forwarding.await;
+    let response = result.map_err(|e| e.to_string())?;
+    on_event
+        .send(GrpcEvent::Complete(Box::new(response)))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn describe_grpc_services(
+    relay: State<'_, RelayService>,
+    request: GrpcRequest,
+) -> Result<Vec<GrpcServiceSchema>, String> {
+    relay.describe_grpc_services(request).await
+        .map_err(|e| e.to_string())
+}
+
+/// Forwards events sent by the relay to the frontend channel, spending one
+/// of `credits` on each. The returned task ends once the sender is dropped.
+fn forward_events<T>(
+    on_event: &Channel<T>,
+    credits: Option<Arc<StreamCredits>>,
+) -> (mpsc::Sender<T>, JoinHandle<()>)
+where
+    T: IpcResponse + Send + 'static,
+{
+    let (events, received) = mpsc::channel(STREAM_EVENT_BUFFER);
+    let on_event = on_event.clone();
+    let forwarding = tauri::async_runtime::spawn(stream_credits::forward(
+        received,
+        credits,
+        move |event| on_event.send(event).is_ok(),
+    ));
+    (events, forwarding)
+}
+
+/// Hands `count` credits back to the streamed response `id` after the
+/// frontend handled that many of its events. Returns `false` if the stream
+/// already ended.
+#[tauri::command]
+fn ack_stream_events(credits: State<'_, StreamCreditRegistry>, id: String, count: usize) -> bool {
+    credits.grant(&id, count)
+}
+
+#[tauri::command]
+fn cancel_request(relay: State<'_, RelayService>, id: String) -> RelayResponse {
+    relay.cancel_request(&id)
+}
+
+#[tauri::command]
+fn get_default_timeouts(relay: State<'_, RelayService>) -> TimeoutSettings {
+    relay.default_timeouts()
+}
+
+#[tauri::command]
+fn set_default_timeouts(relay: State<'_, RelayService>, timeouts: TimeoutSettings) {
+    relay.set_default_timeouts(timeouts);
+}
+
+#[tauri::command]
+fn get_default_proxy(relay: State<'_, RelayService>) -> ProxySetting {
+    relay.default_proxy()
+}
+
+#[tauri::command]
+fn set_default_proxy(relay: State<'_, RelayService>, proxy: ProxySetting) -> Result<(), String> {
+    relay.set_default_proxy(proxy)
+        .map_err(|e| e.to_string())
+}
+
+/// How servers are verified for a workspace's requests, or the default
+/// workspace's.
+#[tauri::command]
+fn get_tls_settings(relay: State<'_, RelayService>, workspace_id: Option<String>) -> TlsSettings {
+    relay.tls_settings(workspace_id.as_deref())
+}
+
+#[tauri::command]
+fn set_tls_settings(
+    relay: State<'_, RelayService>,
+    workspace_id: Option<String>,
+    settings: TlsSettings,
+) -> Result<(), String> {
+    relay.set_tls_settings(workspace_id.as_deref(), settings)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn clear_oauth2_tokens(relay: State<'_, RelayService>) {
+    relay.clear_oauth2_tokens();
+}
+
+#[tauri::command]
+fn clear_graphql_schemas(relay: State<'_, RelayService>) {
+    relay.clear_graphql_schemas();
+}
+
+/// The cookies of a workspace's jar, or of the default jar.
+#[tauri::command]
+fn list_cookies(relay: State<'_, RelayService>, workspace_id: Option<String>) -> Vec<Cookie> {
+    relay.cookies().list(workspace_id.as_deref())
+}
+
+/// Adds a cookie, or replaces the one with the same name, domain and path.
+#[tauri::command]
+fn set_cookie(
+    relay: State<'_, RelayService>,
+    workspace_id: Option<String>,
+    cookie: Cookie,
+) -> Result<(), String> {
+    relay.cookies().set(workspace_id.as_deref(), cookie)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_cookie(
+    relay: State<'_, RelayService>,
+    workspace_id: Option<String>,
+    name: String,
+    domain: String,
+    path: String,
+) -> bool {
+    relay.cookies().remove(workspace_id.as_deref(), &name, &domain, &path)
+}
+
+#[tauri::command]
+fn clear_cookies(relay: State<'_, RelayService>, workspace_id: Option<String>) {
+    relay.cookies().clear(workspace_id.as_deref());
+}
+
+/// Adds the cookies of a Netscape `cookies.txt` file, returning how many
+/// were imported.
+#[tauri::command]
+fn import_cookies(
+    relay: State<'_, RelayService>,
+    workspace_id: Option<String>,
+    contents: String,
+) -> Result<usize, String> {
+    relay.cookies().import_netscape(workspace_id.as_deref(), &contents)
+        .map_err(|e| e.to_string())
+}
+
+/// A jar as a Netscape `cookies.txt` file.
+#[tauri::command]
+fn export_cookies(relay: State<'_, RelayService>, workspace_id: Option<String>) -> String {
+    relay.cookies().export_netscape(workspace_id.as_deref())
+}
+
 #[tauri::command]
 async fn health_check() -> Result<serde_json::Value, String> {
     Ok(serde_json::json!({
@@ -35,11 +335,65 @@ async fn health_check() -> Result<serde_json::Value, String> {
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            let handle = app.handle().clone();
+            let cookie_errors = handle.clone();
+            let cookies = CookieStore::load_or_reset(
+                app.path().app_data_dir()?.join("cookies.json"),
+                Arc::new(move |message| {
+                    let _ = cookie_errors.emit("cookie-jar-error", message);
+                }),
+            )?;
+            app.manage(StreamCreditRegistry::default());
+            app.manage(
+                RelayService::new()
+                    .with_cookie_store(cookies)
+                    .with_browser_opener(Arc::new(|url: &str| {
+                        tauri_plugin_opener::open_url(url, None::<&str>)?;
+                        Ok(())
+                    }))
+                    .with_upload_progress(Arc::new(move |progress| {
+                        let _ = handle.emit("upload-progress", progress);
+                    })),
+            );
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             greet,
             relay_request,
+            relay_request_stream,
+            relay_graphql_request,
+            introspect_graphql_schema,
+            relay_event_stream,
+            open_websocket,
+            send_websocket_message,
+            close_websocket,
+            relay_grpc_request,
+            describe_grpc_services,
+            cancel_request,
+            ack_stream_events,
+            get_default_timeouts,
+            set_default_timeouts,
+            get_default_proxy,
+            set_default_proxy,
+            get_tls_settings,
+            set_tls_settings,
+            clear_oauth2_tokens,
+            clear_graphql_schemas,
+            list_cookies,
+            set_cookie,
+            delete_cookie,
+            clear_cookies,
+            import_cookies,
+            export_cookies,
             health_check
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // Cookie changes are saved in the background; finish before exiting
+            if let RunEvent::Exit = event {
+                app.state::<RelayService>().cookies().flush();
+            }
+        });
 }
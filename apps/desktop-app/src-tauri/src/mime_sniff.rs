@@ -0,0 +1,150 @@
+//! Content sniffing of response bodies from their leading "magic" bytes.
+//!
+//! Based on the signatures of the WHATWG MIME Sniffing standard
+//! (https://mimesniff.spec.whatwg.org/), extended with a few formats commonly
+//! returned by APIs.
+
+/// Signatures matched against the start of the body. `None` bytes in a
+/// pattern match anything.
+const SIGNATURES: &[(&[Option<u8>], &str)] = &[
+    (&bytes(b"\x89PNG\r\n\x1a\n"), "image/png"),
+    (&bytes(b"\xff\xd8\xff"), "image/jpeg"),
+    (&bytes(b"GIF87a"), "image/gif"),
+    (&bytes(b"GIF89a"), "image/gif"),
+    (&riff(b"WEBP"), "image/webp"),
+    (&bytes(b"BM"), "image/bmp"),
+    (&bytes(b"\x00\x00\x01\x00"), "image/x-icon"),
+    (&bytes(b"II*\x00"), "image/tiff"),
+    (&bytes(b"MM\x00*"), "image/tiff"),
+    (&bytes(b"%PDF-"), "application/pdf"),
+    (&bytes(b"PK\x03\x04"), "application/zip"),
+    (&bytes(b"\x1f\x8b\x08"), "application/gzip"),
+    (&bytes(b"\x28\xb5\x2f\xfd"), "application/zstd"),
+    (&bytes(b"7z\xbc\xaf\x27\x1c"), "application/x-7z-compressed"),
+    (&bytes(b"Rar!\x1a\x07"), "application/vnd.rar"),
+    (&bytes(b"\x00asm"), "application/wasm"),
+    (&bytes(b"\x7fELF"), "application/x-elf"),
+    (&bytes(b"SQLite format 3\x00"), "application/vnd.sqlite3"),
+    (&bytes(b"ID3"), "audio/mpeg"),
+    (&bytes(b"OggS\x00"), "audio/ogg"),
+    (&bytes(b"fLaC"), "audio/flac"),
+    (&riff(b"WAVE"), "audio/wav"),
+    (&riff(b"AVI "), "video/x-msvideo"),
+    (&ftyp(), "video/mp4"),
+    (&bytes(b"\x1a\x45\xdf\xa3"), "video/webm"),
+    (&bytes(b"wOFF"), "font/woff"),
+    (&bytes(b"wOF2"), "font/woff2"),
+    (&bytes(b"\x00\x01\x00\x00\x00"), "font/ttf"),
+    (&bytes(b"OTTO"), "font/otf"),
+];
+
+const fn bytes<const N: usize>(pattern: &[u8; N]) -> [Option<u8>; N] {
+    let mut result = [None; N];
+    let mut i = 0;
+    while i < N {
+        result[i] = Some(pattern[i]);
+        i += 1;
+    }
+    result
+}
+
+/// `RIFF` container of the given four-byte form type.
+const fn riff(form: &[u8; 4]) -> [Option<u8>; 12] {
+    let mut result = [None; 12];
+    let mut i = 0;
+    while i < 4 {
+        result[i] = Some(b"RIFF"[i]);
+        result[8 + i] = Some(form[i]);
+        i += 1;
+    }
+    result
+}
+
+/// ISO base media file (`....ftyp`).
+const fn ftyp() -> [Option<u8>; 8] {
+    [
+        None,
+        None,
+        None,
+        None,
+        Some(b'f'),
+        Some(b't'),
+        Some(b'y'),
+        Some(b'p'),
+    ]
+}
+
+/// Returns the MIME type identified by the body's leading bytes, if any.
+pub fn sniff_mime_type(body: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(pattern, _)| {
+            body.len() >= pattern.len()
+                && pattern
+                    .iter()
+                    .zip(body)
+                    .all(|(expected, actual)| expected.is_none_or(|b| b == *actual))
+        })
+        .map(|(_, mime_type)| *mime_type)
+}
+
+/// Whether the body has to be treated as binary: it isn't valid UTF-8 or
+/// contains control characters text never does.
+pub fn is_binary(body: &[u8]) -> bool {
+    std::str::from_utf8(body).is_err() || contains_binary_data(body)
+}
+
+/// Whether the body contains control characters text never does, whatever
+/// its charset.
+pub fn contains_binary_data(body: &[u8]) -> bool {
+    body.iter().any(|b| is_binary_data_byte(*b))
+}
+
+/// The "binary data byte" of the MIME Sniffing standard.
+fn is_binary_data_byte(byte: u8) -> bool {
+    matches!(byte, 0x00..=0x08 | 0x0b | 0x0e..=0x1a | 0x1c..=0x1f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniffs_common_formats() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR", "image/png"),
+            (b"\xff\xd8\xff\xe0\x00\x10JFIF", "image/jpeg"),
+            (b"GIF89a\x01\x00", "image/gif"),
+            (b"RIFF\x24\x00\x00\x00WEBPVP8 ", "image/webp"),
+            (b"RIFF\x24\x00\x00\x00WAVEfmt ", "audio/wav"),
+            (b"%PDF-1.7\n", "application/pdf"),
+            (b"PK\x03\x04\x14\x00", "application/zip"),
+            (b"\x00\x00\x00\x18ftypmp42", "video/mp4"),
+            (b"\x00asm\x01\x00\x00\x00", "application/wasm"),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(sniff_mime_type(body), Some(*expected));
+        }
+    }
+
+    #[test]
+    fn test_unknown_and_short_bodies() {
+        assert_eq!(sniff_mime_type(b""), None);
+        assert_eq!(sniff_mime_type(b"RIFF"), None);
+        assert_eq!(sniff_mime_type(b"{\"json\": true}"), None);
+    }
+
+    #[test]
+    fn test_binary_detection() {
+        assert!(!is_binary(b""));
+        assert!(!is_binary(
+            "plain text\twith tabs\r\nand ünïcode".as_bytes()
+        ));
+        // Invalid UTF-8
+        assert!(is_binary(b"\xff\xfe\xfd"));
+        // Valid UTF-8 with control bytes, e.g. a small protobuf message
+        assert!(is_binary(b"\x08\x01\x12\x04test"));
+        // Text that happens to start like a binary signature
+        assert!(!is_binary(b"BMW models"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Encoding of `multipart/form-data` request bodies (RFC 7578).
+//!
+//! Part headers are assembled in memory while file contents stay on disk as
+//! [`Segment::File`]s, so large uploads are streamed like any file body.
+
+use crate::request_body::{FileSegment, Segment};
+use crate::types::{MultipartPart, MultipartValue};
+use anyhow::{anyhow, Result};
+use hyper::body::Bytes;
+use hyper::header::{HeaderName, HeaderValue};
+use std::path::Path;
+
+/// A `multipart/form-data` body ready to be sent.
+#[derive(Debug, Clone)]
+pub struct MultipartBody {
+    pub boundary: String,
+    pub segments: Vec<Segment>,
+}
+
+impl MultipartBody {
+    /// Encodes the parts with a freshly generated boundary.
+    pub async fn encode(parts: &[MultipartPart]) -> Result<Self> {
+        let boundary = format!(
+            "----ClinicFormBoundary{}",
+            hex::encode(rand::random::<[u8; 12]>())
+        );
+        let mut segments = Vec::new();
+        let mut pending = String::new();
+
+        for part in parts {
+            pending.push_str(&format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                boundary,
+                escape(&part.name)
+            ));
+            let (file, content_type) = match &part.value {
+                MultipartValue::Text { .. } => (None, part.content_type.as_deref()),
+                MultipartValue::File {
+                    file_path,
+                    filename,
+                } => {
+                    let filename = match filename {
+                        Some(filename) => filename.as_str(),
+                        None => Path::new(file_path)
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .unwrap_or_default(),
+                    };
+                    pending.push_str(&format!("; filename=\"{}\"", escape(filename)));
+                    let content_type = part
+                        .content_type
+                        .as_deref()
+                        .unwrap_or("application/octet-stream");
+                    (Some(FileSegment::new(file_path).await?), Some(content_type))
+                }
+            };
+            pending.push_str("\r\n");
+
+            if let Some(content_type) = content_type {
+                pending.push_str(&header_line(&part.name, "Content-Type", content_type)?);
+            }
+            let mut headers: Vec<_> = part.headers.iter().collect();
+            headers.sort();
+            for (name, value) in headers {
+                pending.push_str(&header_line(&part.name, name, value)?);
+            }
+            pending.push_str("\r\n");
+
+            match (file, &part.value) {
+                (Some(file), _) => {
+                    segments.push(Segment::Bytes(Bytes::from(std::mem::take(&mut pending))));
+                    segments.push(Segment::File(file));
+                }
+                (None, MultipartValue::Text { value }) => pending.push_str(value),
+                (None, MultipartValue::File { .. }) => unreachable!("file parts are opened"),
+            }
+            pending.push_str("\r\n");
+        }
+        pending.push_str(&format!("--{}--\r\n", boundary));
+        segments.push(Segment::Bytes(Bytes::from(pending)));
+
+        Ok(Self { boundary, segments })
+    }
+
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+}
+
+fn header_line(part: &str, name: &str, value: &str) -> Result<String> {
+    if HeaderName::from_bytes(name.as_bytes()).is_err() || HeaderValue::from_str(value).is_err() {
+        return Err(anyhow!(
+            "Invalid header {} in multipart part {}",
+            name,
+            part
+        ));
+    }
+    Ok(format!("{}: {}\r\n", name, value))
+}
+
+/// Escapes a field name or filename as browsers do, so it can't end the
+/// quoted string or the header line.
+fn escape(value: &str) -> String {
+    value
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_body::BodySource;
+    use std::collections::HashMap;
+
+    fn text(name: &str, value: &str) -> MultipartPart {
+        MultipartPart {
+            name: name.to_string(),
+            value: MultipartValue::Text {
+                value: value.to_string(),
+            },
+            content_type: None,
+            headers: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_fields_encoding() {
+        let mut described = text("description", "A \"quoted\"\r\nvalue");
+        described.content_type = Some("text/plain; charset=utf-8".to_string());
+        described
+            .headers
+            .insert("X-Field-Id".to_string(), "7".to_string());
+        let body = MultipartBody::encode(&[text("na\"me", "ada"), described])
+            .await
+            .unwrap();
+
+        let boundary = &body.boundary;
+        assert!(boundary.starts_with("----ClinicFormBoundary"));
+        assert_eq!(
+            body.content_type(),
+            format!("multipart/form-data; boundary={}", boundary)
+        );
+        let encoded = BodySource::Multipart(body.clone())
+            .to_bytes()
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(encoded.to_vec()).unwrap(),
+            format!(
+                "--{b}\r\n\
+                 Content-Disposition: form-data; name=\"na%22me\"\r\n\
+                 \r\n\
+                 ada\r\n\
+                 --{b}\r\n\
+                 Content-Disposition: form-data; name=\"description\"\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\
+                 X-Field-Id: 7\r\n\
+                 \r\n\
+                 A \"quoted\"\r\nvalue\r\n\
+                 --{b}--\r\n",
+                b = boundary
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_parts_stay_on_disk() {
+        let path = std::env::temp_dir().join(format!("relay-part-{}.bin", std::process::id()));
+        tokio::fs::write(&path, b"\x00\x01\x02").await.unwrap();
+        let file_path = path.to_string_lossy().into_owned();
+        let file = |filename: Option<&str>| MultipartPart {
+            name: "upload".to_string(),
+            value: MultipartValue::File {
+                file_path: file_path.clone(),
+                filename: filename.map(str::to_string),
+            },
+            content_type: None,
+            headers: HashMap::new(),
+        };
+
+        let body = MultipartBody::encode(&[file(None), file(Some("renamed.png"))])
+            .await
+            .unwrap();
+        assert_eq!(body.segments.len(), 5);
+        assert!(matches!(&body.segments[1], Segment::File(file) if file.length == 3));
+
+        let source = BodySource::Multipart(body);
+        let encoded = source.to_bytes().await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(source.len(), encoded.len() as u64);
+
+        let filename = path.file_name().unwrap().to_str().unwrap();
+        let encoded = String::from_utf8_lossy(&encoded);
+        assert!(encoded.contains(&format!(
+            "name=\"upload\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n\u{0}\u{1}\u{2}\r\n",
+            filename
+        )));
+        assert!(encoded.contains("filename=\"renamed.png\""));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_parts_are_rejected() {
+        let mut part = text("field", "value");
+        part.headers
+            .insert("X-Bad".to_string(), "line\r\nInjected: yes".to_string());
+        let error = MultipartBody::encode(&[part]).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid header X-Bad in multipart part field"
+        );
+
+        let missing = MultipartPart {
+            name: "upload".to_string(),
+            value: MultipartValue::File {
+                file_path: "/nonexistent/part.bin".to_string(),
+                filename: None,
+            },
+            content_type: None,
+            headers: HashMap::new(),
+        };
+        let error = MultipartBody::encode(&[missing]).await.unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Failed to read request body file /nonexistent/part.bin"));
+    }
+}
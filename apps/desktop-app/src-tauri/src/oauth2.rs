@@ -0,0 +1,690 @@
+//! OAuth 2.0 token acquisition (RFC 6749) and caching.
+//!
+//! Tokens are cached per flow and reused until shortly before they expire.
+//! Expired tokens are refreshed with their refresh token when the server
+//! issued one, otherwise the flow's grant is run again.
+//!
+//! The authorization code grant uses PKCE (RFC 7636): the user's browser is
+//! sent to the authorization server, which redirects back to a loopback
+//! listener on `127.0.0.1` (RFC 8252) that receives the code.
+
+use crate::request_body::RelayBody;
+use crate::types::{OAuth2ClientAuthentication, OAuth2Flow, OAuth2Grant};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request as HyperRequest, Response as HyperResponse, StatusCode};
+use hyper_util::client::legacy::connect::Connect;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use url::{form_urlencoded, Url};
+
+/// Tokens this close to expiry are refreshed before being used.
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+/// How long the user has to finish authorizing in the browser.
+const AUTHORIZATION_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Opens a URL in the user's browser.
+pub type BrowserOpener = Arc<dyn Fn(&str) -> Result<()> + Send + Sync>;
+
+/// The cached token of one flow, locked while a new token is obtained.
+type TokenSlot = Arc<AsyncMutex<Option<CachedToken>>>;
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: Option<Instant>,
+}
+
+impl CachedToken {
+    fn is_fresh(&self) -> bool {
+        self.expires_at
+            .is_none_or(|expires_at| Instant::now() + REFRESH_MARGIN < expires_at)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    /// Seconds until expiry; some servers send it as a string
+    #[serde(default)]
+    expires_in: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// Obtains OAuth 2.0 access tokens and caches them per flow.
+///
+/// The cache is shared between clones. Concurrent requests for the same flow
+/// wait for a single token request instead of each starting their own.
+#[derive(Clone, Default)]
+pub struct OAuth2Tokens {
+    entries: Arc<Mutex<HashMap<String, TokenSlot>>>,
+    opener: Option<BrowserOpener>,
+}
+
+impl OAuth2Tokens {
+    /// Sets how the authorization code flow opens the user's browser.
+    pub fn with_browser_opener(mut self, opener: BrowserOpener) -> Self {
+        self.opener = Some(opener);
+        self
+    }
+
+    /// Forgets every cached token.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Returns a valid access token for `flow`, from the cache if possible.
+    pub async fn access_token<C>(
+        &self,
+        client: &Client<C, RelayBody>,
+        flow: &OAuth2Flow,
+    ) -> Result<String>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let entry = self
+            .entries
+            .lock()
+            .unwrap()
+            .entry(cache_key(flow))
+            .or_default()
+            .clone();
+        let mut cached = entry.lock().await;
+
+        if let Some(token) = cached.as_ref().filter(|token| token.is_fresh()) {
+            return Ok(token.access_token.clone());
+        }
+
+        // A failed refresh (e.g. a revoked refresh token) falls back to the grant
+        let refresh_token = cached
+            .as_ref()
+            .and_then(|token| token.refresh_token.clone());
+        let refreshed = match refresh_token {
+            Some(refresh_token) => refresh(client, flow, refresh_token).await.ok(),
+            None => None,
+        };
+        let token = match refreshed {
+            Some(token) => token,
+            None => self.grant(client, flow).await?,
+        };
+
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+    }
+
+    async fn grant<C>(
+        &self,
+        client: &Client<C, RelayBody>,
+        flow: &OAuth2Flow,
+    ) -> Result<CachedToken>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        match &flow.grant {
+            OAuth2Grant::ClientCredentials => {
+                request_token(
+                    client,
+                    flow,
+                    vec![("grant_type", "client_credentials".into())],
+                )
+                .await
+            }
+            OAuth2Grant::Password { username, password } => {
+                let params = vec![
+                    ("grant_type", "password".to_string()),
+                    ("username", username.clone()),
+                    ("password", password.clone()),
+                ];
+                request_token(client, flow, params).await
+            }
+            OAuth2Grant::RefreshToken { refresh_token } => {
+                refresh(client, flow, refresh_token.clone()).await
+            }
+            OAuth2Grant::AuthorizationCode {
+                authorization_url,
+                redirect_port,
+            } => {
+                let (code, redirect_uri, code_verifier) = self
+                    .authorize_in_browser(flow, authorization_url, *redirect_port)
+                    .await?;
+                let params = vec![
+                    ("grant_type", "authorization_code".to_string()),
+                    ("code", code),
+                    ("redirect_uri", redirect_uri),
+                    ("code_verifier", code_verifier),
+                ];
+                request_token(client, flow, params).await
+            }
+        }
+    }
+
+    /// Sends the user to the authorization server and waits for the redirect
+    /// back to the loopback listener. Returns the code, the redirect URI and
+    /// the PKCE code verifier.
+    async fn authorize_in_browser(
+        &self,
+        flow: &OAuth2Flow,
+        authorization_url: &str,
+        redirect_port: Option<u16>,
+    ) -> Result<(String, String, String)> {
+        let opener = self
+            .opener
+            .as_ref()
+            .ok_or_else(|| anyhow!("No browser available for the authorization code flow"))?;
+
+        let listener = TcpListener::bind(("127.0.0.1", redirect_port.unwrap_or(0)))
+            .await
+            .map_err(|e| anyhow!("Failed to start the OAuth redirect listener: {}", e))?;
+        let redirect_uri = format!(
+            "http://127.0.0.1:{}/callback",
+            listener.local_addr()?.port()
+        );
+
+        let code_verifier = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+        let state = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>());
+
+        let mut url = Url::parse(authorization_url)
+            .map_err(|e| anyhow!("Invalid authorization URL: {}", e))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &flow.client_id)
+                .append_pair("redirect_uri", &redirect_uri)
+                .append_pair("state", &state)
+                .append_pair("code_challenge", &code_challenge)
+                .append_pair("code_challenge_method", "S256");
+            if let Some(scope) = flow.scope.as_deref().filter(|scope| !scope.is_empty()) {
+                query.append_pair("scope", scope);
+            }
+        }
+        opener(url.as_str())?;
+
+        let params = tokio::time::timeout(AUTHORIZATION_TIMEOUT, wait_for_redirect(listener))
+            .await
+            .map_err(|_| anyhow!("Timed out waiting for the authorization redirect"))??;
+
+        if params.get("state") != Some(&state) {
+            return Err(anyhow!("Authorization redirect had an unexpected state"));
+        }
+        if let Some(error) = params.get("error") {
+            return Err(match params.get("error_description") {
+                Some(description) => anyhow!("Authorization failed: {}: {}", error, description),
+                None => anyhow!("Authorization failed: {}", error),
+            });
+        }
+        let code = params
+            .get("code")
+            .cloned()
+            .ok_or_else(|| anyhow!("Authorization redirect did not include a code"))?;
+
+        Ok((code, redirect_uri, code_verifier))
+    }
+}
+
+/// Identifies the token a flow yields, so that changing any of its settings
+/// obtains a new token.
+fn cache_key(flow: &OAuth2Flow) -> String {
+    let grant = match &flow.grant {
+        OAuth2Grant::ClientCredentials => "client_credentials".to_string(),
+        OAuth2Grant::Password { username, .. } => format!("password:{}", username),
+        OAuth2Grant::RefreshToken { refresh_token } => format!("refresh_token:{}", refresh_token),
+        OAuth2Grant::AuthorizationCode {
+            authorization_url, ..
+        } => format!("authorization_code:{}", authorization_url),
+    };
+    format!(
+        "{}\n{}\n{}\n{}",
+        flow.token_url,
+        flow.client_id,
+        flow.scope.as_deref().unwrap_or_default(),
+        grant
+    )
+}
+
+async fn refresh<C>(
+    client: &Client<C, RelayBody>,
+    flow: &OAuth2Flow,
+    refresh_token: String,
+) -> Result<CachedToken>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    let params = vec![
+        ("grant_type", "refresh_token".to_string()),
+        ("refresh_token", refresh_token.clone()),
+    ];
+    let mut token = request_token(client, flow, params).await?;
+    // Servers that don't rotate refresh tokens omit them from the response
+    token.refresh_token.get_or_insert(refresh_token);
+    Ok(token)
+}
+
+/// Posts a token request to the flow's token endpoint.
+async fn request_token<C>(
+    client: &Client<C, RelayBody>,
+    flow: &OAuth2Flow,
+    mut params: Vec<(&str, String)>,
+) -> Result<CachedToken>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+{
+    if let Some(scope) = flow.scope.as_deref().filter(|scope| !scope.is_empty()) {
+        params.push(("scope", scope.to_string()));
+    }
+
+    let mut builder = HyperRequest::builder()
+        .method(Method::POST)
+        .uri(&flow.token_url)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header(ACCEPT, "application/json");
+    match (&flow.client_secret, flow.client_authentication) {
+        (Some(secret), OAuth2ClientAuthentication::Basic) => {
+            // Credentials are form-encoded before being joined (RFC 6749 2.3.1)
+            let encode =
+                |value: &str| form_urlencoded::byte_serialize(value.as_bytes()).collect::<String>();
+            let credentials = format!("{}:{}", encode(&flow.client_id), encode(secret));
+            builder = builder.header(
+                AUTHORIZATION,
+                format!("Basic {}", STANDARD.encode(credentials)),
+            );
+        }
+        (secret, _) => {
+            params.push(("client_id", flow.client_id.clone()));
+            if let Some(secret) = secret {
+                params.push(("client_secret", secret.clone()));
+            }
+        }
+    }
+
+    let form = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(&params)
+        .finish();
+    let request = builder
+        .body(RelayBody::from(Bytes::from(form)))
+        .map_err(|e| anyhow!("Invalid token URL: {}", e))?;
+    let response = client
+        .request(request)
+        .await
+        .map_err(|e| anyhow!("Token request failed: {}", e))?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| anyhow!("Failed to read token response: {}", e))?
+        .to_bytes();
+
+    if !status.is_success() {
+        return Err(match serde_json::from_slice::<TokenErrorResponse>(&body) {
+            Ok(TokenErrorResponse {
+                error,
+                error_description: Some(description),
+            }) => anyhow!("Token request failed: {}: {}", error, description),
+            Ok(TokenErrorResponse { error, .. }) => anyhow!("Token request failed: {}", error),
+            Err(_) => anyhow!("Token request failed with status {}", status.as_u16()),
+        });
+    }
+
+    let token: TokenResponse =
+        serde_json::from_slice(&body).map_err(|e| anyhow!("Invalid token response: {}", e))?;
+    let expires_in = token.expires_in.and_then(|value| match value {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.parse().ok(),
+        _ => None,
+    });
+
+    Ok(CachedToken {
+        access_token: token.access_token,
+        refresh_token: token.refresh_token,
+        expires_at: expires_in.map(|secs| Instant::now() + Duration::from_secs(secs)),
+    })
+}
+
+/// Serves the loopback redirect URI until a request carrying a code or an
+/// error arrives, returning its query parameters.
+async fn wait_for_redirect(listener: TcpListener) -> Result<HashMap<String, String>> {
+    let (sender, mut receiver) = mpsc::channel(1);
+
+    loop {
+        tokio::select! {
+            Some(params) = receiver.recv() => return Ok(params),
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let sender = sender.clone();
+                let service = service_fn(move |request| {
+                    let sender = sender.clone();
+                    async move { Ok::<_, Infallible>(redirect_response(request, &sender)) }
+                });
+                tokio::spawn(async move {
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        }
+    }
+}
+
+fn redirect_response(
+    request: HyperRequest<Incoming>,
+    sender: &mpsc::Sender<HashMap<String, String>>,
+) -> HyperResponse<Full<Bytes>> {
+    let params: HashMap<String, String> =
+        form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+            .into_owned()
+            .collect();
+
+    // Browsers also ask for things like /favicon.ico
+    if request.uri().path() != "/callback"
+        || !(params.contains_key("code") || params.contains_key("error"))
+    {
+        let mut response = HyperResponse::new(Full::new(Bytes::from_static(b"Not found")));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+
+    let _ = sender.try_send(params);
+    let mut response = HyperResponse::new(Full::new(Bytes::from_static(
+        b"<html><body>Authorization complete. You can close this window and return to Clinic.</body></html>",
+    )));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "text/html; charset=utf-8".parse().unwrap());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{extract::State, routing::get, routing::post, Form, Json, Router};
+    use hyper_util::client::legacy::connect::HttpConnector;
+    use hyper_util::rt::TokioExecutor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Authorization header and form of a token request.
+    type TokenRequest = (Option<String>, HashMap<String, String>);
+
+    /// Records what the stand-in authorization server was sent.
+    #[derive(Clone, Default)]
+    struct AuthServer {
+        token_requests: Arc<Mutex<Vec<TokenRequest>>>,
+        code_challenge: Arc<Mutex<Option<String>>>,
+        issued: Arc<AtomicUsize>,
+    }
+
+    async fn token(
+        State(server): State<AuthServer>,
+        headers: axum::http::HeaderMap,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> (axum::http::StatusCode, Json<serde_json::Value>) {
+        let authorization = headers
+            .get("authorization")
+            .map(|value| value.to_str().unwrap().to_string());
+        server
+            .token_requests
+            .lock()
+            .unwrap()
+            .push((authorization, form.clone()));
+
+        let valid = match form["grant_type"].as_str() {
+            "client_credentials" => true,
+            "password" => form.get("password").is_some_and(|p| p == "hunter2"),
+            "refresh_token" => form
+                .get("refresh_token")
+                .is_some_and(|t| t.starts_with("refresh-")),
+            "authorization_code" => {
+                let challenge = server.code_challenge.lock().unwrap().clone();
+                let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+                form.get("code").is_some_and(|code| code == "the-code")
+                    && challenge == Some(URL_SAFE_NO_PAD.encode(Sha256::digest(verifier)))
+            }
+            _ => false,
+        };
+        if !valid {
+            return (
+                axum::http::StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "invalid_grant",
+                    "error_description": "Bad credentials"
+                })),
+            );
+        }
+
+        let n = server.issued.fetch_add(1, Ordering::SeqCst) + 1;
+        // Short-lived tokens are refreshed on their next use
+        let expires_in = if form.contains_key("scope") { 10 } else { 3600 };
+        (
+            axum::http::StatusCode::OK,
+            Json(serde_json::json!({
+                "access_token": format!("access-{}", n),
+                "token_type": "Bearer",
+                "expires_in": expires_in,
+                "refresh_token": format!("refresh-{}", n)
+            })),
+        )
+    }
+
+    async fn authorize(
+        State(server): State<AuthServer>,
+        axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>,
+    ) -> axum::response::Redirect {
+        *server.code_challenge.lock().unwrap() = query.get("code_challenge").cloned();
+        axum::response::Redirect::to(&format!(
+            "{}?code=the-code&state={}",
+            query["redirect_uri"], query["state"]
+        ))
+    }
+
+    async fn start_auth_server() -> (String, AuthServer) {
+        let server = AuthServer::default();
+        let app = Router::new()
+            .route("/token", post(token))
+            .route("/authorize", get(authorize))
+            .with_state(server.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{}", addr), server)
+    }
+
+    fn client() -> Client<HttpConnector, RelayBody> {
+        Client::builder(TokioExecutor::new()).build_http()
+    }
+
+    fn flow(base: &str, grant: OAuth2Grant) -> OAuth2Flow {
+        OAuth2Flow {
+            token_url: format!("{}/token", base),
+            client_id: "clinic".to_string(),
+            client_secret: Some("s3cret".to_string()),
+            scope: None,
+            client_authentication: OAuth2ClientAuthentication::Basic,
+            grant,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_credentials_token_is_cached() {
+        let (base, server) = start_auth_server().await;
+        let tokens = OAuth2Tokens::default();
+        let flow = flow(&base, OAuth2Grant::ClientCredentials);
+
+        assert_eq!(
+            tokens.access_token(&client(), &flow).await.unwrap(),
+            "access-1"
+        );
+        assert_eq!(
+            tokens.access_token(&client(), &flow).await.unwrap(),
+            "access-1"
+        );
+
+        let requests = server.token_requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        // clinic:s3cret
+        assert_eq!(requests[0].0.as_deref(), Some("Basic Y2xpbmljOnMzY3JldA=="));
+        assert!(!requests[0].1.contains_key("client_secret"));
+    }
+
+    #[tokio::test]
+    async fn test_password_grant_with_body_credentials() {
+        let (base, server) = start_auth_server().await;
+        let tokens = OAuth2Tokens::default();
+        let mut flow = flow(
+            &base,
+            OAuth2Grant::Password {
+                username: "ada".to_string(),
+                password: "hunter2".to_string(),
+            },
+        );
+        flow.client_authentication = OAuth2ClientAuthentication::Body;
+
+        assert_eq!(
+            tokens.access_token(&client(), &flow).await.unwrap(),
+            "access-1"
+        );
+        {
+            let requests = server.token_requests.lock().unwrap();
+            let (authorization, form) = &requests[0];
+            assert!(authorization.is_none());
+            assert_eq!(form["username"], "ada");
+            assert_eq!(form["client_id"], "clinic");
+            assert_eq!(form["client_secret"], "s3cret");
+        }
+
+        // Errors from the token endpoint are surfaced with their description
+        let mut wrong = flow.clone();
+        wrong.grant = OAuth2Grant::Password {
+            username: "eve".to_string(),
+            password: "guess".to_string(),
+        };
+        let error = tokens.access_token(&client(), &wrong).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Token request failed: invalid_grant: Bad credentials"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expiring_token_is_refreshed() {
+        let (base, server) = start_auth_server().await;
+        let tokens = OAuth2Tokens::default();
+        let mut flow = flow(&base, OAuth2Grant::ClientCredentials);
+        // The stand-in server issues tokens expiring within the refresh margin
+        flow.scope = Some("read".to_string());
+
+        assert_eq!(
+            tokens.access_token(&client(), &flow).await.unwrap(),
+            "access-1"
+        );
+        assert_eq!(
+            tokens.access_token(&client(), &flow).await.unwrap(),
+            "access-2"
+        );
+
+        let refresh = server.token_requests.lock().unwrap()[1].1.clone();
+        assert_eq!(refresh["grant_type"], "refresh_token");
+        assert_eq!(refresh["refresh_token"], "refresh-1");
+
+        // Clearing the cache starts over with the grant
+        tokens.clear();
+        tokens.access_token(&client(), &flow).await.unwrap();
+        let requests = server.token_requests.lock().unwrap();
+        assert_eq!(requests[2].1["grant_type"], "client_credentials");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token_grant() {
+        let (base, server) = start_auth_server().await;
+        let tokens = OAuth2Tokens::default();
+        let flow = flow(
+            &base,
+            OAuth2Grant::RefreshToken {
+                refresh_token: "refresh-saved".to_string(),
+            },
+        );
+
+        assert_eq!(
+            tokens.access_token(&client(), &flow).await.unwrap(),
+            "access-1"
+        );
+        let requests = server.token_requests.lock().unwrap();
+        assert_eq!(requests[0].1["grant_type"], "refresh_token");
+        assert_eq!(requests[0].1["refresh_token"], "refresh-saved");
+    }
+
+    #[tokio::test]
+    async fn test_authorization_code_with_pkce() {
+        let (base, server) = start_auth_server().await;
+
+        // Stands in for the browser: follows the authorization server's
+        // redirect to the loopback listener
+        let opened = Arc::new(Mutex::new(None));
+        let opened_url = opened.clone();
+        let opener: BrowserOpener = Arc::new(move |url: &str| {
+            *opened_url.lock().unwrap() = Some(url.to_string());
+            let url = url.to_string();
+            tokio::spawn(async move {
+                let browser = client();
+                let get = |url: String| {
+                    HyperRequest::get(url)
+                        .body(RelayBody::from(Bytes::new()))
+                        .unwrap()
+                };
+                let redirect = browser.request(get(url)).await.unwrap();
+                let location = redirect.headers()["location"].to_str().unwrap().to_string();
+                let callback = browser.request(get(location)).await.unwrap();
+                assert_eq!(callback.status(), StatusCode::OK);
+            });
+            Ok(())
+        });
+        let tokens = OAuth2Tokens::default().with_browser_opener(opener);
+        let flow = flow(
+            &base,
+            OAuth2Grant::AuthorizationCode {
+                authorization_url: format!("{}/authorize", base),
+                redirect_port: None,
+            },
+        );
+
+        assert_eq!(
+            tokens.access_token(&client(), &flow).await.unwrap(),
+            "access-1"
+        );
+
+        let opened = Url::parse(opened.lock().unwrap().as_deref().unwrap()).unwrap();
+        let query: HashMap<_, _> = opened.query_pairs().into_owned().collect();
+        assert_eq!(query["response_type"], "code");
+        assert_eq!(query["code_challenge_method"], "S256");
+        assert!(query["redirect_uri"].starts_with("http://127.0.0.1:"));
+
+        let requests = server.token_requests.lock().unwrap();
+        assert_eq!(requests[0].1["grant_type"], "authorization_code");
+        assert_eq!(requests[0].1["redirect_uri"], query["redirect_uri"]);
+    }
+}
@@ -0,0 +1,1037 @@
+//! Loading of `.proto` files into a descriptor pool.
+//!
+//! Parses the proto2 and proto3 syntax into `FileDescriptorProto`s the way
+//! `protoc` would, leaving type references unresolved: the descriptor pool
+//! resolves them by the usual scoping rules. Options are skipped except those
+//! that change how messages are encoded (`packed`, `default`, `json_name`,
+//! `allow_alias`). Groups and editions aren't supported.
+
+use anyhow::{anyhow, Result};
+use prost_reflect::DescriptorPool;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumOptions, EnumValueDescriptorProto,
+    FieldDescriptorProto, FieldOptions, FileDescriptorProto, MessageOptions, MethodDescriptorProto,
+    OneofDescriptorProto, ServiceDescriptorProto,
+};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Parses `files` and everything they import into a pool that also holds the
+/// well-known types. Imports are looked up in `import_paths`, then next to
+/// the files themselves.
+pub fn load_proto_files(files: &[String], import_paths: &[String]) -> Result<DescriptorPool> {
+    let mut roots: Vec<PathBuf> = import_paths.iter().map(PathBuf::from).collect();
+    let mut pending = VecDeque::new();
+    for file in files {
+        let path = Path::new(file);
+        // Files are named relative to the import path containing them
+        let name = match roots.iter().find_map(|root| path.strip_prefix(root).ok()) {
+            Some(relative) => relative.to_path_buf(),
+            None => {
+                if let Some(parent) = path.parent() {
+                    roots.push(parent.to_path_buf());
+                }
+                PathBuf::from(path.file_name().unwrap_or_default())
+            }
+        };
+        pending.push_back((name_of(&name), path.to_path_buf()));
+    }
+
+    let mut pool = DescriptorPool::global();
+    let mut seen = HashSet::new();
+    let mut parsed = Vec::new();
+    while let Some((name, path)) = pending.pop_front() {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        let source = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let file = parse_proto(&name, &source)?;
+        for import in &file.dependency {
+            // The well-known types are always available
+            if seen.contains(import) || pool.get_file_by_name(import).is_some() {
+                continue;
+            }
+            let found = roots
+                .iter()
+                .map(|root| root.join(import))
+                .find(|candidate| candidate.is_file())
+                .ok_or_else(|| anyhow!("Import {} of {} not found", import, name))?;
+            pending.push_back((import.clone(), found));
+        }
+        parsed.push(file);
+    }
+
+    pool.add_file_descriptor_protos(parsed)
+        .map_err(|e| anyhow!("Invalid proto files: {}", e))?;
+    Ok(pool)
+}
+
+/// Import names always use forward slashes.
+fn name_of(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Parses the source of the file `name`.
+pub fn parse_proto(name: &str, source: &str) -> Result<FileDescriptorProto> {
+    let tokens = tokenize(source).map_err(|e| anyhow!("{}:{}", name, e))?;
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        proto3: false,
+    };
+    let mut file = parser.file().map_err(|e| anyhow!("{}:{}", name, e))?;
+    file.name = Some(name.to_string());
+    Ok(file)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Int(u64),
+    Float(String),
+    Str(String),
+    Symbol(char),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "'{}'", word),
+            Token::Int(value) => write!(f, "'{}'", value),
+            Token::Float(value) => write!(f, "'{}'", value),
+            Token::Str(value) => write!(f, "\"{}\"", value),
+            Token::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+/// A token and the line it starts on.
+struct Spanned {
+    token: Token,
+    line: usize,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Spanned>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Spanned> = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    // Adjacent strings are concatenated
+    let mut after_string = false;
+    while i < chars.len() {
+        let c = chars[i];
+        let start_line = line;
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(format!("{}: unterminated comment", start_line));
+            }
+            i += 2;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '_' | '.'))
+            {
+                i += 1;
+            }
+            after_string = false;
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(Spanned {
+                token: Token::Word(word),
+                line,
+            });
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric()
+                    || chars[i] == '.'
+                    || (matches!(chars[i], '+' | '-') && matches!(chars[i - 1], 'e' | 'E')))
+            {
+                i += 1;
+            }
+            after_string = false;
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Spanned {
+                token: number(&text).ok_or_else(|| format!("{}: invalid number {}", line, text))?,
+                line,
+            });
+        } else if c == '"' || c == '\'' {
+            let (value, end) = string_literal(&chars, i).map_err(|e| format!("{}: {}", line, e))?;
+            i = end;
+            match tokens.last_mut() {
+                Some(Spanned {
+                    token: Token::Str(previous),
+                    ..
+                }) if after_string => previous.push_str(&value),
+                _ => tokens.push(Spanned {
+                    token: Token::Str(value),
+                    line,
+                }),
+            }
+            after_string = true;
+        } else {
+            after_string = false;
+            tokens.push(Spanned {
+                token: Token::Symbol(c),
+                line,
+            });
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+fn number(text: &str) -> Option<Token> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).ok().map(Token::Int);
+    }
+    if lower.contains(['.', 'e']) {
+        return lower
+            .parse::<f64>()
+            .ok()
+            .map(|_| Token::Float(text.to_string()));
+    }
+    if lower.len() > 1 && lower.starts_with('0') {
+        return u64::from_str_radix(&lower[1..], 8).ok().map(Token::Int);
+    }
+    lower.parse().ok().map(Token::Int)
+}
+
+/// Reads the string literal starting at `start`, returning its value and the
+/// index after its closing quote.
+fn string_literal(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let quote = chars[start];
+    let mut bytes = Vec::new();
+    let mut i = start + 1;
+    loop {
+        let c = *chars.get(i).ok_or("unterminated string")?;
+        i += 1;
+        match c {
+            '\n' => return Err("unterminated string".to_string()),
+            c if c == quote => break,
+            '\\' => {
+                let escaped = *chars.get(i).ok_or("unterminated string")?;
+                i += 1;
+                match escaped {
+                    'n' => bytes.push(b'\n'),
+                    'r' => bytes.push(b'\r'),
+                    't' => bytes.push(b'\t'),
+                    'a' => bytes.push(0x07),
+                    'b' => bytes.push(0x08),
+                    'f' => bytes.push(0x0c),
+                    'v' => bytes.push(0x0b),
+                    'x' | 'X' => {
+                        let digits: String = chars[i..]
+                            .iter()
+                            .take(2)
+                            .take_while(|c| c.is_ascii_hexdigit())
+                            .collect();
+                        i += digits.len();
+                        bytes.push(
+                            u8::from_str_radix(&digits, 16).map_err(|_| "invalid \\x escape")?,
+                        );
+                    }
+                    '0'..='7' => {
+                        let digits: String = chars[i - 1..]
+                            .iter()
+                            .take(3)
+                            .take_while(|c| matches!(c, '0'..='7'))
+                            .collect();
+                        i += digits.len() - 1;
+                        let value =
+                            u32::from_str_radix(&digits, 8).map_err(|_| "invalid escape")?;
+                        bytes.push(u8::try_from(value).map_err(|_| "invalid octal escape")?);
+                    }
+                    other => {
+                        let mut buffer = [0; 4];
+                        bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+                    }
+                }
+            }
+            c => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            }
+        }
+    }
+    let value = String::from_utf8(bytes).map_err(|_| "string is not valid UTF-8")?;
+    Ok((value, i))
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+    proto3: bool,
+}
+
+type ParseResult<T> = Result<T, String>;
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|spanned| &spanned.token)
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.tokens.get(self.position) {
+            Some(spanned) => format!(
+                "{}: expected {}, found {}",
+                spanned.line, expected, spanned.token
+            ),
+            None => {
+                let line = self.tokens.last().map_or(1, |spanned| spanned.line);
+                format!("{}: expected {}, found end of file", line, expected)
+            }
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token
+    }
+
+    fn is_symbol(&self, symbol: char) -> bool {
+        self.peek() == Some(&Token::Symbol(symbol))
+    }
+
+    fn is_word(&self, word: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w == word)
+    }
+
+    fn eat_symbol(&mut self, symbol: char) -> bool {
+        let matched = self.is_symbol(symbol);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let matched = self.is_word(word);
+        if matched {
+            self.position += 1;
+        }
+        matched
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> ParseResult<()> {
+        if self.eat_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("'{}'", symbol)))
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> ParseResult<()> {
+        if self.eat_word(word) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("'{}'", word)))
+        }
+    }
+
+    fn identifier(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(Token::Word(word)) => {
+                let word = word.clone();
+                self.position += 1;
+                Ok(word)
+            }
+            _ => Err(self.error("an identifier")),
+        }
+    }
+
+    /// A type reference, optionally fully qualified with a leading dot.
+    fn type_name(&mut self) -> ParseResult<String> {
+        let leading_dot = self.eat_symbol('.');
+        let name = self.identifier()?;
+        Ok(if leading_dot {
+            format!(".{}", name)
+        } else {
+            name
+        })
+    }
+
+    fn string(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(Token::Str(value)) => {
+                let value = value.clone();
+                self.position += 1;
+                Ok(value)
+            }
+            _ => Err(self.error("a string")),
+        }
+    }
+
+    fn integer(&mut self) -> ParseResult<i64> {
+        let negative = self.eat_symbol('-');
+        match self.peek() {
+            Some(Token::Int(value)) => {
+                let value = i64::try_from(*value).map_err(|_| self.error("a smaller number"))?;
+                self.position += 1;
+                Ok(if negative { -value } else { value })
+            }
+            _ => Err(self.error("an integer")),
+        }
+    }
+
+    fn field_number(&mut self) -> ParseResult<i32> {
+        let number = self.integer()?;
+        i32::try_from(number).map_err(|_| format!("field number {} is out of range", number))
+    }
+
+    fn file(&mut self) -> ParseResult<FileDescriptorProto> {
+        let mut file = FileDescriptorProto::default();
+        if self.eat_word("syntax") {
+            self.expect_symbol('=')?;
+            match self.string()?.as_str() {
+                "proto3" => self.proto3 = true,
+                "proto2" => {}
+                other => return Err(format!("unsupported syntax \"{}\"", other)),
+            }
+            self.expect_symbol(';')?;
+        } else if self.is_word("edition") {
+            return Err(self.error("a syntax declaration (editions aren't supported)"));
+        }
+        if self.proto3 {
+            file.syntax = Some("proto3".to_string());
+        }
+
+        while self.peek().is_some() {
+            if self.eat_symbol(';') {
+                continue;
+            }
+            let keyword = self.identifier()?;
+            match keyword.as_str() {
+                "package" => {
+                    file.package = Some(self.identifier()?);
+                    self.expect_symbol(';')?;
+                }
+                "import" => {
+                    let index = file.dependency.len() as i32;
+                    if self.eat_word("public") {
+                        file.public_dependency.push(index);
+                    } else if self.eat_word("weak") {
+                        file.weak_dependency.push(index);
+                    }
+                    file.dependency.push(self.string()?);
+                    self.expect_symbol(';')?;
+                }
+                "option" => self.skip_option()?,
+                "message" => file.message_type.push(self.message()?),
+                "enum" => file.enum_type.push(self.enumeration()?),
+                "service" => file.service.push(self.service()?),
+                "extend" => {
+                    self.type_name()?;
+                    self.skip_block()?;
+                }
+                _ => {
+                    self.position -= 1;
+                    return Err(self.error("a top-level definition"));
+                }
+            }
+        }
+        Ok(file)
+    }
+
+    /// Skips `name = value;` after the `option` keyword.
+    fn skip_option(&mut self) -> ParseResult<()> {
+        self.option_name()?;
+        self.expect_symbol('=')?;
+        self.skip_constant()?;
+        self.expect_symbol(';')
+    }
+
+    fn option_name(&mut self) -> ParseResult<String> {
+        let mut name = String::new();
+        loop {
+            if self.eat_symbol('(') {
+                name.push('(');
+                name.push_str(&self.type_name()?);
+                self.expect_symbol(')')?;
+                name.push(')');
+            } else {
+                name.push_str(&self.identifier()?);
+            }
+            // Extension options may be followed by `.field`
+            if !self.eat_symbol('.') {
+                return Ok(name);
+            }
+            name.push('.');
+        }
+    }
+
+    /// Reads a constant, returning its text. Aggregate values in braces are
+    /// skipped whole.
+    fn skip_constant(&mut self) -> ParseResult<String> {
+        if self.is_symbol('{') {
+            self.skip_block()?;
+            return Ok(String::new());
+        }
+        let negative = self.eat_symbol('-') || {
+            self.eat_symbol('+');
+            false
+        };
+        let text = match self.next() {
+            Some(Token::Word(word)) => word,
+            Some(Token::Int(value)) => value.to_string(),
+            Some(Token::Float(value)) => value,
+            Some(Token::Str(value)) => value,
+            _ => {
+                self.position -= 1;
+                return Err(self.error("a constant"));
+            }
+        };
+        Ok(if negative { format!("-{}", text) } else { text })
+    }
+
+    /// Skips a block delimited by braces, including nested ones.
+    fn skip_block(&mut self) -> ParseResult<()> {
+        self.expect_symbol('{')?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::Symbol('{')) => depth += 1,
+                Some(Token::Symbol('}')) => depth -= 1,
+                Some(_) => {}
+                None => return Err(self.error("'}'")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the block being read ends here, consuming its closing brace.
+    fn closes(&mut self) -> ParseResult<bool> {
+        if self.peek().is_none() {
+            return Err(self.error("'}'"));
+        }
+        Ok(self.eat_symbol('}'))
+    }
+
+    fn skip_statement(&mut self) -> ParseResult<()> {
+        while !self.eat_symbol(';') {
+            if self.next().is_none() {
+                return Err(self.error("';'"));
+            }
+        }
+        Ok(())
+    }
+
+    fn message(&mut self) -> ParseResult<DescriptorProto> {
+        let mut message = DescriptorProto {
+            name: Some(self.identifier()?),
+            ..Default::default()
+        };
+        // proto3 `optional` fields get synthetic oneofs after the real ones
+        let mut optional_fields = Vec::new();
+        self.expect_symbol('{')?;
+        while !self.closes()? {
+            if self.eat_symbol(';') {
+                continue;
+            }
+            if self.eat_word("message") {
+                message.nested_type.push(self.message()?);
+            } else if self.eat_word("enum") {
+                message.enum_type.push(self.enumeration()?);
+            } else if self.eat_word("option") {
+                self.skip_option()?;
+            } else if self.eat_word("reserved") || self.eat_word("extensions") {
+                self.skip_statement()?;
+            } else if self.eat_word("extend") {
+                self.type_name()?;
+                self.skip_block()?;
+            } else if self.eat_word("oneof") {
+                let index = message.oneof_decl.len() as i32;
+                message.oneof_decl.push(OneofDescriptorProto {
+                    name: Some(self.identifier()?),
+                    ..Default::default()
+                });
+                self.expect_symbol('{')?;
+                while !self.closes()? {
+                    if self.eat_symbol(';') {
+                        continue;
+                    }
+                    if self.eat_word("option") {
+                        self.skip_option()?;
+                        continue;
+                    }
+                    let mut field = self.field(Label::Optional)?;
+                    field.oneof_index = Some(index);
+                    message.field.push(field);
+                }
+            } else if self.eat_word("map") {
+                let field = self.map_field(&mut message)?;
+                message.field.push(field);
+            } else {
+                let label = if self.eat_word("repeated") {
+                    Label::Repeated
+                } else if self.eat_word("required") {
+                    Label::Required
+                } else if self.eat_word("optional") {
+                    if self.proto3 {
+                        optional_fields.push(message.field.len());
+                    }
+                    Label::Optional
+                } else if self.proto3 {
+                    Label::Optional
+                } else {
+                    return Err(self.error("a field label"));
+                };
+                let field = self.field(label)?;
+                message.field.push(field);
+            }
+        }
+
+        for index in optional_fields {
+            let field = &mut message.field[index];
+            field.proto3_optional = Some(true);
+            field.oneof_index = Some(message.oneof_decl.len() as i32);
+            message.oneof_decl.push(OneofDescriptorProto {
+                name: Some(format!("_{}", field.name())),
+                ..Default::default()
+            });
+        }
+        Ok(message)
+    }
+
+    /// `type name = number [options];` after the label.
+    fn field(&mut self, label: Label) -> ParseResult<FieldDescriptorProto> {
+        if self.is_word("group") {
+            return Err(self.error("a field type (groups aren't supported)"));
+        }
+        let type_name = self.type_name()?;
+        let mut field = FieldDescriptorProto {
+            name: Some(self.identifier()?),
+            ..Default::default()
+        };
+        field.set_label(label);
+        match scalar_type(&type_name) {
+            Some(scalar) => field.set_type(scalar),
+            // Resolved to a message or an enum by the descriptor pool
+            None => {
+                field.set_type(Type::Message);
+                field.type_name = Some(type_name);
+            }
+        }
+        self.expect_symbol('=')?;
+        field.number = Some(self.field_number()?);
+        self.field_options(&mut field)?;
+        self.expect_symbol(';')?;
+        Ok(field)
+    }
+
+    /// `map<key, value> name = number [options];` after `map`, adding the
+    /// entry message to `message`.
+    fn map_field(&mut self, message: &mut DescriptorProto) -> ParseResult<FieldDescriptorProto> {
+        self.expect_symbol('<')?;
+        let key_type = self.type_name()?;
+        self.expect_symbol(',')?;
+        let value_type = self.type_name()?;
+        self.expect_symbol('>')?;
+        let name = self.identifier()?;
+        self.expect_symbol('=')?;
+        let number = self.field_number()?;
+
+        let entry_name = format!("{}Entry", camel_case(&name));
+        let entry_field = |name: &str, number: i32, type_name: &str| {
+            let mut field = FieldDescriptorProto {
+                name: Some(name.to_string()),
+                number: Some(number),
+                ..Default::default()
+            };
+            field.set_label(Label::Optional);
+            match scalar_type(type_name) {
+                Some(scalar) => field.set_type(scalar),
+                None => {
+                    field.set_type(Type::Message);
+                    field.type_name = Some(type_name.to_string());
+                }
+            }
+            field
+        };
+        message.nested_type.push(DescriptorProto {
+            name: Some(entry_name.clone()),
+            field: vec![
+                entry_field("key", 1, &key_type),
+                entry_field("value", 2, &value_type),
+            ],
+            options: Some(MessageOptions {
+                map_entry: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+
+        let mut field = FieldDescriptorProto {
+            name: Some(name),
+            number: Some(number),
+            type_name: Some(entry_name),
+            ..Default::default()
+        };
+        field.set_label(Label::Repeated);
+        field.set_type(Type::Message);
+        self.field_options(&mut field)?;
+        self.expect_symbol(';')?;
+        Ok(field)
+    }
+
+    /// Reads `[name = value, ...]`, keeping the options that matter.
+    fn field_options(&mut self, field: &mut FieldDescriptorProto) -> ParseResult<()> {
+        if !self.eat_symbol('[') {
+            return Ok(());
+        }
+        loop {
+            let name = self.option_name()?;
+            self.expect_symbol('=')?;
+            let value = self.skip_constant()?;
+            match name.as_str() {
+                "default" => field.default_value = Some(value),
+                "json_name" => field.json_name = Some(value),
+                "packed" => {
+                    field
+                        .options
+                        .get_or_insert_with(FieldOptions::default)
+                        .packed = Some(value == "true");
+                }
+                _ => {}
+            }
+            if !self.eat_symbol(',') {
+                break;
+            }
+        }
+        self.expect_symbol(']')
+    }
+
+    fn enumeration(&mut self) -> ParseResult<EnumDescriptorProto> {
+        let mut enumeration = EnumDescriptorProto {
+            name: Some(self.identifier()?),
+            ..Default::default()
+        };
+        self.expect_symbol('{')?;
+        while !self.closes()? {
+            if self.eat_symbol(';') {
+                continue;
+            }
+            if self.eat_word("option") {
+                let name = self.option_name()?;
+                self.expect_symbol('=')?;
+                let value = self.skip_constant()?;
+                self.expect_symbol(';')?;
+                if name == "allow_alias" {
+                    enumeration
+                        .options
+                        .get_or_insert_with(EnumOptions::default)
+                        .allow_alias = Some(value == "true");
+                }
+                continue;
+            }
+            if self.eat_word("reserved") {
+                self.skip_statement()?;
+                continue;
+            }
+            let name = self.identifier()?;
+            self.expect_symbol('=')?;
+            let number = self.integer()?;
+            let number = i32::try_from(number)
+                .map_err(|_| format!("enum value {} is out of range", number))?;
+            if self.is_symbol('[') {
+                let mut ignored = FieldDescriptorProto::default();
+                self.field_options(&mut ignored)?;
+            }
+            self.expect_symbol(';')?;
+            enumeration.value.push(EnumValueDescriptorProto {
+                name: Some(name),
+                number: Some(number),
+                ..Default::default()
+            });
+        }
+        Ok(enumeration)
+    }
+
+    fn service(&mut self) -> ParseResult<ServiceDescriptorProto> {
+        let mut service = ServiceDescriptorProto {
+            name: Some(self.identifier()?),
+            ..Default::default()
+        };
+        self.expect_symbol('{')?;
+        while !self.closes()? {
+            if self.eat_symbol(';') {
+                continue;
+            }
+            if self.eat_word("option") {
+                self.skip_option()?;
+                continue;
+            }
+            self.expect_word("rpc")?;
+            let mut method = MethodDescriptorProto {
+                name: Some(self.identifier()?),
+                ..Default::default()
+            };
+            self.expect_symbol('(')?;
+            if self.eat_word("stream") {
+                method.client_streaming = Some(true);
+            }
+            method.input_type = Some(self.type_name()?);
+            self.expect_symbol(')')?;
+            self.expect_word("returns")?;
+            self.expect_symbol('(')?;
+            if self.eat_word("stream") {
+                method.server_streaming = Some(true);
+            }
+            method.output_type = Some(self.type_name()?);
+            self.expect_symbol(')')?;
+            if self.is_symbol('{') {
+                self.skip_block()?;
+            } else {
+                self.expect_symbol(';')?;
+            }
+            service.method.push(method);
+        }
+        Ok(service)
+    }
+}
+
+fn scalar_type(name: &str) -> Option<Type> {
+    Some(match name {
+        "double" => Type::Double,
+        "float" => Type::Float,
+        "int32" => Type::Int32,
+        "int64" => Type::Int64,
+        "uint32" => Type::Uint32,
+        "uint64" => Type::Uint64,
+        "sint32" => Type::Sint32,
+        "sint64" => Type::Sint64,
+        "fixed32" => Type::Fixed32,
+        "fixed64" => Type::Fixed64,
+        "sfixed32" => Type::Sfixed32,
+        "sfixed64" => Type::Sfixed64,
+        "bool" => Type::Bool,
+        "string" => Type::String,
+        "bytes" => Type::Bytes,
+        _ => return None,
+    })
+}
+
+/// `protoc`'s name for map entry messages: `tag_counts` becomes `TagCounts`.
+fn camel_case(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut upper_next = true;
+    for c in name.chars() {
+        if c == '_' {
+            upper_next = true;
+        } else if upper_next {
+            result.push(c.to_ascii_uppercase());
+            upper_next = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_reflect::{DynamicMessage, Kind};
+
+    const LIBRARY: &str = r#"
+        // A library of books
+        syntax = "proto3";
+        package library.v1;
+
+        import "google/protobuf/timestamp.proto";
+        option go_package = "example.com/library";
+
+        message Book {
+            string title = 1;
+            repeated string authors = 2 [json_name = "writers"];
+            Genre genre = 3;
+            map<string, int32> tag_counts = 4;
+            optional uint32 pages = 5;
+            oneof source {
+                string isbn = 6;
+                Shelf shelf = 7;
+            }
+            google.protobuf.Timestamp published = 8;
+            reserved 9, 10 to 12;
+            /* Nested types are found relative to the message */
+            message Shelf { int64 row = 1; }
+        }
+
+        enum Genre {
+            option allow_alias = true;
+            GENRE_UNSPECIFIED = 0;
+            FICTION = 1;
+            NOVEL = 1;
+        }
+
+        service Library {
+            rpc GetBook (Book) returns (Book) {
+                option (google.api.http) = { get: "/v1/{title=books/*}" };
+            }
+            rpc ListBooks (.library.v1.Book) returns (stream Book);
+            rpc Chat (stream Book) returns (stream Book);
+        }
+    "#;
+
+    fn write_temp(dir: &Path, name: &str, contents: &str) -> String {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_messages_enums_and_services() {
+        let dir = std::env::temp_dir().join(format!("relay-proto-{}", std::process::id()));
+        let file = write_temp(&dir, "library.proto", LIBRARY);
+        let pool = load_proto_files(&[file], &[]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let book = pool.get_message_by_name("library.v1.Book").unwrap();
+        let field = |name: &str| book.get_field_by_name(name).unwrap();
+        assert!(field("authors").is_list());
+        assert_eq!(field("authors").json_name(), "writers");
+        assert!(
+            matches!(field("genre").kind(), Kind::Enum(genre) if genre.full_name() == "library.v1.Genre")
+        );
+        assert!(field("tag_counts").is_map());
+        assert!(field("pages").supports_presence());
+        assert_eq!(field("shelf").containing_oneof().unwrap().name(), "source");
+        assert!(
+            matches!(field("shelf").kind(), Kind::Message(shelf) if shelf.full_name() == "library.v1.Book.Shelf")
+        );
+        assert!(
+            matches!(field("published").kind(), Kind::Message(ts) if ts.full_name() == "google.protobuf.Timestamp")
+        );
+
+        let service = pool.get_service_by_name("library.v1.Library").unwrap();
+        let streaming: Vec<_> = service
+            .methods()
+            .map(|method| {
+                (
+                    method.name().to_string(),
+                    method.is_client_streaming(),
+                    method.is_server_streaming(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            streaming,
+            [
+                ("GetBook".to_string(), false, false),
+                ("ListBooks".to_string(), false, true),
+                ("Chat".to_string(), true, true),
+            ]
+        );
+
+        // Messages built from the descriptors round-trip through JSON
+        let json = serde_json::json!({
+            "title": "Dune",
+            "writers": ["Frank Herbert"],
+            "genre": "NOVEL",
+            "tagCounts": {"classic": 2},
+            "pages": 0,
+            "shelf": {"row": "3"},
+            "published": "1965-08-01T00:00:00Z"
+        });
+        let message = DynamicMessage::deserialize(book, json).unwrap();
+        let encoded = serde_json::to_value(&message).unwrap();
+        assert_eq!(encoded["genre"], "FICTION");
+        assert_eq!(encoded["pages"], 0);
+        assert_eq!(encoded["shelf"]["row"], "3");
+        assert_eq!(encoded["published"], "1965-08-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_imports_from_import_paths() {
+        let dir = std::env::temp_dir().join(format!("relay-proto-imports-{}", std::process::id()));
+        write_temp(
+            &dir,
+            "common/money.proto",
+            "syntax = \"proto3\"; package common; message Money { string currency = 1; sint64 units = 2; }",
+        );
+        let file = write_temp(
+            &dir,
+            "shop/order.proto",
+            "syntax = \"proto3\";\npackage shop;\nimport public \"common/money.proto\";\n\
+             message Order { common.Money total = 1; }",
+        );
+        let root = dir.to_string_lossy().into_owned();
+        let pool = load_proto_files(std::slice::from_ref(&file), &[root]).unwrap();
+        let order = pool.get_file_by_name("shop/order.proto").unwrap();
+        assert_eq!(
+            order.dependencies().next().unwrap().name(),
+            "common/money.proto"
+        );
+
+        // Without the import path the dependency can't be found
+        let error = load_proto_files(&[file], &[]).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Import common/money.proto of order.proto not found"
+        );
+    }
+
+    #[test]
+    fn test_proto2_and_syntax_errors() {
+        let file = parse_proto(
+            "legacy.proto",
+            "syntax = 'proto2';\n\
+             message Legacy {\n\
+               required int32 id = 1;\n\
+               optional string name = 2 [default = \"unnamed\\x21\"];\n\
+               repeated int32 codes = 3 [packed = true];\n\
+               extensions 100 to max;\n\
+             }",
+        )
+        .unwrap();
+        let legacy = &file.message_type[0];
+        assert_eq!(legacy.field[0].label(), Label::Required);
+        assert_eq!(legacy.field[1].default_value(), "unnamed!");
+        assert_eq!(legacy.field[2].options.as_ref().unwrap().packed, Some(true));
+        assert_eq!(file.syntax, None);
+
+        let error = |source| parse_proto("broken.proto", source).unwrap_err().to_string();
+        assert_eq!(
+            error("syntax = \"proto3\";\nmessage A {\n  string name = ;\n}"),
+            "broken.proto:3: expected an integer, found ';'"
+        );
+        assert_eq!(
+            error("syntax = \"proto2\";\nmessage A { string name = 1; }"),
+            "broken.proto:2: expected a field label, found 'string'"
+        );
+        assert_eq!(
+            error("edition = \"2023\";"),
+            "broken.proto:1: expected a syntax declaration (editions aren't supported), found 'edition'"
+        );
+        assert_eq!(
+            error("syntax = \"proto3\";\nmessage A {"),
+            "broken.proto:2: expected '}', found end of file"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Redirect following, as browsers do it (the Fetch standard's "HTTP-redirect
+//! fetch").
+//!
+//! `301` and `302` turn a `POST` into a `GET`, `303` turns everything but
+//! `HEAD` into a `GET`, and `307` and `308` resend the request as it was.
+//! Credentials are only sent along while the redirects stay on the origin
+//! they were meant for.
+
+use crate::types::RedirectPolicy;
+use anyhow::{anyhow, Result};
+use hyper::header::{
+    HeaderMap, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LANGUAGE, CONTENT_LENGTH, CONTENT_LOCATION,
+    CONTENT_TYPE, COOKIE, LOCATION, PROXY_AUTHORIZATION,
+};
+use hyper::{Method, StatusCode};
+use url::Url;
+
+/// The method a redirect with `status` is followed with and whether the
+/// body is sent again, or `None` if `status` isn't a redirect to follow.
+pub fn redirected_method(status: StatusCode, method: &Method) -> Option<(Method, bool)> {
+    match status.as_u16() {
+        301 | 302 if method == Method::POST => Some((Method::GET, false)),
+        303 if method != Method::HEAD => Some((Method::GET, false)),
+        301 | 302 | 303 | 307 | 308 => Some((method.clone(), true)),
+        _ => None,
+    }
+}
+
+/// The `Location` of a redirect, resolved against the URL that was
+/// requested. Locations that don't parse or leave HTTP aren't followed.
+pub fn location(current: &Url, headers: &HeaderMap) -> Option<Url> {
+    let location = headers.get(LOCATION)?.to_str().ok()?;
+    let target = current.join(location).ok()?;
+    matches!(target.scheme(), "http" | "https").then_some(target)
+}
+
+pub fn same_origin(a: &Url, b: &Url) -> bool {
+    a.origin() == b.origin()
+}
+
+/// Whether `policy` allows following a redirect from `current` to `target`
+/// after `followed` others. Fails once the limit is reached.
+pub fn should_follow(
+    policy: RedirectPolicy,
+    current: &Url,
+    target: &Url,
+    followed: usize,
+) -> Result<bool> {
+    let max_redirects = match policy {
+        RedirectPolicy::Off => return Ok(false),
+        RedirectPolicy::SameOrigin { .. } if !same_origin(current, target) => return Ok(false),
+        RedirectPolicy::Follow { max_redirects } | RedirectPolicy::SameOrigin { max_redirects } => {
+            max_redirects
+        }
+    };
+    if followed >= max_redirects as usize {
+        return Err(anyhow!("Stopped after {} redirects", max_redirects));
+    }
+    Ok(true)
+}
+
+/// Removes the headers describing a body that is no longer sent.
+pub fn strip_body_headers(headers: &mut HeaderMap) {
+    for name in [
+        CONTENT_TYPE,
+        CONTENT_LENGTH,
+        CONTENT_ENCODING,
+        CONTENT_LANGUAGE,
+        CONTENT_LOCATION,
+    ] {
+        headers.remove(name);
+    }
+}
+
+/// Removes credentials meant for another origin.
+pub fn strip_credentials(headers: &mut HeaderMap) {
+    for name in [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE] {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_rewriting() {
+        let redirected = |status: u16, method: Method| {
+            redirected_method(StatusCode::from_u16(status).unwrap(), &method)
+        };
+        assert_eq!(redirected(301, Method::POST), Some((Method::GET, false)));
+        assert_eq!(redirected(302, Method::POST), Some((Method::GET, false)));
+        assert_eq!(redirected(302, Method::PUT), Some((Method::PUT, true)));
+        assert_eq!(redirected(303, Method::DELETE), Some((Method::GET, false)));
+        assert_eq!(redirected(303, Method::HEAD), Some((Method::HEAD, true)));
+        assert_eq!(redirected(307, Method::POST), Some((Method::POST, true)));
+        assert_eq!(redirected(308, Method::PATCH), Some((Method::PATCH, true)));
+        // Multiple choices and not modified aren't redirects to follow
+        assert_eq!(redirected(300, Method::GET), None);
+        assert_eq!(redirected(304, Method::GET), None);
+        assert_eq!(redirected(200, Method::GET), None);
+    }
+
+    #[test]
+    fn test_location_resolution() {
+        let current = Url::parse("https://example.com/a/b?x=1").unwrap();
+        let resolve = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(LOCATION, value.parse().unwrap());
+            location(&current, &headers).map(String::from)
+        };
+        assert_eq!(resolve("c").as_deref(), Some("https://example.com/a/c"));
+        assert_eq!(
+            resolve("/d?y=2").as_deref(),
+            Some("https://example.com/d?y=2")
+        );
+        assert_eq!(
+            resolve("//cdn.example.com/e").as_deref(),
+            Some("https://cdn.example.com/e")
+        );
+        assert_eq!(resolve("ftp://example.com/f"), None);
+        assert_eq!(resolve("http://[::1"), None);
+        assert_eq!(location(&current, &HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_policies() {
+        let origin = Url::parse("https://example.com/a").unwrap();
+        let same = Url::parse("https://example.com/b").unwrap();
+        let other = Url::parse("http://example.com/b").unwrap();
+        let follow = RedirectPolicy::Follow { max_redirects: 2 };
+        let same_origin_only = RedirectPolicy::SameOrigin { max_redirects: 2 };
+
+        assert!(!should_follow(RedirectPolicy::Off, &origin, &same, 0).unwrap());
+        assert!(should_follow(follow, &origin, &other, 1).unwrap());
+        assert_eq!(
+            should_follow(follow, &origin, &same, 2)
+                .unwrap_err()
+                .to_string(),
+            "Stopped after 2 redirects"
+        );
+        assert!(should_follow(same_origin_only, &origin, &same, 0).unwrap());
+        // A scheme change is another origin
+        assert!(!should_follow(same_origin_only, &origin, &other, 0).unwrap());
+    }
+}
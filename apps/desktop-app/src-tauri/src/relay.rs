@@ -1,142 +1,1128 @@
+use crate::auth::{apply_authorization, credential_headers};
+use crate::aws_sigv4::SigV4;
+use crate::charset::{decode_text, detect_charset, is_wide};
+use crate::content_encoding::{
+    decode_body, StreamDecoder, DEFAULT_DECODED_LIMIT, SUPPORTED_ENCODINGS,
+};
+use crate::cookies::CookieStore;
+use crate::digest_auth::{DigestChallenge, Qop};
+use crate::graphql::{self, GraphQLSchemas};
+use crate::grpc::{self, GrpcStatus, ResponseStream};
+use crate::grpc_reflection::load_from_reflection;
+use crate::in_flight::InFlightRegistry;
+use crate::mime_sniff::{contains_binary_data, is_binary, sniff_mime_type};
+use crate::oauth2::{BrowserOpener, OAuth2Tokens};
+use crate::proto::load_proto_files;
+use crate::proxy::{self, EnvironmentProxies, Proxy, ProxyError, ProxyKind};
+use crate::redirect;
+use crate::request_body::{BodySource, Progress, ProgressCallback, RelayBody};
+use crate::server_timing::{parse_server_timing, processing_time};
+use crate::sse::{EventStreamParser, DEFAULT_RECONNECTION_TIME};
+use crate::timeouts::{limit, with_timeout, ConnectTimeouts, TimeoutError, CONNECT_TIMEOUTS};
+use crate::timing::{
+    connect_error, ConnectPhases, ConnectionTimings, TimingConnector, TimingResolver,
+    TlsTimingConnector,
+};
+use crate::tls::{self, TlsError};
 use crate::types::*;
+use crate::websocket::{self, WebSocketSessions};
 use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use chrono::Utc;
-use http_body_util::{BodyExt, Full};
-use hyper::body::Bytes;
-use hyper::{Method, Request as HyperRequest, Uri};
-use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
-use hyper_util::client::legacy::{connect::HttpConnector, Client};
+use encoding_rs::UTF_8;
+use futures::future::Abortable;
+use futures::StreamExt;
+use http_body_util::BodyExt;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CACHE_CONTROL,
+    CONTENT_ENCODING, CONTENT_TYPE, COOKIE, HOST, PROXY_AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL, TE,
+};
+use hyper::{Method, Request as HyperRequest, Response as HyperResponse, StatusCode, Uri, Version};
+use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
+use prost::Message as _;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use rustls::{ClientConfig, RootCertStore};
 use std::collections::HashMap;
-use std::time::Instant;
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
 use url::Url;
 
+type RelayConnector = TlsTimingConnector;
+
+/// Default for how much of a streamed body is kept for the final response.
+pub const DEFAULT_RETAIN_LIMIT: usize = 10 * 1024 * 1024;
+
+/// Service-wide timeouts used when a request doesn't override them. Only
+/// connection setup is bounded by default so long-polling endpoints keep working.
+pub const DEFAULT_TIMEOUTS: TimeoutSettings = TimeoutSettings {
+    connect: Some(30_000),
+    tls_handshake: Some(30_000),
+    first_byte: None,
+    body_idle: None,
+    total: None,
+};
+
+/// Relays HTTP requests on behalf of the frontend.
+///
+/// The service is cheap to clone and all clones share one connection pool, so
+/// it can be handed to any number of concurrent requests without locking.
+#[derive(Clone)]
 pub struct RelayService {
-    client: Client<HttpsConnector<HttpConnector>, Full<Bytes>>,
+    tcp: TimingConnector,
+    /// The CAs trusted by the system, which workspaces can add to
+    roots: Arc<RootCertStore>,
+    direct: Clients,
+    /// Clients by route, created as requests take them
+    routes: Arc<Mutex<HashMap<Route, Clients>>>,
+    /// TLS settings by workspace, `None` holding the default workspace's
+    tls_settings: Arc<RwLock<HashMap<Option<String>, TlsSettings>>>,
+    default_proxy: Arc<RwLock<ProxySetting>>,
+    environment_proxies: Arc<EnvironmentProxies>,
+    in_flight: InFlightRegistry,
+    default_timeouts: Arc<RwLock<TimeoutSettings>>,
+    oauth2: OAuth2Tokens,
+    graphql_schemas: GraphQLSchemas,
+    cookies: CookieStore,
+    upload_progress: Option<ProgressCallback>,
+    websockets: WebSocketSessions,
+}
+
+/// How a request connects: directly or through a proxy, verifying the server
+/// as its workspace says and presenting its client certificate, if any.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Route {
+    proxy: Option<Proxy>,
+    tls: TlsSettings,
+    client_certificate: Option<ClientCertificate>,
+}
+
+/// The clients connecting by one route. Each has pools of its own, so
+/// connections are only reused for requests meant to take the same route.
+#[derive(Clone)]
+struct Clients {
+    http: Client<RelayConnector, RelayBody>,
+    /// Speaks HTTP/2 even without ALPN, for requests that require it
+    http2: Client<RelayConnector, RelayBody>,
+    /// Connects WebSocket upgrades, which offer nothing but HTTP/1.1
+    websocket: RelayConnector,
+}
+
+impl Clients {
+    fn new(tcp: &TimingConnector, mut tls_config: ClientConfig, proxy: Option<&Proxy>) -> Self {
+        // Advertise both HTTP/2 and HTTP/1.1 via ALPN
+        tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        // WebSocket upgrades only exist in HTTP/1.1, so they offer nothing else
+        let mut websocket_tls_config = tls_config.clone();
+        websocket_tls_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        // Only HTTP/1 requests can be forwarded by an HTTP proxy, the rest
+        // are tunnelled
+        let (forwarding, tunnelling) = match proxy {
+            Some(proxy) => (
+                tcp.with_proxy(proxy.clone(), true),
+                tcp.with_proxy(proxy.clone(), false),
+            ),
+            None => (tcp.clone(), tcp.clone()),
+        };
+        let tls_config = Arc::new(tls_config);
+        Self {
+            http: Client::builder(TokioExecutor::new())
+                .build(TlsTimingConnector::new(forwarding, tls_config.clone())),
+            http2: Client::builder(TokioExecutor::new())
+                .http2_only(true)
+                .build(TlsTimingConnector::new(tunnelling.clone(), tls_config)),
+            websocket: TlsTimingConnector::new(tunnelling, Arc::new(websocket_tls_config)),
+        }
+    }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
 }
 
 impl RelayService {
     pub fn new() -> Self {
-        // Build HTTPS connector with rustls
-        let https = HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .expect("Failed to load native root certificates")
-            .https_or_http()
-            .enable_http1()
-            .enable_http2()
-            .build();
+        Self::with_timeouts(DEFAULT_TIMEOUTS)
+    }
+
+    pub fn with_timeouts(default_timeouts: TimeoutSettings) -> Self {
+        // Build HTTPS connectors with rustls on top of the timed DNS + TCP
+        // connector, trusting the system's CAs unless a workspace says
+        // otherwise
+        let tcp = TimingConnector::new(TimingResolver::new());
+        let roots = tls::native_roots();
+        let tls_config = ClientConfig::builder()
+            .with_root_certificates(roots.clone())
+            .with_no_client_auth();
+
+        Self {
+            direct: Clients::new(&tcp, tls_config, None),
+            tcp,
+            roots: Arc::new(roots),
+            routes: Arc::new(Mutex::new(HashMap::new())),
+            tls_settings: Arc::new(RwLock::new(HashMap::new())),
+            default_proxy: Arc::new(RwLock::new(ProxySetting::Default)),
+            environment_proxies: Arc::new(EnvironmentProxies::from_env()),
+            in_flight: InFlightRegistry::default(),
+            default_timeouts: Arc::new(RwLock::new(default_timeouts)),
+            oauth2: OAuth2Tokens::default(),
+            graphql_schemas: GraphQLSchemas::default(),
+            cookies: CookieStore::default(),
+            upload_progress: None,
+            websockets: WebSocketSessions::default(),
+        }
+    }
+
+    /// Sets how OAuth 2.0 authorization code flows open the user's browser.
+    pub fn with_browser_opener(mut self, opener: BrowserOpener) -> Self {
+        self.oauth2 = self.oauth2.with_browser_opener(opener);
+        self
+    }
+
+    /// Replaces the proxies read from the environment, which the service-wide
+    /// proxy setting falls back to.
+    pub fn with_environment_proxies(mut self, proxies: EnvironmentProxies) -> Self {
+        self.environment_proxies = Arc::new(proxies);
+        self
+    }
+
+    /// Sets the cookie jars requests use, e.g. ones saved to disk.
+    pub fn with_cookie_store(mut self, cookies: CookieStore) -> Self {
+        self.cookies = cookies;
+        self
+    }
+
+    /// Sets where the upload progress of request bodies is reported.
+    pub fn with_upload_progress(mut self, callback: ProgressCallback) -> Self {
+        self.upload_progress = Some(callback);
+        self
+    }
+
+    /// Forgets all cached OAuth 2.0 tokens, so the next request obtains a
+    /// new one.
+    pub fn clear_oauth2_tokens(&self) {
+        self.oauth2.clear();
+    }
+
+    /// Forgets all cached GraphQL schemas.
+    pub fn clear_graphql_schemas(&self) {
+        self.graphql_schemas.clear();
+    }
+
+    pub fn cookies(&self) -> &CookieStore {
+        &self.cookies
+    }
+
+    pub fn default_timeouts(&self) -> TimeoutSettings {
+        *self.default_timeouts.read().unwrap()
+    }
+
+    /// Replaces the service-wide timeouts applied to requests that don't
+    /// override them.
+    pub fn set_default_timeouts(&self, timeouts: TimeoutSettings) {
+        *self.default_timeouts.write().unwrap() = timeouts;
+    }
+
+    pub fn default_proxy(&self) -> ProxySetting {
+        self.default_proxy.read().unwrap().clone()
+    }
+
+    /// Replaces the proxy used by requests that don't choose one.
+    pub fn set_default_proxy(&self, proxy: ProxySetting) -> Result<()> {
+        if let ProxySetting::Custom {
+            url,
+            username,
+            password,
+            ..
+        } = &proxy
+        {
+            Proxy::parse(url, username.as_deref(), password.as_deref())?;
+        }
+        *self.default_proxy.write().unwrap() = proxy;
+        Ok(())
+    }
+
+    /// The proxy a request to `url` goes through, if any.
+    fn proxy_for(&self, setting: &ProxySetting, url: &Url) -> Result<Option<Proxy>> {
+        proxy::select(
+            setting,
+            &self.default_proxy.read().unwrap(),
+            &self.environment_proxies,
+            url,
+        )
+    }
+
+    /// How servers are verified for requests of a workspace, or of the
+    /// default workspace.
+    pub fn tls_settings(&self, workspace_id: Option<&str>) -> TlsSettings {
+        self.tls_settings
+            .read()
+            .unwrap()
+            .get(&workspace_id.map(str::to_string))
+            .cloned()
+            .unwrap_or_default()
+    }
 
-        // Create hyper client with connection pooling
-        let client = Client::builder(TokioExecutor::new()).build(https);
+    /// Replaces how servers are verified for requests of a workspace. Its
+    /// open connections are closed, so CA files are read again even if the
+    /// settings didn't change.
+    pub fn set_tls_settings(
+        &self,
+        workspace_id: Option<&str>,
+        settings: TlsSettings,
+    ) -> Result<()> {
+        tls::client_config(&self.roots, &settings, None)?;
+        let workspace_id = workspace_id.map(str::to_string);
+        let previous = if settings == TlsSettings::default() {
+            self.tls_settings.write().unwrap().remove(&workspace_id)
+        } else {
+            self.tls_settings
+                .write()
+                .unwrap()
+                .insert(workspace_id, settings)
+        };
+        if let Some(previous) = previous {
+            self.routes
+                .lock()
+                .unwrap()
+                .retain(|route, _| route.tls != previous);
+        }
+        Ok(())
+    }
 
-        Self { client }
+    /// The clients connecting through `proxy`, or directly, as the
+    /// workspace's TLS settings say. Certificate files are read when a route
+    /// is first taken.
+    fn clients(
+        &self,
+        proxy: Option<&Proxy>,
+        workspace_id: Option<&str>,
+        client_certificate: Option<&ClientCertificate>,
+    ) -> Result<Clients> {
+        let tls = self.tls_settings(workspace_id);
+        if proxy.is_none() && tls == TlsSettings::default() && client_certificate.is_none() {
+            return Ok(self.direct.clone());
+        }
+        let route = Route {
+            proxy: proxy.cloned(),
+            tls,
+            client_certificate: client_certificate.cloned(),
+        };
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(clients) = routes.get(&route) {
+            return Ok(clients.clone());
+        }
+        let tls_config =
+            tls::client_config(&self.roots, &route.tls, route.client_certificate.as_ref())?;
+        let clients = Clients::new(&self.tcp, tls_config, proxy);
+        routes.insert(route, clients.clone());
+        Ok(clients)
     }
 
     pub async fn relay_http_request(&self, request: Request) -> Result<RelayResponse> {
+        self.relay(request, None).await
+    }
+
+    /// Relays a request, pushing its headers and body chunks to `events` as
+    /// they arrive. Chunks are decoded as they stream in unless `rawBody` is
+    /// set. Reading pauses while `events` is full, so a slow receiver slows
+    /// the download instead of buffering it. At most `retainLimit` body bytes
+    /// are kept for the returned response.
+    pub async fn relay_http_request_stream(
+        &self,
+        request: Request,
+        events: mpsc::Sender<StreamEvent>,
+    ) -> Result<RelayResponse> {
+        self.relay(request, Some(&events)).await
+    }
+
+    async fn relay(
+        &self,
+        request: Request,
+        events: Option<&mpsc::Sender<StreamEvent>>,
+    ) -> Result<RelayResponse> {
         let client_start_time = Instant::now();
         let client_timestamp = Utc::now().timestamp_millis();
-
-        match self
-            .execute_request(request, client_start_time, client_timestamp)
+        let id = request.id.clone();
+
+        // Bound the whole request by the overall deadline
+        let timeouts = request.timeouts.or(&self.default_timeouts());
+        let execution = async {
+            with_timeout(
+                limit(timeouts.total),
+                RelayErrorKind::TotalTimeout,
+                self.execute_request(
+                    request,
+                    timeouts,
+                    client_start_time,
+                    client_timestamp,
+                    events,
+                ),
+            )
             .await
-        {
+            .unwrap_or_else(|timeout| Err(timeout.into()))
+        };
+
+        let Some(result) = self.cancellable(id, execution).await else {
+            return Ok(Self::cancelled_response());
+        };
+
+        match result {
             Ok(response) => Ok(RelayResponse {
                 status: "success".to_string(),
                 response: Some(response),
                 message: None,
+                error_kind: None,
                 timestamp: Utc::now().to_rfc3339(),
             }),
-            Err(e) => Ok(RelayResponse {
-                status: "error".to_string(),
+            Err(e) => Ok(Self::error_response(e)),
+        }
+    }
+
+    /// Relays a GraphQL operation, splitting the response into `data` and
+    /// `errors`. Failed operations often come with a `200 OK`, so the status
+    /// alone doesn't tell whether one succeeded.
+    pub async fn relay_graphql_request(
+        &self,
+        request: GraphQLRequest,
+    ) -> Result<GraphQLRelayResponse> {
+        let relayed = match graphql::http_request(&request) {
+            Ok(http) => self.relay(http, None).await?,
+            Err(e) => Self::error_response(e),
+        };
+        Ok(GraphQLRelayResponse {
+            graphql: relayed
+                .response
+                .as_ref()
+                .and_then(|response| graphql::parse_result(&response.content)),
+            status: relayed.status,
+            response: relayed.response,
+            message: relayed.message,
+            error_kind: relayed.error_kind,
+            timestamp: relayed.timestamp,
+        })
+    }
+
+    /// Fetches the schema of the endpoint `request` is sent to with the
+    /// introspection query, or returns the cached one unless `refresh` is
+    /// set. The request's query is ignored.
+    pub async fn introspect_graphql_schema(
+        &self,
+        request: GraphQLRequest,
+        refresh: bool,
+    ) -> Result<GraphQLSchema> {
+        if !refresh {
+            if let Some(schema) = self.graphql_schemas.get(&request.url) {
+                return Ok(schema);
+            }
+        }
+
+        let relayed = self
+            .relay_graphql_request(graphql::introspection_request(&request))
+            .await?;
+        if relayed.status != "success" {
+            return Err(anyhow!(
+                "{}",
+                relayed
+                    .message
+                    .unwrap_or_else(|| "Introspection failed".to_string())
+            ));
+        }
+        let status_code = relayed.response.map_or(0, |response| response.status_code);
+        let Some(result) = relayed.graphql else {
+            return Err(anyhow!(
+                "Introspection failed: not a GraphQL response (status {})",
+                status_code
+            ));
+        };
+        if let Some(error) = result.errors.first() {
+            return Err(anyhow!("Introspection failed: {}", error.message));
+        }
+        let schema = result
+            .data
+            .and_then(|mut data| data.get_mut("__schema").map(serde_json::Value::take))
+            .filter(|schema| schema.is_object())
+            .ok_or_else(|| anyhow!("Introspection failed: the response has no schema"))?;
+
+        let schema = GraphQLSchema {
+            url: request.url,
+            schema,
+            fetched_at: Utc::now().to_rfc3339(),
+        };
+        self.graphql_schemas.insert(schema.clone());
+        Ok(schema)
+    }
+
+    /// Opens a Server-Sent Events stream and pushes its events to `events`
+    /// until the request is cancelled, the server ends the stream with
+    /// `204 No Content` or the stream fails. Lost connections are reopened
+    /// after the reconnection time, sending the last event ID as
+    /// `Last-Event-ID`. Streams are meant to stay open, so the total timeout
+    /// doesn't apply.
+    pub async fn relay_event_stream(
+        &self,
+        request: Request,
+        events: mpsc::Sender<EventStreamEvent>,
+    ) -> Result<RelayResponse> {
+        let id = request.id.clone();
+        let streaming = self.run_event_stream(request, &events);
+        let Some(result) = self.cancellable(id, streaming).await else {
+            return Ok(Self::cancelled_response());
+        };
+
+        match result {
+            Ok(()) => Ok(RelayResponse {
+                status: "success".to_string(),
                 response: None,
-                message: Some(e.to_string()),
+                message: Some("Event stream closed by the server".to_string()),
+                error_kind: None,
                 timestamp: Utc::now().to_rfc3339(),
             }),
+            Err(e) => Ok(Self::error_response(e)),
         }
     }
 
-    async fn execute_request(
+    async fn run_event_stream(
         &self,
         request: Request,
-        client_start_time: Instant,
-        client_timestamp: i64,
-    ) -> Result<Response> {
-        // Parse URL and add query parameters
-        let mut parsed_url =
-            Url::parse(&request.url).map_err(|e| anyhow!("Invalid URL: {}", e))?;
+        events: &mpsc::Sender<EventStreamEvent>,
+    ) -> Result<()> {
+        let timeouts = request.timeouts.or(&self.default_timeouts());
+        let mut parser = EventStreamParser::default();
+        let mut reconnection_time = DEFAULT_RECONNECTION_TIME;
+
+        loop {
+            // Each connection is prepared afresh, e.g. with a new signature
+            let mut attempt = request.clone();
+            let mut outgoing = self
+                .prepare_request(
+                    &mut attempt,
+                    &timeouts,
+                    Utc::now().timestamp_millis(),
+                    false,
+                )
+                .await?;
+            outgoing
+                .headers
+                .entry(ACCEPT)
+                .or_insert(HeaderValue::from_static("text/event-stream"));
+            outgoing
+                .headers
+                .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+            if !parser.last_event_id().is_empty() {
+                outgoing.headers.insert(
+                    HeaderName::from_static("last-event-id"),
+                    HeaderValue::from_str(parser.last_event_id())?,
+                );
+            }
 
-        // Add query parameters
-        for (key, value) in request.params {
-            let value_str = match value {
-                serde_json::Value::String(s) => s,
-                serde_json::Value::Number(n) => n.to_string(),
-                serde_json::Value::Bool(b) => b.to_string(),
-                _ => value.to_string(),
+            let reason = match self.send(&outgoing, &timeouts).await {
+                Ok(response) => {
+                    let status = response.status();
+                    if status == StatusCode::NO_CONTENT {
+                        return Ok(());
+                    }
+                    if status != StatusCode::OK {
+                        return Err(anyhow!(
+                            "Event stream failed with status {}",
+                            status.as_u16()
+                        ));
+                    }
+                    let content_type = response
+                        .headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|ct| ct.to_str().ok())
+                        .unwrap_or_default();
+                    let essence = content_type.split(';').next().unwrap_or_default().trim();
+                    if !essence.eq_ignore_ascii_case("text/event-stream") {
+                        return Err(anyhow!(
+                            "Expected a text/event-stream response, got {}",
+                            if content_type.is_empty() {
+                                "none"
+                            } else {
+                                content_type
+                            }
+                        ));
+                    }
+
+                    send_event(
+                        events,
+                        EventStreamEvent::Open {
+                            status_code: status.as_u16(),
+                            headers: header_schemas(response.headers()),
+                            timestamp: Utc::now().timestamp_millis(),
+                        },
+                    )
+                    .await?;
+
+                    let mut body = response.into_body();
+                    let idle = limit(timeouts.body_idle);
+                    loop {
+                        let frame =
+                            match with_timeout(idle, RelayErrorKind::BodyIdleTimeout, body.frame())
+                                .await
+                            {
+                                Err(timeout) => break timeout.to_string(),
+                                Ok(None) => break "Stream ended".to_string(),
+                                Ok(Some(Err(e))) => {
+                                    break format!("Failed to read event stream: {}", e)
+                                }
+                                Ok(Some(Ok(frame))) => frame,
+                            };
+                        let Some(data) = frame.data_ref() else {
+                            continue;
+                        };
+                        for event in parser.feed(data) {
+                            send_event(
+                                events,
+                                EventStreamEvent::Message {
+                                    id: event.id,
+                                    event: event.event,
+                                    data: event.data,
+                                    timestamp: Utc::now().timestamp_millis(),
+                                },
+                            )
+                            .await?;
+                        }
+                        if let Some(retry) = parser.take_retry() {
+                            reconnection_time = retry;
+                        }
+                    }
+                }
+                Err(e) => e.to_string(),
             };
-            parsed_url.query_pairs_mut().append_pair(&key, &value_str);
+
+            // A partially received event is dropped with the connection
+            parser = EventStreamParser::resume(parser.last_event_id().to_string());
+            send_event(
+                events,
+                EventStreamEvent::Reconnecting {
+                    reason,
+                    delay: reconnection_time,
+                    last_event_id: parser.last_event_id().to_string(),
+                    timestamp: Utc::now().timestamp_millis(),
+                },
+            )
+            .await?;
+            tokio::time::sleep(Duration::from_millis(reconnection_time)).await;
         }
+    }
 
-        // NOTE: For truly accurate DNS/TCP/TLS timing, we would need lower-level instrumentation
-        // The current implementation measures TTFB which includes connection + processing
+    /// Opens a WebSocket session and pushes the frames it receives to
+    /// `events` until the session closes, fails or is cancelled. While open,
+    /// frames can be sent on it by the request id, which is required.
+    pub async fn open_websocket(
+        &self,
+        request: Request,
+        events: mpsc::Sender<WebSocketEvent>,
+    ) -> Result<RelayResponse> {
+        let Some(id) = request.id.clone() else {
+            return Ok(Self::error_response(anyhow!(
+                "WebSocket sessions need a request id"
+            )));
+        };
+        let session = self.run_websocket(id.clone(), request, &events);
+        let Some(result) = self.cancellable(Some(id), session).await else {
+            return Ok(Self::cancelled_response());
+        };
 
-        // Convert method
-        let method = match request.method {
-            RequestMethod::GET => Method::GET,
-            RequestMethod::POST => Method::POST,
-            RequestMethod::PUT => Method::PUT,
-            RequestMethod::DELETE => Method::DELETE,
-            RequestMethod::PATCH => Method::PATCH,
-            RequestMethod::OPTIONS => Method::OPTIONS,
-            RequestMethod::HEAD => Method::HEAD,
+        match result {
+            Ok(()) => Ok(RelayResponse {
+                status: "success".to_string(),
+                response: None,
+                message: Some("WebSocket session closed".to_string()),
+                error_kind: None,
+                timestamp: Utc::now().to_rfc3339(),
+            }),
+            Err(e) => Ok(Self::error_response(e)),
+        }
+    }
+
+    async fn run_websocket(
+        &self,
+        id: String,
+        mut request: Request,
+        events: &mpsc::Sender<WebSocketEvent>,
+    ) -> Result<()> {
+        let timeouts = request.timeouts.or(&self.default_timeouts());
+        let mut outgoing = self
+            .prepare_request(
+                &mut request,
+                &timeouts,
+                Utc::now().timestamp_millis(),
+                false,
+            )
+            .await?;
+        let url = Url::parse(&outgoing.uri.to_string())?;
+        self.add_cookies(
+            &url,
+            outgoing.workspace_id.as_deref(),
+            &mut outgoing.headers,
+        );
+        let proxy = self.proxy_for(&outgoing.proxy, &url)?;
+
+        let connecting = CONNECT_TIMEOUTS.scope(
+            ConnectTimeouts::from(&timeouts),
+            websocket::connect(
+                self.clients(
+                    proxy.as_ref(),
+                    outgoing.workspace_id.as_deref(),
+                    outgoing.client_certificate.as_ref(),
+                )?
+                .websocket,
+                &outgoing.uri,
+                &outgoing.headers,
+            ),
+        );
+        let (socket, response) = with_timeout(
+            limit(timeouts.first_byte),
+            RelayErrorKind::FirstByteTimeout,
+            connecting,
+        )
+        .await??;
+        self.store_cookies(&url, outgoing.workspace_id.as_deref(), response.headers());
+
+        send_event(
+            events,
+            WebSocketEvent::Open {
+                status_code: response.status().as_u16(),
+                headers: header_schemas(response.headers()),
+                protocol: response
+                    .headers()
+                    .get(SEC_WEBSOCKET_PROTOCOL)
+                    .and_then(|protocol| protocol.to_str().ok())
+                    .map(str::to_string),
+                timestamp: Utc::now().timestamp_millis(),
+            },
+        )
+        .await?;
+
+        let (sink, mut stream) = socket.split();
+        let _session = self.websockets.register(id, sink);
+        // Pings are answered while reading; the stream ends after the
+        // closing handshake
+        while let Some(message) = stream.next().await {
+            let message = message.map_err(|e| anyhow!("WebSocket connection failed: {}", e))?;
+            if let Some(event) = websocket::incoming_event(message) {
+                send_event(events, event).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a text, binary or ping frame on an open WebSocket session.
+    pub async fn send_websocket_message(&self, id: &str, message: WebSocketMessage) -> Result<()> {
+        let frame = websocket::outgoing_frame(message)?;
+        self.websockets.send(id, frame).await
+    }
+
+    /// Starts the closing handshake of an open WebSocket session. The session
+    /// ends once the server answers.
+    pub async fn close_websocket(&self, id: &str, code: Option<u16>, reason: &str) -> Result<()> {
+        let frame = websocket::close_frame(code, reason)?;
+        self.websockets.send(id, frame).await
+    }
+
+    /// Makes a gRPC call and reports its metadata and response messages to
+    /// `events` as they arrive. Request messages are all sent up front, so
+    /// client-streaming and bidirectional calls send them in one go. A call
+    /// ending with a status other than `OK` still succeeds, with the status in
+    /// the response.
+    pub async fn relay_grpc_request(
+        &self,
+        request: GrpcRequest,
+        events: mpsc::Sender<GrpcEvent>,
+    ) -> Result<GrpcRelayResponse> {
+        let id = request.id.clone();
+        let timeouts = request.timeouts.or(&self.default_timeouts());
+        let execution = async {
+            with_timeout(
+                limit(timeouts.total),
+                RelayErrorKind::TotalTimeout,
+                self.execute_grpc(&request, &timeouts, &events),
+            )
+            .await
+            .unwrap_or_else(|timeout| Err(timeout.into()))
         };
 
-        // Build hyper request
-        let uri: Uri = parsed_url.as_str().parse()?;
-        let mut hyper_req_builder = HyperRequest::builder().method(method).uri(&uri);
+        let outcome = match self.cancellable(id, execution).await {
+            Some(Ok(response)) => {
+                return Ok(GrpcRelayResponse {
+                    status: "success".to_string(),
+                    response: Some(response),
+                    message: None,
+                    error_kind: None,
+                    timestamp: Utc::now().to_rfc3339(),
+                })
+            }
+            Some(Err(e)) => Self::error_response(e),
+            None => Self::cancelled_response(),
+        };
+        Ok(GrpcRelayResponse {
+            status: outcome.status,
+            response: None,
+            message: outcome.message,
+            error_kind: outcome.error_kind,
+            timestamp: outcome.timestamp,
+        })
+    }
 
-        // Add headers
-        for (key, value) in request.headers {
-            if let serde_json::Value::String(header_value) = value {
-                hyper_req_builder = hyper_req_builder.header(key.as_str(), header_value);
+    /// Lists the services of a gRPC server with a request template for each
+    /// method, from `.proto` files or the server's reflection service.
+    pub async fn describe_grpc_services(
+        &self,
+        request: GrpcRequest,
+    ) -> Result<Vec<GrpcServiceSchema>> {
+        let timeouts = request.timeouts.or(&self.default_timeouts());
+        let pool = self.grpc_schema(&request, &timeouts).await?;
+        grpc::describe_services(&pool)
+    }
+
+    async fn execute_grpc(
+        &self,
+        request: &GrpcRequest,
+        timeouts: &TimeoutSettings,
+        events: &mpsc::Sender<GrpcEvent>,
+    ) -> Result<GrpcResponse> {
+        let pool = self.grpc_schema(request, timeouts).await?;
+        let service = pool
+            .get_service_by_name(&request.service)
+            .ok_or_else(|| anyhow!("Unknown gRPC service {}", request.service))?;
+        let method = service
+            .methods()
+            .find(|method| method.name() == request.method)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown gRPC method {} of {}",
+                    request.method,
+                    request.service
+                )
+            })?;
+        if !method.is_client_streaming() && request.messages.len() != 1 {
+            return Err(anyhow!(
+                "{} takes exactly one request message, got {}",
+                method.name(),
+                request.messages.len()
+            ));
+        }
+        let messages = request
+            .messages
+            .iter()
+            .map(|message| {
+                DynamicMessage::deserialize(method.input(), message)
+                    .map(|message| message.encode_to_vec())
+                    .map_err(|e| anyhow!("Invalid {} message: {}", method.input().full_name(), e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let start = Instant::now();
+        let path = format!("{}/{}", service.full_name(), method.name());
+        let mut stream = self.start_grpc(request, &path, &messages, timeouts).await?;
+        send_event(
+            events,
+            GrpcEvent::Headers {
+                headers: header_schemas(stream.headers()),
+                timestamp: Utc::now().timestamp_millis(),
+            },
+        )
+        .await?;
+
+        let mut responses = Vec::new();
+        while let Some(payload) = stream.message().await? {
+            let message = DynamicMessage::decode(method.output(), payload).map_err(|e| {
+                anyhow!(
+                    "Invalid {} message from the server: {}",
+                    method.output().full_name(),
+                    e
+                )
+            })?;
+            let data = serde_json::to_value(&message)?;
+            send_event(
+                events,
+                GrpcEvent::Message {
+                    data: data.clone(),
+                    timestamp: Utc::now().timestamp_millis(),
+                },
+            )
+            .await?;
+            responses.push(data);
+        }
+
+        let status = grpc_status(&stream)?;
+        Ok(GrpcResponse {
+            status_code: stream.status_code().as_u16(),
+            grpc_status: status.code,
+            grpc_status_name: grpc::status_name(status.code),
+            grpc_message: status.message,
+            headers: header_schemas(stream.headers()),
+            trailers: header_schemas(stream.trailers()),
+            messages: responses,
+            duration: as_millis(start.elapsed()),
+        })
+    }
+
+    async fn grpc_schema(
+        &self,
+        request: &GrpcRequest,
+        timeouts: &TimeoutSettings,
+    ) -> Result<DescriptorPool> {
+        match &request.schema {
+            GrpcSchemaSource::ProtoFiles {
+                files,
+                import_paths,
+            } => load_proto_files(files, import_paths),
+            GrpcSchemaSource::Reflection => {
+                load_from_reflection(|path, message| {
+                    self.grpc_unary(request, path, message, timeouts)
+                })
+                .await
             }
         }
+    }
 
-        // Add client timestamp header
-        hyper_req_builder =
-            hyper_req_builder.header("X-Client-Timestamp", client_timestamp.to_string());
+    /// Makes a call with one encoded message and collects the encoded
+    /// responses, failing with its [`GrpcStatus`] unless it ends with `OK`.
+    async fn grpc_unary(
+        &self,
+        request: &GrpcRequest,
+        path: &str,
+        message: Vec<u8>,
+        timeouts: &TimeoutSettings,
+    ) -> Result<Vec<Bytes>> {
+        let mut stream = self.start_grpc(request, path, &[message], timeouts).await?;
+        let mut responses = Vec::new();
+        while let Some(payload) = stream.message().await? {
+            responses.push(payload);
+        }
+        let status = grpc_status(&stream)?;
+        if status.code != grpc::OK {
+            return Err(status.into());
+        }
+        Ok(responses)
+    }
 
-        // Build request body
-        let methods_without_body = ["GET", "HEAD", "OPTIONS"];
-        let method_str = format!("{:?}", request.method);
+    /// Sends a call of the method at `path` (`package.Service/Method`) over
+    /// HTTP/2 and waits for the response headers.
+    async fn start_grpc(
+        &self,
+        request: &GrpcRequest,
+        path: &str,
+        messages: &[Vec<u8>],
+        timeouts: &TimeoutSettings,
+    ) -> Result<ResponseStream> {
+        let mut call = Request {
+            url: format!("{}/{}", request.url.trim_end_matches('/'), path),
+            method: RequestMethod::POST,
+            headers: request.metadata.clone(),
+            id: request.id.clone(),
+            timeouts: request.timeouts,
+            authorization: request.authorization.clone(),
+            ..Default::default()
+        };
+        let mut outgoing = self
+            .prepare_request(&mut call, timeouts, Utc::now().timestamp_millis(), false)
+            .await?;
+        // Plain `http` servers are spoken to with HTTP/2 prior knowledge
+        outgoing.version = Version::HTTP_2;
+        outgoing.body = BodySource::Bytes(grpc::encode_frames(messages));
+        outgoing
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+        outgoing
+            .headers
+            .insert(TE, HeaderValue::from_static("trailers"));
+        outgoing.headers.insert(
+            HeaderName::from_static("grpc-accept-encoding"),
+            HeaderValue::from_static("gzip"),
+        );
+        if let Some(total) = limit(timeouts.total) {
+            outgoing.headers.insert(
+                HeaderName::from_static("grpc-timeout"),
+                HeaderValue::from_str(&grpc::timeout_header(total.as_millis() as u64))?,
+            );
+        }
 
-        let hyper_req = if !methods_without_body.contains(&method_str.as_str()) {
-            if let Some(content) = request.body.content {
-                if !content.is_empty() {
-                    // Set content-type if provided
-                    let mut builder = hyper_req_builder;
-                    if let Some(content_type) = request.body.content_type {
-                        builder = builder.header("content-type", content_type);
-                    }
-                    builder.body(Full::new(Bytes::from(content)))?
-                } else {
-                    hyper_req_builder.body(Full::new(Bytes::new()))?
-                }
-            } else {
-                hyper_req_builder.body(Full::new(Bytes::new()))?
+        let response = self.send(&outgoing, timeouts).await?;
+        Ok(ResponseStream::new(response, limit(timeouts.body_idle)))
+    }
+
+    /// Runs `execution`, resolving to `None` if the request is cancelled
+    /// first. Only requests with an id can be cancelled.
+    async fn cancellable<T>(
+        &self,
+        id: Option<String>,
+        execution: impl Future<Output = T>,
+    ) -> Option<T> {
+        match id {
+            Some(id) => {
+                let (registration, _guard) = self.in_flight.register(id);
+                Abortable::new(execution, registration).await.ok()
             }
+            None => Some(execution.await),
+        }
+    }
+
+    /// Cancels the in-flight request with the given id. The cancelled
+    /// `relay_http_request` call resolves with a "cancelled" status.
+    pub fn cancel_request(&self, id: &str) -> RelayResponse {
+        if self.in_flight.cancel(id) {
+            Self::cancelled_response()
         } else {
-            hyper_req_builder.body(Full::new(Bytes::new()))?
-        };
+            RelayResponse {
+                status: "error".to_string(),
+                response: None,
+                message: Some(format!("No in-flight request with id {}", id)),
+                error_kind: None,
+                timestamp: Utc::now().to_rfc3339(),
+            }
+        }
+    }
+
+    fn error_response(error: anyhow::Error) -> RelayResponse {
+        RelayResponse {
+            status: "error".to_string(),
+            response: None,
+            message: Some(error.to_string()),
+            error_kind: if let Some(timeout) = error.downcast_ref::<TimeoutError>() {
+                Some(timeout.kind)
+            } else if error.downcast_ref::<ProxyError>().is_some() {
+                Some(RelayErrorKind::Proxy)
+            } else {
+                error.downcast_ref::<TlsError>().map(|tls| tls.kind)
+            },
+            timestamp: Utc::now().to_rfc3339(),
+        }
+    }
+
+    fn cancelled_response() -> RelayResponse {
+        RelayResponse {
+            status: "cancelled".to_string(),
+            response: None,
+            message: Some("Request cancelled".to_string()),
+            error_kind: None,
+            timestamp: Utc::now().to_rfc3339(),
+        }
+    }
+
+    async fn execute_request(
+        &self,
+        mut request: Request,
+        timeouts: TimeoutSettings,
+        client_start_time: Instant,
+        client_timestamp: i64,
+        events: Option<&mpsc::Sender<StreamEvent>>,
+    ) -> Result<Response> {
+        let mut outgoing = self
+            .prepare_request(&mut request, &timeouts, client_timestamp, true)
+            .await?;
+
+        let mut auth_exchanges = Vec::new();
 
         // Mark request send time (just before making the request)
-        let request_send_time = Instant::now();
+        let mut request_send_time = Instant::now();
+        let mut response = self.send(&outgoing, &timeouts).await?;
+        let mut connect_phases = claim_connect_phases(&response);
+
+        // Digest credentials are computed from the server's challenge and sent
+        // with a second request
+        let mut digest_challenge = None;
+        if let Authorization::Digest { username, password } = &request.authorization {
+            let challenge = (response.status() == StatusCode::UNAUTHORIZED)
+                .then(|| DigestChallenge::from_headers(response.headers()))
+                .flatten();
+            if let Some(challenge) = challenge {
+                let (parts, challenge_body) = response.into_parts();
+                read_body(challenge_body, limit(timeouts.body_idle)).await?;
+                auth_exchanges.push(outgoing.exchange(
+                    parts.status.as_u16(),
+                    header_schemas(&parts.headers),
+                    as_millis(request_send_time.elapsed()),
+                ));
+
+                outgoing
+                    .answer_digest(&challenge, username, password)
+                    .await?;
+                digest_challenge = Some(challenge);
+
+                request_send_time = Instant::now();
+                response = self.send(&outgoing, &timeouts).await?;
+                connect_phases = claim_connect_phases(&response).or(connect_phases);
+            }
+        }
 
-        // Execute request
-        let response = self
-            .client
-            .request(hyper_req)
-            .await
-            .map_err(|e| anyhow!("Request failed: {}", e))?;
+        // Follow redirects as far as the policy allows
+        let mut redirects = Vec::new();
+        while let Some((method, keep_body)) =
+            redirect::redirected_method(response.status(), &outgoing.method)
+        {
+            let current = Url::parse(&outgoing.uri.to_string())?;
+            let Some(target) = redirect::location(&current, response.headers()) else {
+                break;
+            };
+            if !redirect::should_follow(
+                request.redirect_policy,
+                &current,
+                &target,
+                redirects.len(),
+            )? {
+                break;
+            }
+
+            let (parts, redirect_body) = response.into_parts();
+            read_body(redirect_body, limit(timeouts.body_idle)).await?;
+            redirects.push(RedirectHop {
+                method: outgoing.method.to_string(),
+                url: current.to_string(),
+                status_code: parts.status.as_u16(),
+                headers: header_schemas(&parts.headers),
+                location: target.to_string(),
+                duration: as_millis(request_send_time.elapsed()),
+            });
+
+            if !keep_body {
+                outgoing.body = BodySource::Bytes(Bytes::new());
+                redirect::strip_body_headers(&mut outgoing.headers);
+            }
+            if !redirect::same_origin(&current, &target) {
+                redirect::strip_credentials(&mut outgoing.headers);
+                // Once dropped, the credentials stay off the remaining hops
+                for name in outgoing.credential_headers.drain(..) {
+                    outgoing.headers.remove(name);
+                }
+            }
+            outgoing.method = method;
+            outgoing.uri = target.as_str().parse()?;
+            // A `Host` set by the user or by signing names the previous URL
+            outgoing.headers.remove(HOST);
+
+            // Signatures and Digest answers cover the method and URI, so they
+            // are computed again for the new ones
+            if !outgoing.credential_headers.is_empty() {
+                match &request.authorization {
+                    Authorization::AwsSigV4 { .. } => {
+                        outgoing.sign(&request.authorization).await?;
+                    }
+                    Authorization::Digest { username, password } => {
+                        if let Some(challenge) = &digest_challenge {
+                            outgoing
+                                .answer_digest(challenge, username, password)
+                                .await?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            request_send_time = Instant::now();
+            response = self.send(&outgoing, &timeouts).await?;
+            connect_phases = claim_connect_phases(&response).or(connect_phases);
+        }
 
         // Mark response headers received time (TTFB)
         let response_headers_time = Instant::now();
@@ -144,109 +1130,123 @@ impl RelayService {
         let status_code = response.status().as_u16();
 
         // Process response headers
-        let mut processed_headers = HashMap::new();
-        for (key, value) in response.headers() {
-            let key_str = key.as_str().to_string();
-            let value_str = value.to_str().unwrap_or("").to_string();
-            processed_headers.insert(
-                key_str.clone(),
-                HeaderSchema {
-                    id: key_str.clone(),
-                    name: key_str,
-                    value: value_str,
-                },
-            );
-        }
+        let processed_headers = header_schemas(response.headers());
+
+        let server_timing = parse_server_timing(
+            response
+                .headers()
+                .get_all("server-timing")
+                .iter()
+                .filter_map(|value| value.to_str().ok()),
+        );
 
-        let content_type = response
+        let content_type_header = response
             .headers()
             .get("content-type")
             .and_then(|ct| ct.to_str().ok())
+            .map(str::to_string);
+        let content_type = content_type_header
+            .as_deref()
             .map(|ct| ct.split(';').next().unwrap_or(ct).to_string());
 
-        // Read response body
-        let body_bytes = response
-            .into_body()
-            .collect()
-            .await
-            .map_err(|e| anyhow!("Failed to read response body: {}", e))?
-            .to_bytes();
-
-        let response_body =
-            String::from_utf8(body_bytes.to_vec()).unwrap_or_else(|_| "[Binary data]".to_string());
+        let content_encoding = response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        // Read response body, failing if the server goes quiet for too long
+        let idle = limit(timeouts.body_idle);
+        let content_encoding = content_encoding.filter(|_| !request.raw_body);
+        let (body_bytes, transfer_size, decoded_size) = match events {
+            Some(events) => {
+                send_event(
+                    events,
+                    StreamEvent::Headers {
+                        status_code,
+                        headers: processed_headers.clone(),
+                    },
+                )
+                .await?;
+                let retain_limit = request.retain_limit.unwrap_or(DEFAULT_RETAIN_LIMIT);
+                let decoded_limit = request.decoded_limit.unwrap_or(DEFAULT_DECODED_LIMIT);
+                let decoder = StreamDecoder::new(content_encoding.as_deref(), decoded_limit)?;
+                stream_body(response.into_body(), idle, events, decoder, retain_limit).await?
+            }
+            None => {
+                let body_bytes = read_body(response.into_body(), idle).await?;
+                let transfer_size = body_bytes.len();
+                let decoded_limit = request.decoded_limit.unwrap_or(DEFAULT_DECODED_LIMIT);
+                let body_bytes =
+                    decode_body(content_encoding.as_deref(), body_bytes, decoded_limit)?;
+                let decoded_size = body_bytes.len();
+                (body_bytes, transfer_size, decoded_size)
+            }
+        };
+        let body_truncated = body_bytes.len() < decoded_size;
+
+        // Text in a declared charset is decoded to UTF-8, binary bodies are
+        // passed on as base64 so no bytes are lost
+        let (response_body, body_encoding, detected_mime_type, charset) =
+            match detect_charset(content_type_header.as_deref(), &body_bytes) {
+                Some(encoding)
+                    if encoding != UTF_8
+                        && (is_wide(encoding) || !contains_binary_data(&body_bytes)) =>
+                {
+                    let text = decode_text(encoding, &body_bytes);
+                    (text, BodyEncoding::Text, None, Some(encoding.name()))
+                }
+                _ if is_binary(&body_bytes) => (
+                    STANDARD.encode(&body_bytes),
+                    BodyEncoding::Base64,
+                    sniff_mime_type(&body_bytes).map(str::to_string),
+                    None,
+                ),
+                _ => {
+                    let text = decode_text(UTF_8, &body_bytes);
+                    (text, BodyEncoding::Text, None, Some(UTF_8.name()))
+                }
+            };
 
         // Mark response body fully received
         let response_complete_time = Instant::now();
 
-        // Calculate timing metrics from measurable points
-        // 
-        // TTFB (Time To First Byte) = Time from request send until first response byte (headers)
-        // This includes: DNS + TCP + TLS (for new connections) + network RTT + server processing
-        let ttfb = response_headers_time
-            .duration_since(request_send_time)
-            .as_secs_f64()
-            * 1000.0;
-
-        // Split TTFB into latency and processing using heuristics
-        // 
-        // For HTTPS connections, typical breakdown:
-        // - DNS: 20-120ms (depends on cache)
-        // - TCP handshake: 1-100ms (depends on distance)
-        // - TLS handshake: 50-200ms (depends on distance and cipher)
-        // - Total connection: 70-400ms for new connections, ~0ms for reused
-        // - Network RTT: 1-200ms
-        // - Server processing: varies widely
-        //
-        // Heuristic: If TTFB > 100ms, likely includes connection overhead
-        // Split conservatively: ~30-40% connection, rest is RTT + processing
-        
-        let (latency, processing_time) = if ttfb > 100.0 {
-            // Longer TTFB - likely a new connection
-            // Assume 35% is connection establishment (DNS + TCP + TLS)
-            // Remaining 65% is network RTT + server processing
-            let connection_overhead = ttfb * 0.35;
-            let remaining = ttfb - connection_overhead;
-            
-            // Of remaining time, assume 25% is network RTT, 75% is server processing
-            let processing = remaining * 0.75;
-            
-            (connection_overhead, processing)
-        } else if ttfb > 20.0 {
-            // Medium TTFB - connection likely reused
-            // Assume 10% is connection overhead, rest is RTT + processing
-            let connection_overhead = ttfb * 0.10;
-            let remaining = ttfb - connection_overhead;
-            
-            // 30% network RTT, 70% server processing
-            let processing = remaining * 0.70;
-            
-            (connection_overhead, processing)
-        } else {
-            // Very fast response (< 20ms) - likely local or cached
-            // Minimal connection overhead, mostly processing
-            let connection_overhead = ttfb * 0.05;
-            let processing = ttfb - connection_overhead;
-            
-            (connection_overhead, processing)
+        if !auth_exchanges.is_empty() {
+            auth_exchanges.push(outgoing.exchange(
+                status_code,
+                processed_headers.clone(),
+                as_millis(response_complete_time.duration_since(request_send_time)),
+            ));
+        }
+
+        // Connection setup as measured by the connector stack (zero for reused connections)
+        let (dns, tcp_connect, tls_handshake, ready_at) = match connect_phases {
+            Some(phases) => (
+                as_millis(phases.dns),
+                as_millis(phases.tcp_connect),
+                as_millis(phases.tls_handshake),
+                phases.ready_at.max(request_send_time),
+            ),
+            None => (0.0, 0.0, 0.0, request_send_time),
         };
+        let connection_reused = connect_phases.is_none();
 
-        // NOTE: For truly accurate measurements, have your server send timing headers:
-        // Server-Timing: db;dur=53, app;dur=47.2
-        // Then parse and use those values for processing_time
+        // TTFB = Time from the connection being ready until the response headers arrive
+        // This covers network RTT + server processing
+        let ttfb = as_millis(response_headers_time.saturating_duration_since(ready_at));
+
+        // Latency = Connection establishment (DNS + TCP + TLS)
+        let latency = dns + tcp_connect + tls_handshake;
+
+        // Processing Time = What the server reports via Server-Timing, otherwise TTFB
+        let processing_time = processing_time(&server_timing).unwrap_or(ttfb);
 
         // Transfer Time = Time to download response body after headers
-        let transfer_time = response_complete_time
-            .duration_since(response_headers_time)
-            .as_secs_f64()
-            * 1000.0;
+        let transfer_time = as_millis(response_complete_time.duration_since(response_headers_time));
 
         // Total Duration = End-to-end time
-        let duration = response_complete_time
-            .duration_since(client_start_time)
-            .as_secs_f64()
-            * 1000.0;
+        let duration = as_millis(response_complete_time.duration_since(client_start_time));
 
-        let transfer_size = response_body.len();
         let transfer_encoding = processed_headers
             .get("transfer-encoding")
             .map(|h| h.value.clone())
@@ -257,16 +1257,426 @@ impl RelayService {
             content_type,
             status_code,
             content: response_body,
+            body_encoding,
+            detected_mime_type,
+            charset: charset.map(str::to_string),
+            body_truncated,
             performance: ResponsePerformance {
                 duration,
                 latency,
                 processing_time,
                 transfer_time,
                 transfer_size,
+                decoded_size,
                 transfer_encoding,
+                dns,
+                tcp_connect,
+                tls_handshake,
+                ttfb,
+                download: transfer_time,
+                connection_reused,
+                server_timing,
             },
+            auth_exchanges,
+            redirects,
         })
     }
+
+    /// Turns a request into one ready to send: query parameters, headers,
+    /// authorization and body are applied, and the request signed if needed.
+    async fn prepare_request(
+        &self,
+        request: &mut Request,
+        timeouts: &TimeoutSettings,
+        client_timestamp: i64,
+        advertise_encodings: bool,
+    ) -> Result<OutgoingRequest> {
+        // Parse URL and add query parameters
+        let mut parsed_url =
+            Url::parse(&request.url).map_err(|e| anyhow!("Invalid URL: {}", e))?;
+
+        // Add query parameters
+        for (key, value) in &request.params {
+            let value_str = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                _ => value.to_string(),
+            };
+            parsed_url.query_pairs_mut().append_pair(key, &value_str);
+        }
+
+        // Convert method
+        let method = match request.method {
+            RequestMethod::GET => Method::GET,
+            RequestMethod::POST => Method::POST,
+            RequestMethod::PUT => Method::PUT,
+            RequestMethod::DELETE => Method::DELETE,
+            RequestMethod::PATCH => Method::PATCH,
+            RequestMethod::OPTIONS => Method::OPTIONS,
+            RequestMethod::HEAD => Method::HEAD,
+        };
+
+        // Collect headers
+        let mut headers = HeaderMap::new();
+        for (key, value) in &request.headers {
+            if let serde_json::Value::String(header_value) = value {
+                headers.append(
+                    HeaderName::from_bytes(key.as_bytes())?,
+                    HeaderValue::from_str(header_value)?,
+                );
+            }
+        }
+
+        // Obtain an OAuth 2.0 token (or reuse a cached one) before applying it
+        if let Authorization::OAuth2 {
+            token,
+            flow: Some(flow),
+        } = &mut request.authorization
+        {
+            // The token endpoint may be on another host, with proxy rules of its own
+            let token_url =
+                Url::parse(&flow.token_url).map_err(|e| anyhow!("Invalid token URL: {}", e))?;
+            let proxy = self.proxy_for(&request.proxy, &token_url)?;
+            let clients = self.clients(
+                proxy.as_ref(),
+                request.workspace_id.as_deref(),
+                request.client_certificate.as_ref(),
+            )?;
+            *token = CONNECT_TIMEOUTS
+                .scope(
+                    ConnectTimeouts::from(timeouts),
+                    self.oauth2.access_token(&clients.http, flow),
+                )
+                .await?;
+        }
+
+        // Apply authorization (may add a header or a query parameter)
+        apply_authorization(&request.authorization, &mut parsed_url, &mut headers)?;
+
+        // Build request body
+        let methods_without_body = ["GET", "HEAD", "OPTIONS"];
+        let method_str = format!("{:?}", request.method);
+
+        let mut body = BodySource::Bytes(Bytes::new());
+        if !methods_without_body.contains(&method_str.as_str()) {
+            if let Some(source) = BodySource::from_request(&request.body).await? {
+                // Set content-type if provided or implied by the kind of body
+                if let Some(content_type) =
+                    source.content_type(request.body.content_type.as_deref())
+                {
+                    headers.append(CONTENT_TYPE, HeaderValue::from_str(&content_type)?);
+                }
+                body = source;
+            }
+        }
+
+        // Add client timestamp header
+        headers.insert(
+            HeaderName::from_static("x-client-timestamp"),
+            HeaderValue::from(client_timestamp),
+        );
+
+        // Advertise the codings we can decode unless the user asked for others
+        if advertise_encodings && !headers.contains_key(ACCEPT_ENCODING) {
+            headers.insert(
+                ACCEPT_ENCODING,
+                HeaderValue::from_static(SUPPORTED_ENCODINGS),
+            );
+        }
+
+        let mut outgoing = OutgoingRequest {
+            method,
+            uri: parsed_url.as_str().parse()?,
+            headers,
+            body,
+            version: Version::default(),
+            id: request.id.clone(),
+            workspace_id: request.workspace_id.clone(),
+            proxy: request.proxy.clone(),
+            client_certificate: request.client_certificate.clone(),
+            credential_headers: credential_headers(&request.authorization),
+        };
+
+        // SigV4 covers the final query, headers and body, so it is applied last
+        outgoing.sign(&request.authorization).await?;
+
+        Ok(outgoing)
+    }
+
+    /// Sends one request and waits for its response headers.
+    async fn send(
+        &self,
+        outgoing: &OutgoingRequest,
+        timeouts: &TimeoutSettings,
+    ) -> Result<HyperResponse<Incoming>> {
+        let mut hyper_req_builder = HyperRequest::builder()
+            .method(outgoing.method.clone())
+            .version(outgoing.version)
+            .uri(outgoing.uri.clone());
+        // The jar's cookies are added to each request sent rather than to
+        // `outgoing`, so they follow redirects to other origins correctly
+        let url = Url::parse(&outgoing.uri.to_string())?;
+        let proxy = self.proxy_for(&outgoing.proxy, &url)?;
+        if let Some(builder_headers) = hyper_req_builder.headers_mut() {
+            *builder_headers = outgoing.headers.clone();
+            self.add_cookies(&url, outgoing.workspace_id.as_deref(), builder_headers);
+            // Proxies forwarding plain HTTP authenticate each request
+            let forwarded_by = proxy
+                .as_ref()
+                .filter(|proxy| proxy.kind == ProxyKind::Http && url.scheme() == "http");
+            if outgoing.version != Version::HTTP_2
+                && !builder_headers.contains_key(PROXY_AUTHORIZATION)
+            {
+                if let Some(authorization) = forwarded_by.and_then(Proxy::authorization) {
+                    builder_headers.insert(PROXY_AUTHORIZATION, authorization);
+                }
+            }
+        }
+        let progress = self.upload_progress.clone().map(|callback| Progress {
+            id: outgoing.id.clone(),
+            callback,
+        });
+        let hyper_req = hyper_req_builder.body(outgoing.body.open(progress).await?)?;
+
+        let clients = self.clients(
+            proxy.as_ref(),
+            outgoing.workspace_id.as_deref(),
+            outgoing.client_certificate.as_ref(),
+        )?;
+        let client = if outgoing.version == Version::HTTP_2 {
+            &clients.http2
+        } else {
+            &clients.http
+        };
+
+        // Execute request, handing the connection timeouts to the connector
+        let sending =
+            CONNECT_TIMEOUTS.scope(ConnectTimeouts::from(timeouts), client.request(hyper_req));
+        let response = with_timeout(
+            limit(timeouts.first_byte),
+            RelayErrorKind::FirstByteTimeout,
+            sending,
+        )
+        .await?
+        .map_err(|e| connect_error(&e))?;
+
+        self.store_cookies(&url, outgoing.workspace_id.as_deref(), response.headers());
+        Ok(response)
+    }
+
+    /// Adds the cookies of the workspace's jar matching `url`, unless the
+    /// request sets its own `Cookie` header.
+    fn add_cookies(&self, url: &Url, workspace_id: Option<&str>, headers: &mut HeaderMap) {
+        if headers.contains_key(COOKIE) {
+            return;
+        }
+        if let Some(cookies) = self.cookies.header(workspace_id, url) {
+            if let Ok(value) = HeaderValue::from_str(&cookies) {
+                headers.insert(COOKIE, value);
+            }
+        }
+    }
+
+    fn store_cookies(&self, url: &Url, workspace_id: Option<&str>, headers: &HeaderMap) {
+        self.cookies.store(workspace_id, url, headers);
+    }
+}
+
+/// A fully prepared request that can be sent more than once, e.g. to answer
+/// an authentication challenge.
+struct OutgoingRequest {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: BodySource,
+    /// HTTP/2 is forced for gRPC calls; other requests negotiate it
+    version: Version,
+    /// Request id reported with upload progress
+    id: Option<String>,
+    /// Whose cookie jar the request uses
+    workspace_id: Option<String>,
+    /// Chosen again for each URL, since a redirect may leave `NO_PROXY`
+    proxy: ProxySetting,
+    client_certificate: Option<ClientCertificate>,
+    /// Headers carrying the credentials, dropped on leaving the origin
+    credential_headers: Vec<HeaderName>,
+}
+
+impl OutgoingRequest {
+    /// Signs the request if `authorization` is AWS SigV4.
+    async fn sign(&mut self, authorization: &Authorization) -> Result<()> {
+        let Authorization::AwsSigV4 {
+            access_key,
+            secret_key,
+            session_token,
+            region,
+            service,
+            unsigned_payload,
+        } = authorization
+        else {
+            return Ok(());
+        };
+        let signer = SigV4 {
+            access_key,
+            secret_key,
+            session_token: session_token.as_deref(),
+            region,
+            service,
+            unsigned_payload: *unsigned_payload,
+        };
+        // Unsigned payloads spare hashing what may be a large file
+        let body_sha256 = if *unsigned_payload {
+            [0; 32]
+        } else {
+            self.body.sha256().await?
+        };
+        signer.sign_hashed(
+            &self.method,
+            &mut self.uri,
+            &mut self.headers,
+            body_sha256,
+            Utc::now(),
+        )
+    }
+
+    /// Answers a Digest `challenge` for the request's method and URI.
+    async fn answer_digest(
+        &mut self,
+        challenge: &DigestChallenge,
+        username: &str,
+        password: &str,
+    ) -> Result<()> {
+        let target = self
+            .uri
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str());
+        let body = match challenge.qop {
+            Some(Qop::AuthInt) => self.body.to_bytes().await?,
+            _ => Bytes::new(),
+        };
+        let authorization =
+            challenge.authorization(username, password, self.method.as_str(), target, &body);
+        self.headers
+            .insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+        Ok(())
+    }
+
+    fn exchange(&self, status_code: u16, headers: ResponseHeaders, duration: f64) -> AuthExchange {
+        AuthExchange {
+            method: self.method.to_string(),
+            url: self.uri.to_string(),
+            request_headers: header_schemas(&self.headers),
+            status_code,
+            headers,
+            duration,
+        }
+    }
+}
+
+/// Connection phases are only reported by the first response on a connection.
+fn claim_connect_phases(response: &HyperResponse<Incoming>) -> Option<ConnectPhases> {
+    response
+        .extensions()
+        .get::<ConnectionTimings>()
+        .and_then(|timings| timings.claim())
+}
+
+fn header_schemas(headers: &HeaderMap) -> ResponseHeaders {
+    let mut schemas = HashMap::new();
+    for (key, value) in headers {
+        let key_str = key.as_str().to_string();
+        let value_str = value.to_str().unwrap_or("").to_string();
+        schemas.insert(
+            key_str.clone(),
+            HeaderSchema {
+                id: key_str.clone(),
+                name: key_str,
+                value: value_str,
+            },
+        );
+    }
+    schemas
+}
+
+/// Pushes the body to `events` chunk by chunk, decoding it on the way when
+/// given a `decoder`, and keeps up to `retain_limit` decoded bytes. Returns
+/// the retained bytes and the size of the whole body as received and as
+/// decoded.
+async fn stream_body(
+    mut body: Incoming,
+    idle: Option<Duration>,
+    events: &mpsc::Sender<StreamEvent>,
+    mut decoder: Option<StreamDecoder>,
+    retain_limit: usize,
+) -> Result<(Vec<u8>, usize, usize)> {
+    let mut retained = Vec::new();
+    let mut received = 0;
+    let mut size = 0;
+    let mut push = |data: &[u8], retained: &mut Vec<u8>| {
+        let keep = data.len().min(retain_limit.saturating_sub(retained.len()));
+        retained.extend_from_slice(&data[..keep]);
+        let offset = size as u64;
+        size += data.len();
+        StreamEvent::Chunk {
+            data: STANDARD.encode(data),
+            offset,
+        }
+    };
+
+    while let Some(frame) =
+        with_timeout(idle, RelayErrorKind::BodyIdleTimeout, body.frame()).await?
+    {
+        let frame = frame.map_err(|e| anyhow!("Failed to read response body: {}", e))?;
+        let Some(data) = frame.data_ref() else {
+            continue;
+        };
+        received += data.len();
+        let decoded = match decoder.as_mut() {
+            Some(decoder) => decoder.push(data)?,
+            None => data.to_vec(),
+        };
+        if !decoded.is_empty() {
+            send_event(events, push(&decoded, &mut retained)).await?;
+        }
+    }
+    if let Some(decoder) = decoder {
+        let decoded = decoder.finish()?;
+        if !decoded.is_empty() {
+            send_event(events, push(&decoded, &mut retained)).await?;
+        }
+    }
+    Ok((retained, received, size))
+}
+
+/// The status a finished call ended with.
+fn grpc_status(stream: &ResponseStream) -> Result<GrpcStatus> {
+    stream
+        .status()
+        .cloned()
+        .ok_or_else(|| anyhow!("gRPC call ended without a status"))
+}
+
+async fn send_event<T>(events: &mpsc::Sender<T>, event: T) -> Result<()> {
+    events
+        .send(event)
+        .await
+        .map_err(|_| anyhow!("Response stream closed by the receiver"))
+}
+
+/// Reads a response body to the end, failing if the server goes quiet for
+/// longer than `idle`.
+async fn read_body(mut body: Incoming, idle: Option<Duration>) -> Result<Vec<u8>> {
+    let mut body_bytes = Vec::new();
+    while let Some(frame) =
+        with_timeout(idle, RelayErrorKind::BodyIdleTimeout, body.frame()).await?
+    {
+        let frame = frame.map_err(|e| anyhow!("Failed to read response body: {}", e))?;
+        if let Some(data) = frame.data_ref() {
+            body_bytes.extend_from_slice(data);
+        }
+    }
+    Ok(body_bytes)
 }
 
 impl Default for RelayService {
@@ -278,7 +1688,7 @@ impl Default for RelayService {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use wiremock::matchers::{method, path, header, body_string, header_exists};
+    use wiremock::matchers::{method, path, header, body_string, header_exists, query_param};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     #[tokio::test]
@@ -302,8 +1712,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -349,8 +1761,10 @@ mod tests {
             body: RequestBody {
                 content_type: Some("application/json".to_string()),
                 content: Some(r#"{"name":"test"}"#.to_string()),
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -383,8 +1797,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params,
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -420,8 +1836,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -450,8 +1868,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: Some("updated".to_string()),
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -471,8 +1891,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -493,8 +1915,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: Some("patch".to_string()),
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -524,8 +1948,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -546,8 +1972,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -579,8 +2007,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -628,8 +2058,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -640,6 +2072,249 @@ mod tests {
         assert!(resp.performance.transfer_time > 0.0, "Transfer time should be measured for large response");
     }
 
+    #[tokio::test]
+    async fn test_server_timing_header_parsed() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/timed"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("timed")
+                    .append_header("Server-Timing", "db;dur=53, app;dur=47.2")
+                    .append_header("Server-Timing", r#"cache;desc="Cache Read""#),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let service = RelayService::new();
+        let request = Request {
+            url: format!("{}/timed", mock_server.uri()),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        let perf = response.response.unwrap().performance;
+
+        assert_eq!(perf.server_timing.len(), 3);
+        assert_eq!(perf.server_timing[0].name, "db");
+        assert_eq!(perf.server_timing[2].description.as_deref(), Some("Cache Read"));
+        assert_eq!(perf.server_timing[2].duration, None);
+
+        // Server-reported durations take precedence over the measured TTFB
+        assert!((perf.processing_time - 100.2).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_aws_sigv4_signing_applied() {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("PUT"))
+            .and(path("/bucket/key.txt"))
+            .and(query_param("note", "two words"))
+            .and(header_exists("x-amz-date"))
+            .and(header("x-amz-security-token", "session-token"))
+            .and(header(
+                "x-amz-content-sha256",
+                "239f59ed55e737c77147cf55ad0c1b030b6d7ee748a7426952f9b852d5a935e5",
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_string("stored"))
+            .mount(&mock_server)
+            .await;
+
+        let service = RelayService::new();
+        let mut params = HashMap::new();
+        params.insert(
+            "note".to_string(),
+            serde_json::Value::String("two words".to_string()),
+        );
+        let request = Request {
+            url: format!("{}/bucket/key.txt", mock_server.uri()),
+            method: RequestMethod::PUT,
+            body: RequestBody {
+                content_type: Some("text/plain".to_string()),
+                content: Some("payload".to_string()),
+                ..Default::default()
+            },
+            params,
+            authorization: Authorization::AwsSigV4 {
+                access_key: "AKIDEXAMPLE".to_string(),
+                secret_key: "secret".to_string(),
+                session_token: Some("session-token".to_string()),
+                region: "eu-west-1".to_string(),
+                service: "s3".to_string(),
+                unsigned_payload: false,
+            },
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(response.response.unwrap().status_code, 200);
+
+        let received = &mock_server.received_requests().await.unwrap()[0];
+        // The query goes out in the encoding it was signed with
+        assert_eq!(received.url.query(), Some("note=two%20words"));
+        let authorization = received.headers["authorization"].to_str().unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(authorization.contains("/eu-west-1/s3/aws4_request"));
+        // Headers set by the relay, like the client timestamp, are signed too
+        assert!(authorization.contains(
+            "SignedHeaders=accept-encoding;content-type;host;x-amz-content-sha256;\
+             x-amz-date;x-amz-security-token;x-client-timestamp,"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_response_decoded() {
+        use std::io::Write;
+
+        let mock_server = MockServer::start().await;
+        let text = "compressible ".repeat(100);
+        let mut encoder =
+            flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        let gzipped = encoder.finish().unwrap();
+
+        Mock::given(method("GET"))
+            .and(path("/gzip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Encoding", "gzip")
+                    .set_body_raw(gzipped.clone(), "text/plain"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let service = RelayService::new();
+        let request = Request {
+            url: format!("{}/gzip", mock_server.uri()),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request.clone()).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.content, text);
+        assert_eq!(resp.performance.transfer_size, gzipped.len());
+        assert_eq!(resp.performance.decoded_size, text.len());
+
+        let received = &mock_server.received_requests().await.unwrap()[0];
+        assert_eq!(received.headers["accept-encoding"], "gzip, deflate, br, zstd");
+
+        // Decoding can be turned off to inspect the bytes as received
+        let raw = Request {
+            raw_body: true,
+            ..request
+        };
+        let response = service.relay_http_request(raw).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.body_encoding, BodyEncoding::Base64);
+        assert_eq!(STANDARD.decode(&resp.content).unwrap(), gzipped);
+        assert_eq!(resp.detected_mime_type.as_deref(), Some("application/gzip"));
+        assert_eq!(resp.performance.transfer_size, gzipped.len());
+        assert_eq!(resp.performance.decoded_size, gzipped.len());
+    }
+
+    #[tokio::test]
+    async fn test_binary_response_as_base64() {
+        let mock_server = MockServer::start().await;
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\x00\x00\x00\x01".to_vec();
+
+        Mock::given(method("GET"))
+            .and(path("/image"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw(png.clone(), "application/octet-stream"),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/text"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("BMW, not a bitmap"))
+            .mount(&mock_server)
+            .await;
+
+        let service = RelayService::new();
+        let request = Request {
+            url: format!("{}/image", mock_server.uri()),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.body_encoding, BodyEncoding::Base64);
+        assert_eq!(STANDARD.decode(&resp.content).unwrap(), png);
+        assert_eq!(resp.detected_mime_type.as_deref(), Some("image/png"));
+        assert_eq!(resp.content_type.as_deref(), Some("application/octet-stream"));
+
+        let request = Request {
+            url: format!("{}/text", mock_server.uri()),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.body_encoding, BodyEncoding::Text);
+        assert_eq!(resp.content, "BMW, not a bitmap");
+        assert_eq!(resp.detected_mime_type, None);
+    }
+
+    #[tokio::test]
+    async fn test_charset_declared_response_decoded() {
+        let mock_server = MockServer::start().await;
+        let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode("こんにちは、世界");
+
+        Mock::given(method("GET"))
+            .and(path("/sjis"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_raw(shift_jis.into_owned(), "text/plain; charset=Shift_JIS"),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/latin1"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                b"<html><head><meta charset=iso-8859-1></head>Caf\xe9</html>".to_vec(),
+                "text/html",
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let service = RelayService::new();
+        let request = Request {
+            url: format!("{}/sjis", mock_server.uri()),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.body_encoding, BodyEncoding::Text);
+        assert_eq!(resp.content, "こんにちは、世界");
+        assert_eq!(resp.charset.as_deref(), Some("Shift_JIS"));
+        assert_eq!(resp.content_type.as_deref(), Some("text/plain"));
+
+        let request = Request {
+            url: format!("{}/latin1", mock_server.uri()),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.body_encoding, BodyEncoding::Text);
+        assert!(resp.content.contains("Café"));
+        assert_eq!(resp.charset.as_deref(), Some("windows-1252"));
+    }
+
     #[tokio::test]
     async fn test_client_timestamp_header_sent() {
         let mock_server = MockServer::start().await;
@@ -659,8 +2334,10 @@ mod tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -673,12 +2350,14 @@ mod e2e_tests {
     use super::*;
     use axum::{
         body::Body,
+        extract::ws::{CloseFrame as WsCloseFrame, Message as WsMessage, WebSocketUpgrade},
         extract::{Path, Query},
         http::StatusCode,
         response::Response,
-        routing::{delete, get, patch, post, put},
+        routing::{any, delete, get, patch, post, put},
         Json, Router,
     };
+    use futures::StreamExt;
     use serde::{Deserialize, Serialize};
     use std::net::SocketAddr;
     use tokio::time::{sleep, Duration};
@@ -696,17 +2375,324 @@ mod e2e_tests {
         limit: Option<u32>,
     }
 
-    /// Create a test HTTP server with various endpoints
-    async fn create_test_server() -> (Router, SocketAddr) {
-        let app = Router::new()
-            .route("/", get(|| async { "Test Server Running" }))
-            .route("/hello", get(|| async { "Hello, World!" }))
-            .route(
-                "/echo",
-                post(|body: String| async move { body }),
-            )
-            .route(
-                "/json",
+    /// Digest-protected endpoint (admin:secret) offering SHA-256 with
+    /// `auth-int`, verified independently of the relay's implementation
+    async fn digest_protected(
+        method: axum::http::Method,
+        uri: axum::http::Uri,
+        headers: axum::http::HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        use sha2::{Digest, Sha256};
+        let sha256 = |data: &[u8]| hex::encode(Sha256::digest(data));
+        let nonce = "dcd98b7102dd2f0e8b11d0f600bfb0c093";
+
+        let fields: HashMap<&str, &str> = headers
+            .get("authorization")
+            .and_then(|auth| auth.to_str().ok())
+            .and_then(|auth| auth.strip_prefix("Digest "))
+            .map(|params| {
+                params
+                    .split(", ")
+                    .filter_map(|param| param.split_once('='))
+                    .map(|(key, value)| (key, value.trim_matches('"')))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if let (Some(response), Some(cnonce), Some(nc)) = (
+            fields.get("response"),
+            fields.get("cnonce"),
+            fields.get("nc"),
+        ) {
+            let ha1 = sha256(b"admin:clinic:secret");
+            let ha2 = sha256(format!("{}:{}:{}", method, uri, sha256(&body)).as_bytes());
+            let expected =
+                sha256(format!("{}:{}:{}:{}:auth-int:{}", ha1, nonce, nc, cnonce, ha2).as_bytes());
+            if *response == expected && fields.get("uri") == Some(&uri.to_string().as_str()) {
+                // `?next=` sends an authorized request on to another path
+                if let Some(next) = uri.query().and_then(|query| query.strip_prefix("next=")) {
+                    return Response::builder()
+                        .status(StatusCode::TEMPORARY_REDIRECT)
+                        .header("location", next)
+                        .body(Body::from("Moved"))
+                        .unwrap();
+                }
+                return Response::new(Body::from("Authorized via digest"));
+            }
+        }
+
+        Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("www-authenticate", r#"Basic realm="clinic""#)
+            .header(
+                "www-authenticate",
+                format!(
+                    r#"Digest realm="clinic", qop="auth-int", algorithm=SHA-256, nonce="{}", opaque="5ccc069c""#,
+                    nonce
+                ),
+            )
+            .body(Body::from("Unauthorized"))
+            .unwrap()
+    }
+
+    /// Introspection queries answered by `/graphql`
+    static INTROSPECTIONS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+    /// A GraphQL endpoint with a `book(id)` query, taking operations as a
+    /// JSON body or query parameters
+    async fn graphql_endpoint(
+        method: axum::http::Method,
+        headers: axum::http::HeaderMap,
+        Query(params): Query<HashMap<String, String>>,
+        body: Bytes,
+    ) -> Response {
+        let operation: serde_json::Value = if method == axum::http::Method::GET {
+            serde_json::json!({
+                "query": params.get("query"),
+                "variables": params
+                    .get("variables")
+                    .map(|variables| serde_json::from_str::<serde_json::Value>(variables).unwrap()),
+                "operationName": params.get("operationName"),
+            })
+        } else {
+            serde_json::from_slice(&body).unwrap()
+        };
+        let query = operation["query"].as_str().unwrap_or_default();
+        let reply = |status: StatusCode, result: serde_json::Value| {
+            Response::builder()
+                .status(status)
+                .header("content-type", "application/graphql-response+json")
+                .body(Body::from(result.to_string()))
+                .unwrap()
+        };
+
+        if !query.trim_end().ends_with('}') {
+            return reply(
+                StatusCode::BAD_REQUEST,
+                serde_json::json!({
+                    "errors": [{
+                        "message": "Syntax Error: Expected Name, found <EOF>.",
+                        "locations": [{ "line": 1, "column": query.len() + 1 }]
+                    }]
+                }),
+            );
+        }
+        if query.contains("__schema") {
+            if headers.contains_key("x-disable-introspection") {
+                return reply(
+                    StatusCode::OK,
+                    serde_json::json!({
+                        "errors": [{ "message": "GraphQL introspection is not allowed" }]
+                    }),
+                );
+            }
+            INTROSPECTIONS.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            return reply(
+                StatusCode::OK,
+                serde_json::json!({
+                    "data": {
+                        "__schema": {
+                            "queryType": { "name": "Query" },
+                            "mutationType": null,
+                            "subscriptionType": null,
+                            "types": [{ "kind": "OBJECT", "name": "Query" }],
+                            "directives": []
+                        }
+                    }
+                }),
+            );
+        }
+
+        let id = operation["variables"]["id"].as_str().unwrap_or_default();
+        let book = (id == "42").then(|| {
+            serde_json::json!({
+                "title": "Dune",
+                "via": method.as_str(),
+                "operation": operation["operationName"],
+            })
+        });
+        let mut result = serde_json::json!({ "data": { "book": book } });
+        if book.is_none() {
+            result["errors"] = serde_json::json!([{
+                "message": format!("Book {} not found", id),
+                "path": ["book"],
+                "extensions": { "code": "NOT_FOUND" }
+            }]);
+        }
+        reply(StatusCode::OK, result)
+    }
+
+    const COMMON_PROTO: &str = r#"
+        syntax = "proto3";
+        package test.common;
+
+        message HelloRequest {
+          string name = 1;
+        }
+    "#;
+
+    const GREETER_PROTO: &str = r#"
+        syntax = "proto3";
+        package test.greeter;
+
+        import "common.proto";
+
+        message HelloReply {
+          string message = 1;
+        }
+
+        service Greeter {
+          rpc SayHello(test.common.HelloRequest) returns (HelloReply);
+          rpc StreamGreetings(test.common.HelloRequest) returns (stream HelloReply);
+          rpc CollectNames(stream test.common.HelloRequest) returns (HelloReply);
+          rpc Chat(stream test.common.HelloRequest) returns (stream HelloReply);
+          rpc Fail(test.common.HelloRequest) returns (HelloReply);
+        }
+    "#;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct HelloRequest {
+        #[prost(string, tag = "1")]
+        name: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct HelloReply {
+        #[prost(string, tag = "1")]
+        message: String,
+    }
+
+    fn grpc_messages(body: &[u8]) -> Vec<Bytes> {
+        let mut decoder = grpc::FrameDecoder::default();
+        decoder.push(body);
+        std::iter::from_fn(|| decoder.next_frame().map(|(_, payload)| payload)).collect()
+    }
+
+    /// A gRPC response with the messages in the body and the status in the
+    /// trailers
+    fn grpc_reply(messages: Vec<Vec<u8>>, headers: &[(&'static str, String)]) -> Response {
+        use http_body_util::StreamBody;
+        use hyper::body::Frame;
+
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        let frames = vec![
+            Ok::<_, std::convert::Infallible>(Frame::data(grpc::encode_frames(&messages))),
+            Ok(Frame::trailers(trailers)),
+        ];
+        let mut response = Response::new(Body::new(StreamBody::new(futures::stream::iter(frames))));
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+        for (name, value) in headers {
+            response
+                .headers_mut()
+                .insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        response
+    }
+
+    /// Implements `test.greeter.Greeter`, answering each name in the request
+    async fn greeter(
+        Path(method): Path<String>,
+        headers: axum::http::HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let names: Vec<String> = grpc_messages(&body)
+            .into_iter()
+            .map(|message| HelloRequest::decode(message).unwrap().name)
+            .collect();
+        let replies: Vec<String> = match method.as_str() {
+            "SayHello" => vec![format!("Hello, {}!", names[0])],
+            "StreamGreetings" => (1..=3)
+                .map(|n| format!("Hello #{}, {}!", n, names[0]))
+                .collect(),
+            "CollectNames" => vec![format!("Hello, {}!", names.join(" and "))],
+            "Chat" => names.iter().map(|name| format!("Echo: {}", name)).collect(),
+            "Fail" => {
+                // Trailers-only response
+                return Response::builder()
+                    .header("content-type", "application/grpc")
+                    .header("grpc-status", "5")
+                    .header("grpc-message", "No greeter named %22nobody%22")
+                    .body(Body::empty())
+                    .unwrap();
+            }
+            _ => unreachable!("unknown method {}", method),
+        };
+        let replies = replies
+            .into_iter()
+            .map(|message| HelloReply { message }.encode_to_vec())
+            .collect();
+        let caller = headers
+            .get("x-caller")
+            .map(|caller| caller.to_str().unwrap().to_string())
+            .unwrap_or_default();
+        grpc_reply(replies, &[("x-caller-seen", caller)])
+    }
+
+    /// Implements only the older `v1alpha` reflection service, sending a
+    /// file without its dependencies
+    async fn reflection(body: Bytes) -> Response {
+        use crate::grpc_reflection::*;
+
+        let files = [
+            crate::proto::parse_proto("common.proto", COMMON_PROTO).unwrap(),
+            crate::proto::parse_proto("greeter.proto", GREETER_PROTO).unwrap(),
+        ];
+        let file_response = |name: &str| match files.iter().find(|file| file.name() == name) {
+            Some(file) => MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+                file_descriptor_proto: vec![file.encode_to_vec()],
+            }),
+            None => MessageResponse::ErrorResponse(ErrorResponse {
+                error_code: 5,
+                error_message: format!("{} not found", name),
+            }),
+        };
+        let replies = grpc_messages(&body)
+            .into_iter()
+            .map(|message| {
+                let request = ServerReflectionRequest::decode(message).unwrap();
+                let response = match request.message_request.unwrap() {
+                    MessageRequest::ListServices(_) => {
+                        MessageResponse::ListServicesResponse(ListServiceResponse {
+                            service: [
+                                "test.greeter.Greeter",
+                                "grpc.reflection.v1alpha.ServerReflection",
+                            ]
+                            .map(|name| ServiceResponse {
+                                name: name.to_string(),
+                            })
+                            .to_vec(),
+                        })
+                    }
+                    MessageRequest::FileContainingSymbol(symbol) => {
+                        assert_eq!(symbol, "test.greeter.Greeter");
+                        file_response("greeter.proto")
+                    }
+                    MessageRequest::FileByFilename(name) => file_response(&name),
+                };
+                ServerReflectionResponse {
+                    message_response: Some(response),
+                }
+                .encode_to_vec()
+            })
+            .collect();
+        grpc_reply(replies, &[])
+    }
+
+    /// Create a test HTTP server with various endpoints
+    async fn create_test_server() -> (Router, SocketAddr) {
+        let app = Router::new()
+            .route("/", get(|| async { "Test Server Running" }))
+            .route("/hello", get(|| async { "Hello, World!" }))
+            .route(
+                "/echo",
+                post(|body: String| async move { body }),
+            )
+            .route(
+                "/json",
                 get(|| async {
                     Json(TestUser {
                         id: 1,
@@ -767,6 +2753,122 @@ mod e2e_tests {
                     "Slow response"
                 }),
             )
+            .route(
+                "/stall-body",
+                get(|| async {
+                    // Send the first chunk, then never finish the body
+                    let chunks = futures::stream::once(async {
+                        Ok::<_, std::io::Error>(axum::body::Bytes::from("partial"))
+                    })
+                    .chain(futures::stream::pending());
+                    Body::from_stream(chunks)
+                }),
+            )
+            .route(
+                "/chunked",
+                get(|| async {
+                    // Five lines, each flushed separately after a short pause
+                    let chunks = futures::stream::iter(0..5).then(|i| async move {
+                        sleep(Duration::from_millis(10)).await;
+                        Ok::<_, std::io::Error>(axum::body::Bytes::from(format!("line {}\n", i)))
+                    });
+                    Body::from_stream(chunks)
+                }),
+            )
+            .route(
+                "/chunked-gzip",
+                get(|headers: axum::http::HeaderMap| async move {
+                    // The lines of /chunked, gzipped when the client accepts
+                    // it and sent in small pieces
+                    let text = "line 0\nline 1\nline 2\nline 3\nline 4\n";
+                    let accepts_gzip = headers
+                        .get("accept-encoding")
+                        .and_then(|value| value.to_str().ok())
+                        .is_some_and(|value| value.contains("gzip"));
+                    let body = if accepts_gzip {
+                        use std::io::Write;
+                        let mut encoder = flate2::write::GzEncoder::new(
+                            Vec::new(),
+                            flate2::Compression::default(),
+                        );
+                        encoder.write_all(text.as_bytes()).unwrap();
+                        encoder.finish().unwrap()
+                    } else {
+                        text.as_bytes().to_vec()
+                    };
+                    let chunks: Vec<_> = body
+                        .chunks(8)
+                        .map(|chunk| Ok::<_, std::io::Error>(axum::body::Bytes::copy_from_slice(chunk)))
+                        .collect();
+                    let mut response = Response::builder();
+                    if accepts_gzip {
+                        response = response.header("content-encoding", "gzip");
+                    }
+                    response
+                        .body(Body::from_stream(futures::stream::iter(chunks)))
+                        .unwrap()
+                }),
+            )
+            .route(
+                "/events",
+                get(|headers: axum::http::HeaderMap| async move {
+                    // Sends two events and drops the connection, then resumes
+                    // after the Last-Event-ID and stays open
+                    let resumed = headers
+                        .get("last-event-id")
+                        .is_some_and(|id| id.as_bytes() == b"2");
+                    let chunks: Vec<&'static str> = if resumed {
+                        vec!["id: 3\ndata: third\n\n"]
+                    } else {
+                        vec![
+                            ": welcome\nretry: 50\n\n",
+                            "id: 1\nevent: greeting\ndata: first\n\n",
+                            "id: 2\ndata: second\ndata: line\n\n",
+                        ]
+                    };
+                    let events = futures::stream::iter(chunks)
+                        .map(|chunk| Ok::<_, std::io::Error>(axum::body::Bytes::from(chunk)));
+                    let body = if resumed {
+                        Body::from_stream(events.chain(futures::stream::pending()))
+                    } else {
+                        Body::from_stream(events)
+                    };
+                    Response::builder()
+                        .header("content-type", "text/event-stream")
+                        .body(body)
+                        .unwrap()
+                }),
+            )
+            .route(
+                "/ws",
+                get(|ws: WebSocketUpgrade| async move {
+                    // Echoes data frames; "bye" makes the server close
+                    ws.protocols(["echo"]).on_upgrade(|mut socket| async move {
+                        while let Some(Ok(message)) = socket.recv().await {
+                            let reply = match message {
+                                WsMessage::Text(text) if text == "bye" => {
+                                    WsMessage::Close(Some(WsCloseFrame {
+                                        code: 4000,
+                                        reason: "server says bye".into(),
+                                    }))
+                                }
+                                WsMessage::Text(_) | WsMessage::Binary(_) => message,
+                                _ => continue,
+                            };
+                            if socket.send(reply).await.is_err() {
+                                break;
+                            }
+                        }
+                    })
+                }),
+            )
+            .route(
+                "/hang",
+                get(|| async {
+                    sleep(Duration::from_secs(30)).await;
+                    "Finally"
+                }),
+            )
             .route(
                 "/large",
                 get(|| async {
@@ -793,14 +2895,150 @@ mod e2e_tests {
             )
             .route(
                 "/auth",
-                get(|headers: axum::http::HeaderMap| async move {
-                    if let Some(auth) = headers.get("authorization") {
-                        if auth == "Bearer secret-token" {
-                            return (StatusCode::OK, "Authorized");
+                get(
+                    |headers: axum::http::HeaderMap,
+                     Query(query): Query<HashMap<String, String>>| async move {
+                        if let Some(auth) = headers.get("authorization") {
+                            if auth == "Bearer secret-token" {
+                                return (StatusCode::OK, "Authorized");
+                            }
+                            // admin:secret
+                            if auth == "Basic YWRtaW46c2VjcmV0" {
+                                return (StatusCode::OK, "Authorized via basic");
+                            }
+                            if auth == "Token secret-token" {
+                                return (StatusCode::OK, "Authorized via custom scheme");
+                            }
+                        }
+                        if headers.get("x-api-key").is_some_and(|key| key == "secret-key") {
+                            return (StatusCode::OK, "Authorized via API key header");
+                        }
+                        if query.get("api_key").is_some_and(|key| key == "secret-key") {
+                            return (StatusCode::OK, "Authorized via API key query");
                         }
+                        (StatusCode::UNAUTHORIZED, "Unauthorized")
+                    },
+                ),
+            )
+            .route("/digest", get(digest_protected).post(digest_protected))
+            .route(
+                "/multipart",
+                post(|mut multipart: axum::extract::Multipart| async move {
+                    use sha2::{Digest, Sha256};
+                    let mut parts = Vec::new();
+                    while let Some(field) = multipart.next_field().await.unwrap() {
+                        let name = field.name().map(str::to_string);
+                        let filename = field.file_name().map(str::to_string);
+                        let content_type = field.content_type().map(str::to_string);
+                        let data = field.bytes().await.unwrap();
+                        parts.push(serde_json::json!({
+                            "name": name,
+                            "filename": filename,
+                            "contentType": content_type,
+                            "size": data.len(),
+                            "sha256": hex::encode(Sha256::digest(&data)),
+                        }));
+                    }
+                    Json(parts)
+                }),
+            )
+            .route(
+                "/upload",
+                post(|headers: axum::http::HeaderMap, body: Bytes| async move {
+                    use sha2::{Digest, Sha256};
+                    format!(
+                        "content-length={} received={} sha256={}",
+                        headers["content-length"].to_str().unwrap(),
+                        body.len(),
+                        hex::encode(Sha256::digest(&body))
+                    )
+                }),
+            )
+            .route(
+                "/oauth/token",
+                post(|body: String| async move {
+                    if body.contains("grant_type=client_credentials") {
+                        (
+                            StatusCode::OK,
+                            Json(serde_json::json!({
+                                "access_token": "secret-token",
+                                "token_type": "Bearer",
+                                "expires_in": 3600
+                            })),
+                        )
+                    } else {
+                        (
+                            StatusCode::BAD_REQUEST,
+                            Json(serde_json::json!({ "error": "unsupported_grant_type" })),
+                        )
+                    }
+                }),
+            )
+            .route("/graphql", get(graphql_endpoint).post(graphql_endpoint))
+            .route(
+                "/redirect/:code",
+                any(
+                    |Path(code): Path<u16>, Query(query): Query<HashMap<String, String>>| async move {
+                        Response::builder()
+                            .status(code)
+                            .header("location", &query["to"])
+                            .body(Body::from("Moved"))
+                            .unwrap()
+                    },
+                ),
+            )
+            .route(
+                "/chain/:hops",
+                get(|Path(hops): Path<u32>| async move {
+                    if hops == 0 {
+                        return Response::new(Body::from("End of the chain"));
+                    }
+                    Response::builder()
+                        .status(StatusCode::FOUND)
+                        .header("location", format!("/chain/{}", hops - 1))
+                        .body(Body::empty())
+                        .unwrap()
+                }),
+            )
+            .route(
+                "/inspect",
+                any(
+                    |method: axum::http::Method, headers: axum::http::HeaderMap, body: String| async move {
+                        let header = |name: &str| {
+                            headers
+                                .get(name)
+                                .map(|value| value.to_str().unwrap().to_string())
+                        };
+                        Json(serde_json::json!({
+                            "method": method.as_str(),
+                            "authorization": header("authorization"),
+                            "contentType": header("content-type"),
+                            "cookie": header("cookie"),
+                            "host": header("host"),
+                            "apiKey": header("x-api-key"),
+                            "amzDate": header("x-amz-date"),
+                            "amzSecurityToken": header("x-amz-security-token"),
+                            "amzContentSha256": header("x-amz-content-sha256"),
+                            "body": body,
+                        }))
+                    },
+                ),
+            )
+            .route(
+                "/set-cookie",
+                get(|Query(cookies): Query<Vec<(String, String)>>| async move {
+                    let mut response = Response::builder();
+                    for (_, cookie) in cookies {
+                        response = response.header("set-cookie", cookie);
                     }
-                    (StatusCode::UNAUTHORIZED, "Unauthorized")
+                    response.body(Body::from("Cookies set")).unwrap()
                 }),
+            )
+            // gRPC over HTTP/2 prior knowledge
+            .route("/test.greeter.Greeter/:method", post(greeter))
+            .route(
+                "/grpc.reflection.v1alpha.ServerReflection/ServerReflectionInfo",
+                post(reflection),
             );
 
         let addr = SocketAddr::from(([127, 0, 0, 1], 0)); // Port 0 = random available port
@@ -840,8 +3078,10 @@ mod e2e_tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -864,8 +3104,10 @@ mod e2e_tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -897,8 +3139,10 @@ mod e2e_tests {
             body: RequestBody {
                 content_type: Some("application/json".to_string()),
                 content: Some(serde_json::to_string(&user).unwrap()),
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -923,8 +3167,10 @@ mod e2e_tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -952,268 +3198,2855 @@ mod e2e_tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params,
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert!(resp.content.contains("rust"));
+        assert!(resp.content.contains("20"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_put_request() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let user = TestUser {
+            id: 0,
+            name: "Updated Name".to_string(),
+            email: "updated@example.com".to_string(),
+        };
+
+        let request = Request {
+            url: format!("{}/users/5", server_url),
+            method: RequestMethod::PUT,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: Some("application/json".to_string()),
+                content: Some(serde_json::to_string(&user).unwrap()),
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        
+        let updated_user: TestUser = serde_json::from_str(&resp.content).unwrap();
+        assert_eq!(updated_user.id, 5);
+        assert_eq!(updated_user.name, "Updated Name");
+    }
+
+    #[tokio::test]
+    async fn test_e2e_delete_request() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/users/10", server_url),
+            method: RequestMethod::DELETE,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 204);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_patch_request() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/users/7", server_url),
+            method: RequestMethod::PATCH,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: Some("text/plain".to_string()),
+                content: Some("partial update".to_string()),
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert!(resp.content.contains("Patched user 7"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_slow_response() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/slow", server_url),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
         };
 
-        let response = service.relay_http_request(request).await.unwrap();
-        
-        let resp = response.response.unwrap();
-        assert_eq!(resp.status_code, 200);
-        assert!(resp.content.contains("rust"));
-        assert!(resp.content.contains("20"));
+        let start = Instant::now();
+        let response = service.relay_http_request(request).await.unwrap();
+        let elapsed = start.elapsed();
+
+        assert_eq!(response.status, "success");
+        let resp = response.response.unwrap();
+        
+        // Should take at least 100ms due to sleep
+        assert!(elapsed.as_millis() >= 100);
+        assert!(resp.performance.duration >= 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_concurrent_requests() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let concurrency = 8;
+
+        let start = Instant::now();
+        let handles: Vec<_> = (0..concurrency)
+            .map(|_| {
+                let service = service.clone();
+                let request = Request {
+                    url: format!("{}/slow", server_url),
+                    method: RequestMethod::GET,
+                    headers: HashMap::new(),
+                    body: RequestBody {
+                        content_type: None,
+                        content: None,
+                        ..Default::default()
+                    },
+                    params: HashMap::new(),
+                    ..Default::default()
+                };
+                tokio::spawn(async move { service.relay_http_request(request).await })
+            })
+            .collect();
+
+        for handle in futures::future::join_all(handles).await {
+            let response = handle.unwrap().unwrap();
+            assert_eq!(response.status, "success");
+        }
+        let elapsed = start.elapsed();
+
+        // Each request takes 100ms; run serially they would need 800ms
+        assert!(
+            elapsed < Duration::from_millis(400),
+            "{} concurrent requests took {:?}, expected roughly the time of one",
+            concurrency,
+            elapsed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_cancel_in_flight_request() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/hang", server_url),
+            method: RequestMethod::GET,
+            id: Some("hanging-request".to_string()),
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let in_flight = {
+            let service = service.clone();
+            tokio::spawn(async move { service.relay_http_request(request).await })
+        };
+
+        // Let the request reach the server before cancelling it
+        sleep(Duration::from_millis(100)).await;
+        let cancel_response = service.cancel_request("hanging-request");
+        assert_eq!(cancel_response.status, "cancelled");
+
+        let response = in_flight.await.unwrap().unwrap();
+        assert_eq!(response.status, "cancelled");
+        assert!(response.response.is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // The request is no longer tracked once it has been cancelled
+        let response = service.cancel_request("hanging-request");
+        assert_eq!(response.status, "error");
+    }
+
+    #[tokio::test]
+    async fn test_e2e_cancel_unknown_request() {
+        let service = RelayService::new();
+
+        let response = service.cancel_request("does-not-exist");
+        assert_eq!(response.status, "error");
+        assert!(response.message.unwrap().contains("does-not-exist"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_first_byte_timeout() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/slow", server_url),
+            method: RequestMethod::GET,
+            timeouts: TimeoutSettings {
+                first_byte: Some(20),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::FirstByteTimeout));
+        assert!(response.message.unwrap().contains("first response byte"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_body_idle_timeout() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/stall-body", server_url),
+            method: RequestMethod::GET,
+            timeouts: TimeoutSettings {
+                body_idle: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::BodyIdleTimeout));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_total_timeout() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/stall-body", server_url),
+            method: RequestMethod::GET,
+            timeouts: TimeoutSettings {
+                body_idle: Some(5_000),
+                total: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TotalTimeout));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_tls_handshake_timeout() {
+        // A server that accepts TCP connections but never speaks TLS
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let service = RelayService::new();
+        let request = Request {
+            url: format!("https://{}/", addr),
+            method: RequestMethod::GET,
+            timeouts: TimeoutSettings {
+                tls_handshake: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TlsHandshakeTimeout));
+        assert!(response.message.unwrap().contains("TLS handshake"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_service_wide_timeouts() {
+        let server_url = start_test_server().await;
+        let service = RelayService::with_timeouts(TimeoutSettings {
+            first_byte: Some(20),
+            ..DEFAULT_TIMEOUTS
+        });
+
+        let request = Request {
+            url: format!("{}/slow", server_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+
+        // The service-wide first byte timeout applies by default
+        let response = service.relay_http_request(request.clone()).await.unwrap();
+        assert_eq!(response.error_kind, Some(RelayErrorKind::FirstByteTimeout));
+
+        // A request can disable it with 0
+        let request = Request {
+            timeouts: TimeoutSettings {
+                first_byte: Some(0),
+                ..Default::default()
+            },
+            ..request
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(response.error_kind, None);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_large_response() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/large", server_url),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        
+        // Should be 500KB
+        assert_eq!(resp.performance.transfer_size, 500 * 1024);
+        assert!(resp.performance.transfer_time > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_different_status_codes() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        // Test 201 Created
+        let request = Request {
+            url: format!("{}/status/201", server_url),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().status_code, 201);
+
+        // Test 404 Not Found
+        let request = Request {
+            url: format!("{}/status/404", server_url),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().status_code, 404);
+
+        // Test 500 Internal Server Error
+        let request = Request {
+            url: format!("{}/status/500", server_url),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().status_code, 500);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_custom_headers() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/headers", server_url),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        
+        let resp = response.response.unwrap();
+        assert!(resp.headers.contains_key("x-custom-header"));
+        assert_eq!(resp.headers.get("x-custom-header").unwrap().value, "test-value");
+        assert!(resp.headers.contains_key("x-request-id"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_authentication() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        // Test without auth header - should fail
+        let request = Request {
+            url: format!("{}/auth", server_url),
+            method: RequestMethod::GET,
+            headers: HashMap::new(),
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.response.as_ref().unwrap().status_code, 401);
+
+        // Test with correct auth header - should succeed
+        let mut headers = HashMap::new();
+        headers.insert(
+            "authorization".to_string(),
+            serde_json::Value::String("Bearer secret-token".to_string()),
+        );
+
+        let request = Request {
+            url: format!("{}/auth", server_url),
+            method: RequestMethod::GET,
+            headers,
+            body: RequestBody {
+                content_type: None,
+                content: None,
+                ..Default::default()
+            },
+            params: HashMap::new(),
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().status_code, 200);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_authorization_applied_by_relay() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let cases = vec![
+            (
+                Authorization::Bearer {
+                    token: "secret-token".to_string(),
+                },
+                "Authorized",
+            ),
+            (
+                Authorization::Basic {
+                    username: "admin".to_string(),
+                    password: "secret".to_string(),
+                },
+                "Authorized via basic",
+            ),
+            (
+                Authorization::ApiKey {
+                    key: "secret-key".to_string(),
+                    name: "X-API-Key".to_string(),
+                    location: ApiKeyLocation::Header,
+                },
+                "Authorized via API key header",
+            ),
+            (
+                Authorization::ApiKey {
+                    key: "secret-key".to_string(),
+                    name: "api_key".to_string(),
+                    location: ApiKeyLocation::Query,
+                },
+                "Authorized via API key query",
+            ),
+            (
+                Authorization::Custom {
+                    token: "secret-token".to_string(),
+                    scheme: Some("Token".to_string()),
+                },
+                "Authorized via custom scheme",
+            ),
+        ];
+
+        for (authorization, expected) in cases {
+            let request = Request {
+                url: format!("{}/auth", server_url),
+                method: RequestMethod::GET,
+                authorization: authorization.clone(),
+                ..Default::default()
+            };
+
+            let response = service.relay_http_request(request).await.unwrap();
+            let resp = response.response.unwrap();
+            assert_eq!(resp.status_code, 200, "{:?} was rejected", authorization);
+            assert_eq!(resp.content, expected);
+        }
+
+        // Wrong credentials are still rejected
+        let request = Request {
+            url: format!("{}/auth", server_url),
+            method: RequestMethod::GET,
+            authorization: Authorization::Basic {
+                username: "admin".to_string(),
+                password: "wrong".to_string(),
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().status_code, 401);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_digest_authentication() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let request = Request {
+            url: format!("{}/digest?page=2", server_url),
+            method: RequestMethod::POST,
+            body: RequestBody {
+                content_type: Some("text/plain".to_string()),
+                content: Some("digest me".to_string()),
+                ..Default::default()
+            },
+            authorization: Authorization::Digest {
+                username: "admin".to_string(),
+                password: "secret".to_string(),
+            },
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.content, "Authorized via digest");
+
+        // Both the challenge and the authorized exchange are reported
+        assert_eq!(resp.auth_exchanges.len(), 2);
+        let (challenge, answer) = (&resp.auth_exchanges[0], &resp.auth_exchanges[1]);
+        assert_eq!(challenge.status_code, 401);
+        assert_eq!(challenge.method, "POST");
+        assert!(challenge.url.ends_with("/digest?page=2"));
+        assert!(!challenge.request_headers.contains_key("authorization"));
+        assert!(challenge.headers["www-authenticate"].value.starts_with("Digest"));
+        assert_eq!(answer.status_code, 200);
+        let authorization = &answer.request_headers["authorization"].value;
+        assert!(authorization.contains("algorithm=SHA-256"));
+        assert!(authorization.contains("qop=auth-int"));
+        assert!(authorization.contains("opaque=\"5ccc069c\""));
+
+        // Wrong credentials end with the server's second 401
+        let request = Request {
+            url: format!("{}/digest", server_url),
+            method: RequestMethod::GET,
+            authorization: Authorization::Digest {
+                username: "admin".to_string(),
+                password: "wrong".to_string(),
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 401);
+        assert_eq!(resp.auth_exchanges.len(), 2);
+
+        // Other schemes make a single exchange and report none
+        let request = Request {
+            url: format!("{}/auth", server_url),
+            method: RequestMethod::GET,
+            authorization: Authorization::Bearer {
+                token: "secret-token".to_string(),
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        assert!(response.response.unwrap().auth_exchanges.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_oauth2_token_obtained_by_relay() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let flow = OAuth2Flow {
+            token_url: format!("{}/oauth/token", server_url),
+            client_id: "clinic".to_string(),
+            client_secret: Some("s3cret".to_string()),
+            scope: None,
+            client_authentication: OAuth2ClientAuthentication::Basic,
+            grant: OAuth2Grant::ClientCredentials,
+        };
+        let request = Request {
+            url: format!("{}/auth", server_url),
+            method: RequestMethod::GET,
+            authorization: Authorization::OAuth2 {
+                token: String::new(),
+                flow: Some(flow.clone()),
+            },
+            ..Default::default()
+        };
+
+        let response = service.relay_http_request(request.clone()).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.content, "Authorized");
+
+        // Token endpoint errors fail the request
+        let mut failing = request;
+        failing.authorization = Authorization::OAuth2 {
+            token: String::new(),
+            flow: Some(OAuth2Flow {
+                grant: OAuth2Grant::Password {
+                    username: "ada".to_string(),
+                    password: "hunter2".to_string(),
+                },
+                ..flow
+            }),
+        };
+        let response = service.relay_http_request(failing).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(
+            response.message.as_deref(),
+            Some("Token request failed: unsupported_grant_type")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_binary_and_file_bodies() {
+        use sha2::{Digest, Sha256};
+        let server_url = start_test_server().await;
+        let progress = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let service = RelayService::new().with_upload_progress({
+            let progress = progress.clone();
+            Arc::new(move |update| progress.lock().unwrap().push(update))
+        });
+
+        // Base64 content is sent as the decoded bytes
+        let request = Request {
+            url: format!("{}/upload", server_url),
+            method: RequestMethod::POST,
+            body: RequestBody {
+                content_type: Some("application/octet-stream".to_string()),
+                content: Some(STANDARD.encode(b"\x00\x01\xfe\xff")),
+                encoding: BodyEncoding::Base64,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(
+            response.response.unwrap().content,
+            format!(
+                "content-length=4 received=4 sha256={}",
+                hex::encode(Sha256::digest(b"\x00\x01\xfe\xff"))
+            )
+        );
+
+        // Files are streamed from disk with their length as Content-Length
+        let contents: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("relay-upload-{}", std::process::id()));
+        tokio::fs::write(&path, &contents).await.unwrap();
+        progress.lock().unwrap().clear();
+
+        let request = Request {
+            url: format!("{}/upload", server_url),
+            method: RequestMethod::POST,
+            id: Some("file-upload".to_string()),
+            body: RequestBody {
+                file_path: Some(path.to_string_lossy().into_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(
+            response.response.unwrap().content,
+            format!(
+                "content-length=300000 received=300000 sha256={}",
+                hex::encode(Sha256::digest(&contents))
+            )
+        );
+        let updates = progress.lock().unwrap().clone();
+        assert!(updates.len() > 1);
+        assert_eq!(
+            updates.last().unwrap(),
+            &UploadProgress {
+                id: Some("file-upload".to_string()),
+                sent: 300_000,
+                total: 300_000,
+            }
+        );
+
+        // The file is sent again to answer a Digest challenge covering the body
+        let request = Request {
+            url: format!("{}/digest", server_url),
+            method: RequestMethod::POST,
+            body: RequestBody {
+                file_path: Some(path.to_string_lossy().into_owned()),
+                ..Default::default()
+            },
+            authorization: Authorization::Digest {
+                username: "admin".to_string(),
+                password: "secret".to_string(),
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.content, "Authorized via digest");
+
+        // Missing files fail the request
+        let request = Request {
+            url: format!("{}/upload", server_url),
+            method: RequestMethod::POST,
+            body: RequestBody {
+                file_path: Some(path.to_string_lossy().into_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert!(response
+            .message
+            .unwrap()
+            .starts_with("Failed to read request body file"));
+    }
+
+    /// Runs a streamed request, collecting every event it pushes.
+    async fn stream_events(
+        service: &RelayService,
+        request: Request,
+    ) -> (RelayResponse, Vec<StreamEvent>) {
+        let (events, mut received) = tokio::sync::mpsc::channel(1);
+        let collecting = tokio::spawn(async move {
+            let mut collected = Vec::new();
+            while let Some(event) = received.recv().await {
+                collected.push(event);
+            }
+            collected
+        });
+        let response = service
+            .relay_http_request_stream(request, events)
+            .await
+            .unwrap();
+        (response, collecting.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_e2e_streamed_response() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let expected = "line 0\nline 1\nline 2\nline 3\nline 4\n";
+
+        let request = Request {
+            url: format!("{}/chunked", server_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+        let (response, events) = stream_events(&service, request.clone()).await;
+
+        let StreamEvent::Headers { status_code, .. } = &events[0] else {
+            panic!("expected headers first, got {:?}", events[0]);
+        };
+        assert_eq!(*status_code, 200);
+        let mut streamed = Vec::new();
+        for event in &events[1..] {
+            let StreamEvent::Chunk { data, offset } = event else {
+                panic!("expected a chunk, got {:?}", event);
+            };
+            assert_eq!(*offset, streamed.len() as u64);
+            streamed.extend(STANDARD.decode(data).unwrap());
+        }
+        assert!(events.len() > 2, "body arrived in one chunk");
+        assert_eq!(String::from_utf8(streamed).unwrap(), expected);
+
+        let resp = response.response.unwrap();
+        assert_eq!(resp.content, expected);
+        assert!(!resp.body_truncated);
+
+        // Only the start of the body is kept beyond the retain limit
+        let request = Request {
+            retain_limit: Some(10),
+            ..request
+        };
+        let (response, events) = stream_events(&service, request).await;
+        let resp = response.response.unwrap();
+        assert_eq!(resp.content, &expected[..10]);
+        assert!(resp.body_truncated);
+        assert_eq!(resp.performance.transfer_size, expected.len());
+        assert_eq!(resp.performance.decoded_size, expected.len());
+        assert_eq!(events.len() - 1, 5);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_streamed_response_is_decoded() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let expected = "line 0\nline 1\nline 2\nline 3\nline 4\n";
+        let request = Request {
+            url: format!("{}/chunked-gzip", server_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+
+        let (response, events) = stream_events(&service, request.clone()).await;
+        let StreamEvent::Headers { headers, .. } = &events[0] else {
+            panic!("expected headers first, got {:?}", events[0]);
+        };
+        assert_eq!(headers["content-encoding"].value, "gzip");
+        let mut streamed = Vec::new();
+        for event in &events[1..] {
+            let StreamEvent::Chunk { data, offset } = event else {
+                panic!("expected a chunk, got {:?}", event);
+            };
+            assert_eq!(*offset, streamed.len() as u64);
+            streamed.extend(STANDARD.decode(data).unwrap());
+        }
+        assert_eq!(String::from_utf8(streamed).unwrap(), expected);
+        let resp = response.response.unwrap();
+        assert_eq!(resp.content, expected);
+        assert_ne!(resp.performance.transfer_size, expected.len());
+        assert_eq!(resp.performance.decoded_size, expected.len());
+
+        // Truncated bodies are decoded too
+        let request = Request {
+            retain_limit: Some(10),
+            ..request.clone()
+        };
+        let (response, _) = stream_events(&service, request.clone()).await;
+        let resp = response.response.unwrap();
+        assert_eq!(resp.content, &expected[..10]);
+        assert!(resp.body_truncated);
+        assert_eq!(resp.performance.decoded_size, expected.len());
+
+        // Raw bodies are streamed as received
+        let request = Request {
+            raw_body: true,
+            retain_limit: None,
+            ..request
+        };
+        let (response, events) = stream_events(&service, request).await;
+        let mut streamed = Vec::new();
+        for event in &events[1..] {
+            if let StreamEvent::Chunk { data, .. } = event {
+                streamed.extend(STANDARD.decode(data).unwrap());
+            }
+        }
+        assert!(streamed.starts_with(&[0x1f, 0x8b]));
+        let resp = response.response.unwrap();
+        assert_eq!(resp.performance.decoded_size, streamed.len());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_streamed_response_backpressure() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let request = Request {
+            url: format!("{}/large", server_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+
+        // Nothing reads the events, so the relay stops once the buffer is full
+        let (events, mut received) = tokio::sync::mpsc::channel(1);
+        let relaying = tokio::spawn({
+            let service = service.clone();
+            let request = request.clone();
+            async move { service.relay_http_request_stream(request, events).await }
+        });
+        sleep(Duration::from_millis(200)).await;
+        assert!(!relaying.is_finished());
+
+        let mut size = 0;
+        while let Some(event) = received.recv().await {
+            if let StreamEvent::Chunk { data, .. } = event {
+                size += STANDARD.decode(data).unwrap().len();
+            }
+        }
+        let response = relaying.await.unwrap().unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(size, 500 * 1024);
+
+        // A receiver that goes away fails the request
+        let (events, received) = tokio::sync::mpsc::channel(1);
+        drop(received);
+        let response = service
+            .relay_http_request_stream(request, events)
+            .await
+            .unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(
+            response.message.as_deref(),
+            Some("Response stream closed by the receiver")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_streamed_response_waits_for_credits() {
+        use crate::stream_credits::{forward, StreamCreditRegistry};
+
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let request = Request {
+            url: format!("{}/large", server_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+
+        // Events are forwarded the way the Tauri command does, with a frontend
+        // that doesn't acknowledge them yet
+        let registry = StreamCreditRegistry::default();
+        let credits = Arc::new(registry.open("download".to_string(), 2));
+        let (events, received) = tokio::sync::mpsc::channel(1);
+        let forwarded = Arc::new(Mutex::new(Vec::new()));
+        let forwarding = tokio::spawn(forward(received, Some(credits.clone()), {
+            let forwarded = forwarded.clone();
+            move |event| {
+                forwarded.lock().unwrap().push(event);
+                true
+            }
+        }));
+        let relaying = tokio::spawn({
+            let service = service.clone();
+            async move { service.relay_http_request_stream(request, events).await }
+        });
+        sleep(Duration::from_millis(200)).await;
+        assert!(!relaying.is_finished());
+        assert_eq!(forwarded.lock().unwrap().len(), 2);
+
+        // Acknowledging events lets the relay read on
+        while !relaying.is_finished() {
+            registry.grant("download", 4);
+            sleep(Duration::from_millis(10)).await;
+        }
+        let response = relaying.await.unwrap().unwrap();
+        assert_eq!(response.status, "success");
+        credits.release();
+        forwarding.await.unwrap();
+
+        let size: usize = forwarded
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| match event {
+                StreamEvent::Chunk { data, .. } => STANDARD.decode(data).unwrap().len(),
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(size, 500 * 1024);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_event_stream_reconnects() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let request = Request {
+            id: Some("event-stream".to_string()),
+            url: format!("{}/events", server_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+
+        let (events, mut received) = tokio::sync::mpsc::channel(16);
+        let streaming = tokio::spawn({
+            let service = service.clone();
+            async move { service.relay_event_stream(request, events).await }
+        });
+
+        let mut collected = Vec::new();
+        while collected.len() < 6 {
+            let event = tokio::time::timeout(Duration::from_secs(5), received.recv())
+                .await
+                .expect("event stream stalled")
+                .unwrap();
+            collected.push(event);
+        }
+        service.cancel_request("event-stream");
+        let response = streaming.await.unwrap().unwrap();
+        assert_eq!(response.status, "cancelled");
+
+        let EventStreamEvent::Open { status_code, .. } = &collected[0] else {
+            panic!("expected the stream to open, got {:?}", collected[0]);
+        };
+        assert_eq!(*status_code, 200);
+        let message = |event: &EventStreamEvent| match event {
+            EventStreamEvent::Message {
+                id, event, data, ..
+            } => (id.clone(), event.clone(), data.clone()),
+            other => panic!("expected a message, got {:?}", other),
+        };
+        assert_eq!(
+            message(&collected[1]),
+            ("1".to_string(), "greeting".to_string(), "first".to_string())
+        );
+        assert_eq!(
+            message(&collected[2]),
+            (
+                "2".to_string(),
+                "message".to_string(),
+                "second\nline".to_string()
+            )
+        );
+        let EventStreamEvent::Reconnecting {
+            delay,
+            last_event_id,
+            ..
+        } = &collected[3]
+        else {
+            panic!("expected a reconnection, got {:?}", collected[3]);
+        };
+        // The server's retry field replaces the default reconnection time
+        assert_eq!(*delay, 50);
+        assert_eq!(last_event_id, "2");
+        assert!(matches!(collected[4], EventStreamEvent::Open { .. }));
+        assert_eq!(
+            message(&collected[5]),
+            ("3".to_string(), "message".to_string(), "third".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_event_stream_rejects_other_responses() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+
+        for (path, expected) in [
+            (
+                "/hello",
+                "Expected a text/event-stream response, got text/plain; charset=utf-8",
+            ),
+            ("/status/503", "Event stream failed with status 503"),
+        ] {
+            let request = Request {
+                url: format!("{}{}", server_url, path),
+                method: RequestMethod::GET,
+                ..Default::default()
+            };
+            let (events, mut received) = tokio::sync::mpsc::channel(16);
+            let response = service.relay_event_stream(request, events).await.unwrap();
+            assert_eq!(response.status, "error");
+            assert_eq!(response.message.as_deref(), Some(expected));
+            assert!(received.recv().await.is_none());
+        }
+
+        // The server ends the stream for good with 204 No Content
+        let request = Request {
+            url: format!("{}/status/204", server_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+        let (events, _received) = tokio::sync::mpsc::channel(16);
+        let response = service.relay_event_stream(request, events).await.unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(
+            response.message.as_deref(),
+            Some("Event stream closed by the server")
+        );
+    }
+
+    /// Opens a WebSocket session in the background, returning its events
+    fn open_websocket(
+        service: &RelayService,
+        request: Request,
+    ) -> (
+        tokio::task::JoinHandle<Result<RelayResponse>>,
+        tokio::sync::mpsc::Receiver<WebSocketEvent>,
+    ) {
+        let (events, received) = tokio::sync::mpsc::channel(16);
+        let service = service.clone();
+        let session = tokio::spawn(async move { service.open_websocket(request, events).await });
+        (session, received)
+    }
+
+    async fn next_event(
+        received: &mut tokio::sync::mpsc::Receiver<WebSocketEvent>,
+    ) -> WebSocketEvent {
+        tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .expect("WebSocket session stalled")
+            .expect("WebSocket session ended")
+    }
+
+    #[tokio::test]
+    async fn test_e2e_websocket_echo() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let mut headers = HashMap::new();
+        headers.insert(
+            "Sec-WebSocket-Protocol".to_string(),
+            serde_json::Value::String("echo".to_string()),
+        );
+        let request = Request {
+            id: Some("ws-echo".to_string()),
+            url: format!("{}/ws", server_url.replace("http://", "ws://")),
+            method: RequestMethod::GET,
+            headers,
+            ..Default::default()
+        };
+        let (session, mut received) = open_websocket(&service, request);
+
+        let WebSocketEvent::Open {
+            status_code,
+            protocol,
+            ..
+        } = next_event(&mut received).await
+        else {
+            panic!("expected the session to open first");
+        };
+        assert_eq!(status_code, 101);
+        assert_eq!(protocol.as_deref(), Some("echo"));
+
+        service
+            .send_websocket_message(
+                "ws-echo",
+                WebSocketMessage::Text {
+                    data: "hello".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut received).await,
+            WebSocketEvent::Text { ref data, .. } if data == "hello"
+        ));
+
+        service
+            .send_websocket_message(
+                "ws-echo",
+                WebSocketMessage::Binary {
+                    data: STANDARD.encode([0, 1, 2, 255]),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut received).await,
+            WebSocketEvent::Binary { ref data, .. } if STANDARD.decode(data).unwrap() == [0, 1, 2, 255]
+        ));
+
+        // The server answers pings with the same payload
+        service
+            .send_websocket_message(
+                "ws-echo",
+                WebSocketMessage::Ping {
+                    data: STANDARD.encode("are you there"),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut received).await,
+            WebSocketEvent::Pong { ref data, .. } if *data == STANDARD.encode("are you there")
+        ));
+
+        service
+            .close_websocket("ws-echo", Some(1000), "done")
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut received).await,
+            WebSocketEvent::Close { code: Some(1000), ref reason, .. } if reason == "done"
+        ));
+        let response = session.await.unwrap().unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(
+            response.message.as_deref(),
+            Some("WebSocket session closed")
+        );
+
+        let error = service
+            .send_websocket_message(
+                "ws-echo",
+                WebSocketMessage::Text {
+                    data: "too late".to_string(),
+                },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "No open WebSocket session with id ws-echo"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_websocket_server_close_and_failures() {
+        let server_url = start_test_server().await;
+        let ws_url = server_url.replace("http://", "ws://");
+        let service = RelayService::new();
+
+        let request = Request {
+            id: Some("ws-bye".to_string()),
+            url: format!("{}/ws", ws_url),
+            method: RequestMethod::GET,
+            ..Default::default()
+        };
+        let (session, mut received) = open_websocket(&service, request);
+        let WebSocketEvent::Open { protocol, .. } = next_event(&mut received).await else {
+            panic!("expected the session to open first");
+        };
+        assert_eq!(protocol, None);
+        service
+            .send_websocket_message(
+                "ws-bye",
+                WebSocketMessage::Text {
+                    data: "bye".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            next_event(&mut received).await,
+            WebSocketEvent::Close { code: Some(4000), ref reason, .. } if reason == "server says bye"
+        ));
+        assert_eq!(session.await.unwrap().unwrap().status, "success");
+
+        // Endpoints that don't upgrade, and sessions without an id, fail
+        for (id, url, expected) in [
+            (
+                Some("ws-plain".to_string()),
+                format!("{}/hello", ws_url),
+                "WebSocket upgrade failed with status 200",
+            ),
+            (
+                None,
+                format!("{}/ws", ws_url),
+                "WebSocket sessions need a request id",
+            ),
+            (
+                Some("ws-http".to_string()),
+                format!("{}/ws", server_url),
+                "Unsupported WebSocket scheme http",
+            ),
+        ] {
+            let request = Request {
+                id,
+                url,
+                method: RequestMethod::GET,
+                ..Default::default()
+            };
+            let (session, mut received) = open_websocket(&service, request);
+            let response = session.await.unwrap().unwrap();
+            assert_eq!(response.status, "error");
+            assert_eq!(response.message.as_deref(), Some(expected));
+            assert!(received.recv().await.is_none());
+        }
+    }
+
+    fn redirected_request(
+        url: String,
+        method: RequestMethod,
+        redirect_policy: RedirectPolicy,
+    ) -> Request {
+        Request {
+            url,
+            method,
+            body: RequestBody {
+                content_type: Some("text/plain".to_string()),
+                content: Some("payload".to_string()),
+                ..Default::default()
+            },
+            authorization: Authorization::Bearer {
+                token: "secret-token".to_string(),
+            },
+            redirect_policy,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_redirects_rewrite_methods() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let follow = RedirectPolicy::Follow { max_redirects: 10 };
+
+        for (code, method, expected_method, body_kept) in [
+            (301, RequestMethod::POST, "GET", false),
+            (302, RequestMethod::POST, "GET", false),
+            (302, RequestMethod::PUT, "PUT", true),
+            (303, RequestMethod::PUT, "GET", false),
+            (307, RequestMethod::POST, "POST", true),
+            (308, RequestMethod::PATCH, "PATCH", true),
+        ] {
+            let url = format!("{}/redirect/{}?to=/inspect", server_url, code);
+            let response = service
+                .relay_http_request(redirected_request(url.clone(), method, follow))
+                .await
+                .unwrap()
+                .response
+                .unwrap();
+            assert_eq!(response.status_code, 200);
+            let inspected: serde_json::Value = serde_json::from_str(&response.content).unwrap();
+            assert_eq!(inspected["method"], expected_method, "{} redirect", code);
+            assert_eq!(
+                inspected["body"],
+                if body_kept { "payload" } else { "" },
+                "{} redirect",
+                code
+            );
+            assert_eq!(inspected["contentType"].is_string(), body_kept);
+            // Same-origin hops keep the credentials
+            assert_eq!(inspected["authorization"], "Bearer secret-token");
+
+            let hop = &response.redirects[0];
+            assert_eq!(response.redirects.len(), 1);
+            assert_eq!(hop.status_code, code);
+            assert_eq!(hop.url, url);
+            assert_eq!(hop.location, format!("{}/inspect", server_url));
+            assert_eq!(hop.headers["location"].value, "/inspect");
+            assert!(hop.duration > 0.0);
+        }
+
+        // Without a policy the redirect is the response
+        let response = service
+            .relay_http_request(redirected_request(
+                format!("{}/redirect/301?to=/inspect", server_url),
+                RequestMethod::GET,
+                RedirectPolicy::Off,
+            ))
+            .await
+            .unwrap()
+            .response
+            .unwrap();
+        assert_eq!(response.status_code, 301);
+        assert_eq!(response.content, "Moved");
+        assert!(response.redirects.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_redirect_limits_and_origins() {
+        let server_url = start_test_server().await;
+        let other_url = start_test_server().await;
+        let service = RelayService::new();
+
+        let chain = |max_redirects| {
+            redirected_request(
+                format!("{}/chain/3", server_url),
+                RequestMethod::GET,
+                RedirectPolicy::Follow { max_redirects },
+            )
+        };
+        let response = service.relay_http_request(chain(3)).await.unwrap();
+        let response = response.response.unwrap();
+        assert_eq!(response.content, "End of the chain");
+        let locations: Vec<_> = response
+            .redirects
+            .iter()
+            .map(|hop| hop.location.trim_start_matches(&server_url))
+            .collect();
+        assert_eq!(locations, ["/chain/2", "/chain/1", "/chain/0"]);
+        let response = service.relay_http_request(chain(2)).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(
+            response.message.as_deref(),
+            Some("Stopped after 2 redirects")
+        );
+
+        // Another port is another origin, which gets no credentials
+        let cross_origin = format!("{}/redirect/307?to={}/inspect", server_url, other_url);
+        let response = service
+            .relay_http_request(redirected_request(
+                cross_origin.clone(),
+                RequestMethod::POST,
+                RedirectPolicy::Follow { max_redirects: 10 },
+            ))
+            .await
+            .unwrap()
+            .response
+            .unwrap();
+        let inspected: serde_json::Value = serde_json::from_str(&response.content).unwrap();
+        assert_eq!(inspected["authorization"], serde_json::Value::Null);
+        assert_eq!(inspected["body"], "payload");
+
+        let response = service
+            .relay_http_request(redirected_request(
+                cross_origin,
+                RequestMethod::POST,
+                RedirectPolicy::SameOrigin { max_redirects: 10 },
+            ))
+            .await
+            .unwrap()
+            .response
+            .unwrap();
+        assert_eq!(response.status_code, 307);
+        assert!(response.redirects.is_empty());
+    }
+
+    fn sigv4_authorization() -> Authorization {
+        Authorization::AwsSigV4 {
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "secret".to_string(),
+            session_token: Some("session-token".to_string()),
+            region: "eu-west-1".to_string(),
+            service: "s3".to_string(),
+            unsigned_payload: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_redirect_credentials() {
+        use sha2::{Digest, Sha256};
+
+        let server_url = start_test_server().await;
+        let other_url = start_test_server().await;
+        let service = RelayService::new();
+        let api_key = Authorization::ApiKey {
+            key: "api-secret".to_string(),
+            name: "X-Api-Key".to_string(),
+            location: ApiKeyLocation::Header,
+        };
+        let inspect = |url: String, authorization: Authorization| {
+            let service = &service;
+            async move {
+                let follow = RedirectPolicy::Follow { max_redirects: 10 };
+                let mut request = redirected_request(url, RequestMethod::POST, follow);
+                request.authorization = authorization;
+                let response = service.relay_http_request(request).await.unwrap();
+                let response = response.response.unwrap();
+                assert_eq!(response.redirects.len(), 1);
+                serde_json::from_str::<serde_json::Value>(&response.content).unwrap()
+            }
+        };
+
+        // Neither the API key nor the signature reach another origin
+        let cross_origin = format!("{}/redirect/307?to={}/inspect", server_url, other_url);
+        let inspected = inspect(cross_origin.clone(), api_key.clone()).await;
+        assert_eq!(inspected["apiKey"], serde_json::Value::Null);
+        assert_eq!(inspected["body"], "payload");
+        let inspected = inspect(cross_origin.clone(), sigv4_authorization()).await;
+        assert_eq!(inspected["host"], other_url.trim_start_matches("http://"));
+        for field in [
+            "authorization",
+            "amzDate",
+            "amzSecurityToken",
+            "amzContentSha256",
+        ] {
+            assert_eq!(inspected[field], serde_json::Value::Null, "{}", field);
+        }
+
+        // A user-set Host doesn't follow the redirect either
+        let mut request = redirected_request(
+            cross_origin,
+            RequestMethod::GET,
+            RedirectPolicy::Follow { max_redirects: 10 },
+        );
+        request.headers.insert(
+            "Host".to_string(),
+            serde_json::Value::String(server_url.trim_start_matches("http://").to_string()),
+        );
+        let response = service.relay_http_request(request).await.unwrap();
+        let inspected: serde_json::Value =
+            serde_json::from_str(&response.response.unwrap().content).unwrap();
+        assert_eq!(inspected["host"], other_url.trim_start_matches("http://"));
+
+        // On the same origin the API key is kept and the request signed again
+        // for the GET without a body it became
+        let same_origin = format!("{}/redirect/303?to=/inspect", server_url);
+        let inspected = inspect(same_origin.clone(), api_key).await;
+        assert_eq!(inspected["apiKey"], "api-secret");
+        let inspected = inspect(same_origin, sigv4_authorization()).await;
+        assert_eq!(inspected["method"], "GET");
+        assert_eq!(inspected["amzSecurityToken"], "session-token");
+        assert_eq!(
+            inspected["amzContentSha256"],
+            hex::encode(Sha256::digest(b""))
+        );
+        let authorization = inspected["authorization"].as_str().unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(!authorization.contains("content-type"));
+
+        // A Digest answer is computed again for the redirected URI
+        let request = Request {
+            url: format!("{}/digest?next=/digest", server_url),
+            method: RequestMethod::POST,
+            body: RequestBody {
+                content_type: Some("text/plain".to_string()),
+                content: Some("digest me".to_string()),
+                ..Default::default()
+            },
+            authorization: Authorization::Digest {
+                username: "admin".to_string(),
+                password: "secret".to_string(),
+            },
+            redirect_policy: RedirectPolicy::Follow { max_redirects: 10 },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        let response = response.response.unwrap();
+        assert_eq!(response.redirects.len(), 1);
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.content, "Authorized via digest");
+    }
+
+    fn cookie_request(url: String, workspace_id: Option<&str>) -> Request {
+        Request {
+            url,
+            workspace_id: workspace_id.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    /// The `Cookie` header `/inspect` received.
+    async fn sent_cookies(service: &RelayService, request: Request) -> Option<String> {
+        let response = service.relay_http_request(request).await.unwrap();
+        let inspected: serde_json::Value =
+            serde_json::from_str(&response.response.unwrap().content).unwrap();
+        inspected["cookie"].as_str().map(str::to_string)
+    }
+
+    #[tokio::test]
+    async fn test_e2e_cookies_are_stored_and_sent() {
+        let server_url = start_test_server().await;
+        let service = RelayService::new();
+        let set_cookies = |workspace_id, cookies: &[&str]| {
+            let mut url = Url::parse(&format!("{}/set-cookie", server_url)).unwrap();
+            for cookie in cookies {
+                url.query_pairs_mut().append_pair("cookie", cookie);
+            }
+            cookie_request(url.to_string(), workspace_id)
+        };
+
+        let response = service
+            .relay_http_request(set_cookies(
+                None,
+                &["session=abc; Path=/; HttpOnly", "theme=dark; Path=/docs"],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().content, "Cookies set");
+        let inspect = format!("{}/inspect", server_url);
+        assert_eq!(
+            sent_cookies(&service, cookie_request(inspect.clone(), None))
+                .await
+                .as_deref(),
+            Some("session=abc")
+        );
+
+        // A Cookie header set on the request replaces the jar's
+        let mut own = cookie_request(inspect.clone(), None);
+        own.headers
+            .insert("Cookie".to_string(), serde_json::json!("mine=1"));
+        assert_eq!(sent_cookies(&service, own).await.as_deref(), Some("mine=1"));
+
+        // Workspaces have jars of their own
+        assert_eq!(
+            sent_cookies(&service, cookie_request(inspect.clone(), Some("work"))).await,
+            None
+        );
+        service
+            .relay_http_request(set_cookies(Some("work"), &["team=blue"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            sent_cookies(&service, cookie_request(inspect.clone(), Some("work")))
+                .await
+                .as_deref(),
+            Some("team=blue")
+        );
+
+        // An expired cookie deletes the stored one
+        service
+            .relay_http_request(set_cookies(None, &["session=; Path=/; Max-Age=0"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            sent_cookies(&service, cookie_request(inspect, None)).await,
+            None
+        );
+        let names: Vec<_> = service
+            .cookies()
+            .list(None)
+            .into_iter()
+            .map(|cookie| cookie.name)
+            .collect();
+        assert_eq!(names, ["theme"]);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_cookie_jars_persist() {
+        let server_url = start_test_server().await;
+        let path =
+            std::env::temp_dir().join(format!("relay-e2e-cookies-{}.json", std::process::id()));
+        let service =
+            RelayService::new().with_cookie_store(CookieStore::load(path.clone(), Arc::new(|_| {})).unwrap());
+
+        // Imported cookies are sent, and saved for the next session
+        let imported = service
+            .cookies()
+            .import_netscape(
+                Some("work"),
+                "127.0.0.1\tFALSE\t/\tFALSE\t0\tsession\timported\n",
+            )
+            .unwrap();
+        assert_eq!(imported, 1);
+        service.cookies().flush();
+        let inspect = format!("{}/inspect", server_url);
+        let restarted =
+            RelayService::new().with_cookie_store(CookieStore::load(path.clone(), Arc::new(|_| {})).unwrap());
+        let sent = sent_cookies(&restarted, cookie_request(inspect.clone(), Some("work"))).await;
+        assert_eq!(sent.as_deref(), Some("session=imported"));
+
+        restarted.cookies().clear(Some("work"));
+        restarted.cookies().flush();
+        let reloaded = CookieStore::load(path.clone(), Arc::new(|_| {})).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(reloaded.list(Some("work")).is_empty());
+    }
+
+    type ProxyLog = Arc<Mutex<Vec<String>>>;
+
+    /// Starts a stand-in proxy speaking just enough HTTP and SOCKS5, which
+    /// wants the username `user` and password `pass` if `authenticate` is
+    /// set. Returns its URL and what it was asked for, e.g.
+    /// `CONNECT 127.0.0.1:1234`.
+    async fn start_test_proxy(scheme: &str, authenticate: bool) -> (String, ProxyLog) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind test proxy");
+        let url = format!("{}://{}", scheme, listener.local_addr().unwrap());
+        let log = ProxyLog::default();
+        let seen = log.clone();
+        tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let seen = seen.clone();
+                tokio::spawn(async move {
+                    let _ = serve_proxy_connection(client, authenticate, seen).await;
+                });
+            }
+        });
+        (url, log)
+    }
+
+    async fn serve_proxy_connection(
+        mut client: tokio::net::TcpStream,
+        authenticate: bool,
+        log: ProxyLog,
+    ) -> std::io::Result<()> {
+        use tokio::io::{copy_bidirectional, AsyncReadExt, AsyncWriteExt};
+
+        let first = client.read_u8().await?;
+        let target = if first == 0x05 {
+            let mut methods = vec![0; client.read_u8().await? as usize];
+            client.read_exact(&mut methods).await?;
+            if !authenticate {
+                client.write_all(&[0x05, 0x00]).await?;
+            } else if methods.contains(&0x02) {
+                client.write_all(&[0x05, 0x02]).await?;
+                client.read_u8().await?;
+                let mut username = vec![0; client.read_u8().await? as usize];
+                client.read_exact(&mut username).await?;
+                let mut password = vec![0; client.read_u8().await? as usize];
+                client.read_exact(&mut password).await?;
+                let accepted = username == b"user" && password == b"pass";
+                client.write_all(&[0x01, u8::from(!accepted)]).await?;
+                if !accepted {
+                    return Ok(());
+                }
+            } else {
+                return client.write_all(&[0x05, 0xff]).await;
+            }
+
+            let mut request = [0; 4];
+            client.read_exact(&mut request).await?;
+            let host = match request[3] {
+                0x01 => std::net::Ipv4Addr::from(client.read_u32().await?).to_string(),
+                0x03 => {
+                    let mut name = vec![0; client.read_u8().await? as usize];
+                    client.read_exact(&mut name).await?;
+                    String::from_utf8(name).unwrap()
+                }
+                _ => format!("[{}]", std::net::Ipv6Addr::from(client.read_u128().await?)),
+            };
+            let target = format!("{}:{}", host, client.read_u16().await?);
+            log.lock().unwrap().push(format!("SOCKS5 {}", target));
+            client
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await?;
+            target
+        } else {
+            let mut head = vec![first];
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(client.read_u8().await?);
+            }
+            let head = String::from_utf8(head).unwrap();
+            let request_line = head.lines().next().unwrap();
+            let (method, target) = request_line
+                .trim_end_matches(" HTTP/1.1")
+                .split_once(' ')
+                .unwrap();
+            log.lock().unwrap().push(format!("{} {}", method, target));
+            let authorized = !authenticate
+                || head.lines().any(|line| {
+                    line.eq_ignore_ascii_case("proxy-authorization: basic dXNlcjpwYXNz")
+                });
+            if !authorized {
+                return client
+                    .write_all(
+                        b"HTTP/1.1 407 Proxy Authentication Required\r\ncontent-length: 0\r\n\r\n",
+                    )
+                    .await;
+            }
+            if method != "CONNECT" {
+                // Forwarded as received, since the test server takes absolute URIs
+                let authority = target
+                    .parse::<Uri>()
+                    .unwrap()
+                    .authority()
+                    .unwrap()
+                    .to_string();
+                let mut server = tokio::net::TcpStream::connect(authority).await?;
+                server.write_all(head.as_bytes()).await?;
+                copy_bidirectional(&mut client, &mut server).await?;
+                return Ok(());
+            }
+            client
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await?;
+            target.to_string()
+        };
+
+        let mut server = tokio::net::TcpStream::connect(target).await?;
+        copy_bidirectional(&mut client, &mut server).await?;
+        Ok(())
+    }
+
+    fn custom_proxy(url: &str, credentials: Option<(&str, &str)>) -> ProxySetting {
+        ProxySetting::Custom {
+            url: url.to_string(),
+            username: credentials.map(|(username, _)| username.to_string()),
+            password: credentials.map(|(_, password)| password.to_string()),
+            no_proxy: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_http_proxy() {
+        let server_url = start_test_server().await;
+        let server = server_url.trim_start_matches("http://").to_string();
+        let (proxy_url, log) = start_test_proxy("http", true).await;
+        let service = RelayService::new().with_environment_proxies(EnvironmentProxies::default());
+        let inspect = |proxy| Request {
+            url: format!("{}/inspect", server_url),
+            proxy,
+            ..Default::default()
+        };
+
+        // Plain HTTP is forwarded, with the proxy credentials on each request
+        let response = service
+            .relay_http_request(inspect(custom_proxy(&proxy_url, Some(("user", "pass")))))
+            .await
+            .unwrap();
+        let response = response.response.unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            log.lock().unwrap().as_slice(),
+            [format!("GET http://{}/inspect", server)]
+        );
+        let response = service
+            .relay_http_request(inspect(custom_proxy(&proxy_url, None)))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().status_code, 407);
+
+        // WebSocket upgrades go through a CONNECT tunnel
+        let request = Request {
+            id: Some("ws-proxied".to_string()),
+            url: format!("ws://{}/ws", server),
+            proxy: custom_proxy(&proxy_url, Some(("user", "pass"))),
+            ..Default::default()
+        };
+        let (session, mut received) = open_websocket(&service, request);
+        assert!(matches!(
+            next_event(&mut received).await,
+            WebSocketEvent::Open {
+                status_code: 101,
+                ..
+            }
+        ));
+        service
+            .close_websocket("ws-proxied", None, "")
+            .await
+            .unwrap();
+        assert_eq!(session.await.unwrap().unwrap().status, "success");
+        assert_eq!(
+            log.lock().unwrap().last(),
+            Some(&format!("CONNECT {}", server))
+        );
+
+        let request = Request {
+            id: Some("ws-refused".to_string()),
+            url: format!("ws://{}/ws", server),
+            proxy: custom_proxy(&proxy_url, None),
+            ..Default::default()
+        };
+        let (session, _received) = open_websocket(&service, request);
+        let response = session.await.unwrap().unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::Proxy));
+        assert_eq!(
+            response.message.unwrap(),
+            format!(
+                "Proxy {} refused the tunnel to {}: 407 Proxy Authentication Required",
+                proxy_url, server
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_oauth2_token_request_uses_its_own_proxy() {
+        let server_url = start_test_server().await;
+        let port = server_url.rsplit(':').next().unwrap();
+        let (proxy_url, log) = start_test_proxy("http", false).await;
+        let service = RelayService::new().with_environment_proxies(EnvironmentProxies::default());
+
+        // The API host is bypassed but the token endpoint's isn't
+        let token_url = format!("http://localhost:{}/oauth/token", port);
+        let request = Request {
+            url: format!("{}/auth", server_url),
+            authorization: Authorization::OAuth2 {
+                token: String::new(),
+                flow: Some(OAuth2Flow {
+                    token_url: token_url.clone(),
+                    client_id: "clinic".to_string(),
+                    client_secret: Some("s3cret".to_string()),
+                    scope: None,
+                    client_authentication: OAuth2ClientAuthentication::Basic,
+                    grant: OAuth2Grant::ClientCredentials,
+                }),
+            },
+            proxy: ProxySetting::Custom {
+                url: proxy_url,
+                username: None,
+                password: None,
+                no_proxy: Some("127.0.0.1".to_string()),
+            },
+            ..Default::default()
+        };
+        let response = service.relay_http_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().content, "Authorized");
+        assert_eq!(
+            log.lock().unwrap().as_slice(),
+            [format!("POST {}", token_url)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_socks5_proxy_and_service_wide_setting() {
+        let server_url = start_test_server().await;
+        let port = Url::parse(&server_url).unwrap().port().unwrap();
+        let (socks5_url, log) = start_test_proxy("socks5", true).await;
+        let socks5h_url = socks5_url.replace("socks5", "socks5h");
+        let service = RelayService::new().with_environment_proxies(EnvironmentProxies::default());
+        let hello = |host: &str, proxy| Request {
+            url: format!("http://{}:{}/hello", host, port),
+            proxy,
+            ..Default::default()
+        };
+
+        // socks5h leaves resolving host names to the proxy
+        let response = service
+            .relay_http_request(hello(
+                "localhost",
+                custom_proxy(&socks5h_url, Some(("user", "pass"))),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().content, "Hello, World!");
+        assert_eq!(
+            log.lock().unwrap().as_slice(),
+            [format!("SOCKS5 localhost:{}", port)]
+        );
+
+        let response = service
+            .relay_http_request(hello(
+                "127.0.0.1",
+                custom_proxy(&socks5_url, Some(("user", "wrong"))),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::Proxy));
+        assert_eq!(
+            response.message.unwrap(),
+            format!("Proxy {} rejected the username or password", socks5_url)
+        );
+
+        // The service-wide proxy applies to requests without a setting of
+        // their own, except for hosts in its NO_PROXY list
+        service
+            .set_default_proxy(ProxySetting::Custom {
+                url: socks5_url.replace("://", "://user:pass@"),
+                username: None,
+                password: None,
+                no_proxy: Some("localhost".to_string()),
+            })
+            .unwrap();
+        log.lock().unwrap().clear();
+        for host in ["127.0.0.1", "localhost"] {
+            let response = service
+                .relay_http_request(hello(host, ProxySetting::Default))
+                .await
+                .unwrap();
+            assert_eq!(response.response.unwrap().content, "Hello, World!");
+        }
+        let response = service
+            .relay_http_request(hello("127.0.0.1", ProxySetting::Direct))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "success");
+        assert_eq!(
+            log.lock().unwrap().as_slice(),
+            [format!("SOCKS5 127.0.0.1:{}", port)]
+        );
+        assert!(service
+            .set_default_proxy(custom_proxy("https://proxy", None))
+            .is_err());
+    }
+
+    type TestCertificate = (rcgen::Certificate, rcgen::KeyPair);
+
+    fn test_ca() -> TestCertificate {
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Relay Test CA");
+        let key = rcgen::KeyPair::generate().unwrap();
+        (params.self_signed(&key).unwrap(), key)
+    }
+
+    /// A certificate for `name` issued by `ca`, or self-signed, which expired
+    /// years ago if `expired` is set.
+    fn test_certificate(
+        name: &str,
+        ca: Option<&TestCertificate>,
+        expired: bool,
+    ) -> TestCertificate {
+        let mut params = rcgen::CertificateParams::new(vec![name.to_string()]).unwrap();
+        if expired {
+            params.not_before = rcgen::date_time_ymd(2020, 1, 1);
+            params.not_after = rcgen::date_time_ymd(2021, 1, 1);
+        }
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = match ca {
+            Some((ca_cert, ca_key)) => params.signed_by(&key, ca_cert, ca_key).unwrap(),
+            None => params.self_signed(&key).unwrap(),
+        };
+        (cert, key)
+    }
+
+    /// Starts an HTTPS server presenting `certificate`, which answers every
+    /// request with "Hello over TLS". With a `client_ca`, clients must present
+    /// a certificate it issued, whose fingerprint is added to the answer.
+    /// Returns its port.
+    async fn start_tls_server(
+        certificate: &TestCertificate,
+        client_ca: Option<&TestCertificate>,
+    ) -> u16 {
+        use http_body_util::Full;
+        use hyper::server::conn::http1;
+        use hyper::service::service_fn;
+        use hyper_util::rt::TokioIo;
+
+        let (cert, key) = certificate;
+        let builder = rustls::ServerConfig::builder();
+        let builder = match client_ca {
+            Some((ca_cert, _)) => {
+                let mut roots = RootCertStore::empty();
+                roots.add(ca_cert.der().clone()).unwrap();
+                let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+                    .build()
+                    .unwrap();
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(
+                vec![cert.der().clone()],
+                rustls::pki_types::PrivateKeyDer::Pkcs8(key.serialize_der().into()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("Failed to bind TLS test server");
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    // Handshakes rejected by the client just end the connection
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let greeting = match stream.get_ref().1.peer_certificates() {
+                        Some([client, ..]) => {
+                            format!("Hello over TLS, {}", hex::encode(tls::fingerprint(client)))
+                        }
+                        _ => "Hello over TLS".to_string(),
+                    };
+                    let hello = service_fn(move |_| {
+                        let greeting = greeting.clone();
+                        async move {
+                            Ok::<_, std::convert::Infallible>(hyper::Response::new(Full::new(
+                                Bytes::from(greeting),
+                            )))
+                        }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), hello)
+                        .await;
+                });
+            }
+        });
+        port
+    }
+
+    fn tls_request(host: &str, port: u16, workspace_id: Option<&str>) -> Request {
+        Request {
+            url: format!("https://{}:{}/", host, port),
+            workspace_id: workspace_id.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_tls_trust_settings() {
+        let ca = test_ca();
+        let port = start_tls_server(&test_certificate("localhost", Some(&ca), false), None).await;
+        let ca_file =
+            std::env::temp_dir().join(format!("relay-test-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca_file, ca.0.pem()).unwrap();
+        let service = RelayService::new();
+
+        // The test CA isn't one the system trusts
+        let response = service
+            .relay_http_request(tls_request("localhost", port, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TlsUnknownIssuer));
+        assert!(response
+            .message
+            .unwrap()
+            .starts_with("TLS handshake with localhost failed: invalid peer certificate"));
+
+        // A workspace trusting it can connect, others still can't
+        let settings = TlsSettings {
+            ca_files: vec![ca_file.display().to_string()],
+            ..Default::default()
+        };
+        service
+            .set_tls_settings(Some("work"), settings.clone())
+            .unwrap();
+        assert_eq!(service.tls_settings(Some("work")), settings);
+        let response = service
+            .relay_http_request(tls_request("localhost", port, Some("work")))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().content, "Hello over TLS");
+        let response = service
+            .relay_http_request(tls_request("localhost", port, None))
+            .await
+            .unwrap();
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TlsUnknownIssuer));
+
+        // Trusting the issuer doesn't make the certificate good for other names
+        let response = service
+            .relay_http_request(tls_request("127.0.0.1", port, Some("work")))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TlsNameMismatch));
+
+        // Settings that can't be used are refused, keeping the previous ones
+        std::fs::remove_file(&ca_file).unwrap();
+        let error = service
+            .set_tls_settings(Some("work"), settings.clone())
+            .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("Failed to load CA file {}", ca_file.display())));
+        assert_eq!(service.tls_settings(Some("work")), settings);
+        let response = service
+            .relay_http_request(tls_request("localhost", port, Some("work")))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "success");
+    }
+
+    #[tokio::test]
+    async fn test_e2e_pinned_and_unverified_certificates() {
+        let self_signed = test_certificate("localhost", None, false);
+        let port = start_tls_server(&self_signed, None).await;
+        let service = RelayService::new();
+        let pinned = |fingerprint: String| TlsSettings {
+            pinned_fingerprints: vec![fingerprint],
+            ..Default::default()
+        };
+        let insecure = TlsSettings {
+            skip_verification: true,
+            ..Default::default()
+        };
+        let fingerprint = hex::encode(tls::fingerprint(self_signed.0.der()));
+        service
+            .set_tls_settings(Some("pinned"), pinned(fingerprint.to_uppercase()))
+            .unwrap();
+        service
+            .set_tls_settings(Some("wrong-pin"), pinned(hex::encode([0; 32])))
+            .unwrap();
+        service
+            .set_tls_settings(Some("insecure"), insecure.clone())
+            .unwrap();
+
+        // A pinned certificate is accepted without any CA vouching for it
+        let response = service
+            .relay_http_request(tls_request("localhost", port, Some("pinned")))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().content, "Hello over TLS");
+        let response = service
+            .relay_http_request(tls_request("localhost", port, Some("wrong-pin")))
+            .await
+            .unwrap();
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TlsUnknownIssuer));
+
+        // Skipping verification accepts any certificate for any name
+        for host in ["localhost", "127.0.0.1"] {
+            let response = service
+                .relay_http_request(tls_request(host, port, Some("insecure")))
+                .await
+                .unwrap();
+            assert_eq!(response.response.unwrap().content, "Hello over TLS");
+        }
+
+        // Certificates issued by a trusted CA still have to be current
+        let ca = test_ca();
+        let expired_port =
+            start_tls_server(&test_certificate("localhost", Some(&ca), true), None).await;
+        let ca_file =
+            std::env::temp_dir().join(format!("relay-test-expired-ca-{}.pem", std::process::id()));
+        std::fs::write(&ca_file, ca.0.pem()).unwrap();
+        let trusting = TlsSettings {
+            ca_files: vec![ca_file.display().to_string()],
+            ..Default::default()
+        };
+        service.set_tls_settings(Some("work"), trusting).unwrap();
+        let response = service
+            .relay_http_request(tls_request("localhost", expired_port, Some("work")))
+            .await
+            .unwrap();
+        std::fs::remove_file(&ca_file).unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(
+            response.error_kind,
+            Some(RelayErrorKind::TlsCertificateExpired)
+        );
+
+        // The default workspace's settings apply to requests without one, until
+        // they're reset
+        service.set_tls_settings(None, insecure).unwrap();
+        let response = service
+            .relay_http_request(tls_request("localhost", expired_port, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "success");
+        service
+            .set_tls_settings(None, TlsSettings::default())
+            .unwrap();
+        assert_eq!(service.tls_settings(None), TlsSettings::default());
+        let response = service
+            .relay_http_request(tls_request("localhost", port, None))
+            .await
+            .unwrap();
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TlsUnknownIssuer));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_client_certificates() {
+        let server_ca = test_ca();
+        let client_ca = test_ca();
+        let port = start_tls_server(
+            &test_certificate("localhost", Some(&server_ca), false),
+            Some(&client_ca),
+        )
+        .await;
+        let directory =
+            std::env::temp_dir().join(format!("relay-test-mtls-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).display().to_string();
+        std::fs::write(path("ca.pem"), server_ca.0.pem()).unwrap();
+        let service = RelayService::new();
+        service
+            .set_tls_settings(
+                Some("partner"),
+                TlsSettings {
+                    ca_files: vec![path("ca.pem")],
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let request = |client_certificate| Request {
+            client_certificate,
+            ..tls_request("localhost", port, Some("partner"))
+        };
+        let greeting = |(cert, _): &TestCertificate| {
+            format!(
+                "Hello over TLS, {}",
+                hex::encode(tls::fingerprint(cert.der()))
+            )
+        };
+
+        // PEM files, here with the key in the same file as the certificate
+        let alice = test_certificate("alice", Some(&client_ca), false);
+        std::fs::write(
+            path("alice.pem"),
+            format!("{}{}", alice.0.pem(), alice.1.serialize_pem()),
+        )
+        .unwrap();
+        let pem = ClientCertificate::Pem {
+            cert_file: path("alice.pem"),
+            key_file: path("alice.pem"),
+        };
+        let response = service
+            .relay_http_request(request(Some(pem.clone())))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().content, greeting(&alice));
+
+        // A PKCS#12 bundle, whose connections aren't shared with other
+        // certificates
+        let bob = test_certificate("bob", Some(&client_ca), false);
+        let mut store = p12_keystore::KeyStore::new();
+        store.add_entry(
+            "bob",
+            p12_keystore::KeyStoreEntry::PrivateKeyChain(p12_keystore::PrivateKeyChain::new(
+                bob.1.serialize_der(),
+                [1],
+                [p12_keystore::Certificate::from_der(bob.0.der()).unwrap()],
+            )),
+        );
+        std::fs::write(path("bob.p12"), store.writer("secret").write().unwrap()).unwrap();
+        let pkcs12 = |password: &str| ClientCertificate::Pkcs12 {
+            file: path("bob.p12"),
+            password: Some(password.to_string()),
+        };
+        let response = service
+            .relay_http_request(request(Some(pkcs12("secret"))))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().content, greeting(&bob));
+        let response = service
+            .relay_http_request(request(Some(pem)))
+            .await
+            .unwrap();
+        assert_eq!(response.response.unwrap().content, greeting(&alice));
+
+        let response = service
+            .relay_http_request(request(Some(pkcs12("wrong"))))
+            .await
+            .unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(response.error_kind, Some(RelayErrorKind::TlsConfig));
+        assert!(response
+            .message
+            .unwrap()
+            .starts_with(&format!("Failed to open PKCS#12 file {}", path("bob.p12"))));
+
+        // The server refuses clients without a certificate its CA issued
+        let stranger = test_certificate("stranger", None, false);
+        std::fs::write(path("stranger.pem"), stranger.0.pem()).unwrap();
+        std::fs::write(path("stranger.key"), stranger.1.serialize_pem()).unwrap();
+        let unknown = ClientCertificate::Pem {
+            cert_file: path("stranger.pem"),
+            key_file: path("stranger.key"),
+        };
+        for (client_certificate, alert) in
+            [(None, "CertificateRequired"), (Some(unknown), "UnknownCA")]
+        {
+            let response = service
+                .relay_http_request(request(client_certificate))
+                .await
+                .unwrap();
+            assert_eq!(response.status, "error");
+            assert_eq!(
+                response.error_kind,
+                Some(RelayErrorKind::TlsClientCertificateRejected)
+            );
+            assert_eq!(
+                response.message.unwrap(),
+                format!("TLS connection failed: received fatal alert: {}", alert)
+            );
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn graphql_request(server_url: &str, method: GraphQLMethod, id: &str) -> GraphQLRequest {
+        GraphQLRequest {
+            url: format!("{}/graphql", server_url),
+            query: "query Book($id: ID!) { book(id: $id) { title via operation } }".to_string(),
+            variables: Some(serde_json::json!({ "id": id })),
+            operation_name: Some("Book".to_string()),
+            method,
+            ..Default::default()
+        }
     }
 
     #[tokio::test]
-    async fn test_e2e_put_request() {
+    async fn test_e2e_graphql_post_and_get() {
         let server_url = start_test_server().await;
         let service = RelayService::new();
 
-        let user = TestUser {
-            id: 0,
-            name: "Updated Name".to_string(),
-            email: "updated@example.com".to_string(),
-        };
-
-        let request = Request {
-            url: format!("{}/users/5", server_url),
-            method: RequestMethod::PUT,
-            headers: HashMap::new(),
-            body: RequestBody {
-                content_type: Some("application/json".to_string()),
-                content: Some(serde_json::to_string(&user).unwrap()),
-            },
-            params: HashMap::new(),
-        };
+        for (method, via) in [(GraphQLMethod::POST, "POST"), (GraphQLMethod::GET, "GET")] {
+            let response = service
+                .relay_graphql_request(graphql_request(&server_url, method, "42"))
+                .await
+                .unwrap();
+            assert_eq!(response.status, "success");
+            assert_eq!(response.response.unwrap().status_code, 200);
+            let result = response.graphql.unwrap();
+            assert_eq!(
+                result.data.unwrap()["book"],
+                serde_json::json!({ "title": "Dune", "via": via, "operation": "Book" })
+            );
+            assert!(result.errors.is_empty());
+        }
 
-        let response = service.relay_http_request(request).await.unwrap();
-        
-        let resp = response.response.unwrap();
-        assert_eq!(resp.status_code, 200);
-        
-        let updated_user: TestUser = serde_json::from_str(&resp.content).unwrap();
-        assert_eq!(updated_user.id, 5);
-        assert_eq!(updated_user.name, "Updated Name");
+        // Field errors come with a 200 and partial data
+        let response = service
+            .relay_graphql_request(graphql_request(&server_url, GraphQLMethod::POST, "7"))
+            .await
+            .unwrap();
+        let result = response.graphql.unwrap();
+        assert_eq!(result.data.unwrap()["book"], serde_json::Value::Null);
+        assert_eq!(result.errors[0].message, "Book 7 not found");
+        assert_eq!(result.errors[0].path, [serde_json::json!("book")]);
+
+        // Request errors have no data
+        let mut request = graphql_request(&server_url, GraphQLMethod::GET, "42");
+        request.query = "query { book(id: 42) {".to_string();
+        let response = service.relay_graphql_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().status_code, 400);
+        let result = response.graphql.unwrap();
+        assert_eq!(result.data, None);
+        assert_eq!(result.errors[0].locations[0].column, 23);
+
+        // Responses that aren't GraphQL aren't split
+        let mut request = graphql_request(&server_url, GraphQLMethod::GET, "42");
+        request.url = format!("{}/hello", server_url);
+        let response = service.relay_graphql_request(request).await.unwrap();
+        assert_eq!(response.response.unwrap().content, "Hello, World!");
+        assert!(response.graphql.is_none());
+
+        let mut request = graphql_request(&server_url, GraphQLMethod::POST, "42");
+        request.variables = Some(serde_json::json!("42"));
+        let response = service.relay_graphql_request(request).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert_eq!(
+            response.message.as_deref(),
+            Some("GraphQL variables must be an object")
+        );
     }
 
     #[tokio::test]
-    async fn test_e2e_delete_request() {
+    async fn test_e2e_graphql_introspection_cached() {
         let server_url = start_test_server().await;
         let service = RelayService::new();
+        let request = graphql_request(&server_url, GraphQLMethod::POST, "42");
+        let introspections = || INTROSPECTIONS.load(std::sync::atomic::Ordering::SeqCst);
 
-        let request = Request {
-            url: format!("{}/users/10", server_url),
-            method: RequestMethod::DELETE,
-            headers: HashMap::new(),
-            body: RequestBody {
-                content_type: None,
-                content: None,
-            },
-            params: HashMap::new(),
-        };
+        let schema = service
+            .introspect_graphql_schema(request.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(schema.url, format!("{}/graphql", server_url));
+        assert_eq!(schema.schema["queryType"]["name"], "Query");
+        assert_eq!(introspections(), 1);
+
+        // Served from the cache until refreshed or cleared
+        let cached = service
+            .introspect_graphql_schema(request.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(cached.fetched_at, schema.fetched_at);
+        assert_eq!(introspections(), 1);
+        service
+            .introspect_graphql_schema(request.clone(), true)
+            .await
+            .unwrap();
+        assert_eq!(introspections(), 2);
+        service.clear_graphql_schemas();
+        service
+            .introspect_graphql_schema(request.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(introspections(), 3);
+
+        // Another URL, so the cached schema isn't used
+        let mut disabled = request.clone();
+        disabled.url = format!("{}/graphql?disabled", server_url);
+        disabled.headers.insert(
+            "x-disable-introspection".to_string(),
+            serde_json::json!("1"),
+        );
+        let error = service
+            .introspect_graphql_schema(disabled, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Introspection failed: GraphQL introspection is not allowed"
+        );
 
-        let response = service.relay_http_request(request).await.unwrap();
-        
-        let resp = response.response.unwrap();
-        assert_eq!(resp.status_code, 204);
+        let mut not_graphql = request;
+        not_graphql.url = format!("{}/hello", server_url);
+        let error = service
+            .introspect_graphql_schema(not_graphql, false)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Introspection failed: not a GraphQL response (status 405)"
+        );
     }
 
-    #[tokio::test]
-    async fn test_e2e_patch_request() {
-        let server_url = start_test_server().await;
-        let service = RelayService::new();
+    /// Writes the greeter's `.proto` files to a directory of their own and
+    /// returns the path of `greeter.proto`
+    fn write_greeter_protos(test: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("relay-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("common.proto"), COMMON_PROTO).unwrap();
+        std::fs::write(dir.join("greeter.proto"), GREETER_PROTO).unwrap();
+        dir.join("greeter.proto").to_string_lossy().into_owned()
+    }
 
-        let request = Request {
-            url: format!("{}/users/7", server_url),
-            method: RequestMethod::PATCH,
-            headers: HashMap::new(),
-            body: RequestBody {
-                content_type: Some("text/plain".to_string()),
-                content: Some("partial update".to_string()),
-            },
-            params: HashMap::new(),
-        };
+    fn grpc_request(
+        server_url: &str,
+        schema: GrpcSchemaSource,
+        method: &str,
+        names: &[&str],
+    ) -> GrpcRequest {
+        GrpcRequest {
+            url: server_url.to_string(),
+            service: "test.greeter.Greeter".to_string(),
+            method: method.to_string(),
+            schema,
+            messages: names
+                .iter()
+                .map(|name| serde_json::json!({ "name": name }))
+                .collect(),
+            metadata: HashMap::new(),
+            id: None,
+            timeouts: TimeoutSettings::default(),
+            authorization: Authorization::default(),
+        }
+    }
 
-        let response = service.relay_http_request(request).await.unwrap();
-        
-        let resp = response.response.unwrap();
-        assert_eq!(resp.status_code, 200);
-        assert!(resp.content.contains("Patched user 7"));
+    /// Makes a gRPC call, returning its response and the events it sent
+    async fn call_grpc(
+        service: &RelayService,
+        request: GrpcRequest,
+    ) -> (GrpcRelayResponse, Vec<GrpcEvent>) {
+        let (events, mut received) = tokio::sync::mpsc::channel(16);
+        let response = service.relay_grpc_request(request, events).await.unwrap();
+        let mut sent = Vec::new();
+        while let Some(event) = received.recv().await {
+            sent.push(event);
+        }
+        (response, sent)
+    }
+
+    fn replies(response: &GrpcRelayResponse) -> Vec<&str> {
+        response
+            .response
+            .as_ref()
+            .expect("gRPC call failed")
+            .messages
+            .iter()
+            .map(|message| message["message"].as_str().unwrap())
+            .collect()
     }
 
     #[tokio::test]
-    async fn test_e2e_slow_response() {
+    async fn test_e2e_grpc_calls_from_proto_files() {
         let server_url = start_test_server().await;
         let service = RelayService::new();
-
-        let request = Request {
-            url: format!("{}/slow", server_url),
-            method: RequestMethod::GET,
-            headers: HashMap::new(),
-            body: RequestBody {
-                content_type: None,
-                content: None,
-            },
-            params: HashMap::new(),
+        let greeter = write_greeter_protos("grpc-calls");
+        let schema = GrpcSchemaSource::ProtoFiles {
+            files: vec![greeter.clone()],
+            import_paths: Vec::new(),
         };
 
-        let start = Instant::now();
-        let response = service.relay_http_request(request).await.unwrap();
-        let elapsed = start.elapsed();
+        let mut request = grpc_request(&server_url, schema.clone(), "SayHello", &["Ada"]);
+        request
+            .metadata
+            .insert("x-caller".to_string(), serde_json::json!("tests"));
+        request.timeouts.total = Some(5_000);
+        let (response, events) = call_grpc(&service, request).await;
+        assert_eq!(replies(&response), ["Hello, Ada!"]);
+        let call = response.response.unwrap();
+        assert_eq!(call.status_code, 200);
+        assert_eq!(call.grpc_status, 0);
+        assert_eq!(call.grpc_status_name, "OK");
+        assert_eq!(call.headers["x-caller-seen"].value, "tests");
+        assert_eq!(call.trailers["grpc-status"].value, "0");
+        assert!(matches!(&events[0], GrpcEvent::Headers { .. }));
+        assert!(
+            matches!(&events[1], GrpcEvent::Message { data, .. } if data["message"] == "Hello, Ada!")
+        );
 
-        assert_eq!(response.status, "success");
-        let resp = response.response.unwrap();
-        
-        // Should take at least 100ms due to sleep
-        assert!(elapsed.as_millis() >= 100);
-        assert!(resp.performance.duration >= 100.0);
+        // Server streaming reports each message as it arrives
+        let request = grpc_request(&server_url, schema.clone(), "StreamGreetings", &["Ada"]);
+        let (response, events) = call_grpc(&service, request).await;
+        assert_eq!(
+            replies(&response),
+            ["Hello #1, Ada!", "Hello #2, Ada!", "Hello #3, Ada!"]
+        );
+        assert_eq!(events.len(), 4);
+
+        // Client streaming and bidirectional calls send every message
+        let request = grpc_request(
+            &server_url,
+            schema.clone(),
+            "CollectNames",
+            &["Ada", "Grace"],
+        );
+        let (response, _) = call_grpc(&service, request).await;
+        assert_eq!(replies(&response), ["Hello, Ada and Grace!"]);
+        let request = grpc_request(&server_url, schema, "Chat", &["Ada", "Grace"]);
+        let (response, _) = call_grpc(&service, request).await;
+        assert_eq!(replies(&response), ["Echo: Ada", "Echo: Grace"]);
+
+        std::fs::remove_dir_all(std::path::Path::new(&greeter).parent().unwrap()).unwrap();
     }
 
     #[tokio::test]
-    async fn test_e2e_large_response() {
+    async fn test_e2e_grpc_status_errors_and_reflection() {
         let server_url = start_test_server().await;
         let service = RelayService::new();
 
-        let request = Request {
-            url: format!("{}/large", server_url),
-            method: RequestMethod::GET,
-            headers: HashMap::new(),
-            body: RequestBody {
-                content_type: None,
-                content: None,
-            },
-            params: HashMap::new(),
-        };
+        // The server only offers v1alpha reflection, found after v1 fails
+        let services = service
+            .describe_grpc_services(grpc_request(
+                &server_url,
+                GrpcSchemaSource::Reflection,
+                "",
+                &[],
+            ))
+            .await
+            .unwrap();
+        assert_eq!(services.len(), 1);
+        assert_eq!(services[0].name, "test.greeter.Greeter");
+        let methods: Vec<_> = services[0]
+            .methods
+            .iter()
+            .map(|method| method.name.as_str())
+            .collect();
+        assert_eq!(
+            methods,
+            [
+                "SayHello",
+                "StreamGreetings",
+                "CollectNames",
+                "Chat",
+                "Fail"
+            ]
+        );
+        assert_eq!(
+            services[0].methods[0].input_type,
+            "test.common.HelloRequest"
+        );
+        assert_eq!(
+            services[0].methods[0].request_template,
+            serde_json::json!({ "name": "" })
+        );
 
-        let response = service.relay_http_request(request).await.unwrap();
-        
-        let resp = response.response.unwrap();
-        assert_eq!(resp.status_code, 200);
-        
-        // Should be 500KB
-        assert_eq!(resp.performance.transfer_size, 500 * 1024);
-        assert!(resp.performance.transfer_time > 0.0);
+        let request = grpc_request(
+            &server_url,
+            GrpcSchemaSource::Reflection,
+            "SayHello",
+            &["Ada"],
+        );
+        let (response, _) = call_grpc(&service, request).await;
+        assert_eq!(replies(&response), ["Hello, Ada!"]);
+
+        // A failed call still succeeds, reporting the status it ended with
+        let request = grpc_request(
+            &server_url,
+            GrpcSchemaSource::Reflection,
+            "Fail",
+            &["nobody"],
+        );
+        let (response, events) = call_grpc(&service, request).await;
+        assert_eq!(response.status, "success");
+        let call = response.response.unwrap();
+        assert_eq!(call.grpc_status, 5);
+        assert_eq!(call.grpc_status_name, "NOT_FOUND");
+        assert_eq!(call.grpc_message, "No greeter named \"nobody\"");
+        assert!(call.messages.is_empty());
+        assert_eq!(events.len(), 1);
+
+        for (method, names, expected) in [
+            (
+                "Missing",
+                &["Ada"][..],
+                "Unknown gRPC method Missing of test.greeter.Greeter",
+            ),
+            (
+                "SayHello",
+                &["Ada", "Grace"][..],
+                "SayHello takes exactly one request message, got 2",
+            ),
+        ] {
+            let request = grpc_request(&server_url, GrpcSchemaSource::Reflection, method, names);
+            let (response, _) = call_grpc(&service, request).await;
+            assert_eq!(response.status, "error");
+            assert_eq!(response.message.as_deref(), Some(expected));
+        }
+
+        // Messages must match the method's input type
+        let mut request = grpc_request(&server_url, GrpcSchemaSource::Reflection, "SayHello", &[]);
+        request.messages = vec![serde_json::json!({ "title": "Ada" })];
+        let (response, _) = call_grpc(&service, request).await;
+        assert!(response
+            .message
+            .unwrap()
+            .starts_with("Invalid test.common.HelloRequest message"));
+
+        // Servers without reflection fail with the mapped status
+        let services = service
+            .describe_grpc_services(grpc_request(
+                &format!("{}/nowhere", server_url),
+                GrpcSchemaSource::Reflection,
+                "",
+                &[],
+            ))
+            .await;
+        assert_eq!(
+            services.unwrap_err().to_string(),
+            "UNIMPLEMENTED: Received HTTP status 404"
+        );
     }
 
     #[tokio::test]
-    async fn test_e2e_different_status_codes() {
+    async fn test_e2e_echo_form_body() {
         let server_url = start_test_server().await;
         let service = RelayService::new();
 
-        // Test 201 Created
-        let request = Request {
-            url: format!("{}/status/201", server_url),
-            method: RequestMethod::GET,
-            headers: HashMap::new(),
-            body: RequestBody {
-                content_type: None,
-                content: None,
-            },
-            params: HashMap::new(),
-        };
-
-        let response = service.relay_http_request(request).await.unwrap();
-        assert_eq!(response.response.unwrap().status_code, 201);
-
-        // Test 404 Not Found
-        let request = Request {
-            url: format!("{}/status/404", server_url),
-            method: RequestMethod::GET,
-            headers: HashMap::new(),
-            body: RequestBody {
-                content_type: None,
-                content: None,
-            },
-            params: HashMap::new(),
+        let field = |name: &str, value: &str, enabled: bool| FormField {
+            id: name.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+            enabled,
         };
-
-        let response = service.relay_http_request(request).await.unwrap();
-        assert_eq!(response.response.unwrap().status_code, 404);
-
-        // Test 500 Internal Server Error
         let request = Request {
-            url: format!("{}/status/500", server_url),
-            method: RequestMethod::GET,
-            headers: HashMap::new(),
+            url: format!("{}/echo", server_url),
+            method: RequestMethod::POST,
             body: RequestBody {
-                content_type: None,
-                content: None,
+                form: Some(vec![
+                    field("name", "Ada Lovelace", true),
+                    field("note", "a+b=c & 100% ✓", true),
+                    field("draft", "skipped", false),
+                    field("name", "Countess", true),
+                ]),
+                ..Default::default()
             },
-            params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
-        assert_eq!(response.response.unwrap().status_code, 500);
+        let resp = response.response.unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(
+            resp.content,
+            "name=Ada+Lovelace&note=a%2Bb%3Dc+%26+100%25+%E2%9C%93&name=Countess"
+        );
+        let decoded: Vec<(String, String)> = url::form_urlencoded::parse(resp.content.as_bytes())
+            .into_owned()
+            .collect();
+        assert_eq!(
+            decoded,
+            [
+                ("name".to_string(), "Ada Lovelace".to_string()),
+                ("note".to_string(), "a+b=c & 100% ✓".to_string()),
+                ("name".to_string(), "Countess".to_string()),
+            ]
+        );
     }
 
     #[tokio::test]
-    async fn test_e2e_custom_headers() {
+    async fn test_e2e_multipart_form_data() {
+        use sha2::{Digest, Sha256};
         let server_url = start_test_server().await;
         let service = RelayService::new();
 
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 256) as u8).collect();
+        let path = std::env::temp_dir().join(format!("relay-multipart-{}.png", std::process::id()));
+        tokio::fs::write(&path, &contents).await.unwrap();
+
         let request = Request {
-            url: format!("{}/headers", server_url),
-            method: RequestMethod::GET,
-            headers: HashMap::new(),
+            url: format!("{}/multipart", server_url),
+            method: RequestMethod::POST,
             body: RequestBody {
-                content_type: None,
-                content: None,
+                // The generated boundary replaces a bare multipart content type
+                content_type: Some("multipart/form-data".to_string()),
+                multipart: Some(vec![
+                    MultipartPart {
+                        name: "title".to_string(),
+                        value: MultipartValue::Text {
+                            value: "Holiday photo".to_string(),
+                        },
+                        content_type: None,
+                        headers: HashMap::new(),
+                    },
+                    MultipartPart {
+                        name: "photo".to_string(),
+                        value: MultipartValue::File {
+                            file_path: path.to_string_lossy().into_owned(),
+                            filename: Some("beach.png".to_string()),
+                        },
+                        content_type: Some("image/png".to_string()),
+                        headers: HashMap::new(),
+                    },
+                ]),
+                ..Default::default()
             },
-            params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
-        
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(response.status, "success");
         let resp = response.response.unwrap();
-        assert!(resp.headers.contains_key("x-custom-header"));
-        assert_eq!(resp.headers.get("x-custom-header").unwrap().value, "test-value");
-        assert!(resp.headers.contains_key("x-request-id"));
+        assert_eq!(resp.status_code, 200);
+
+        let parts: serde_json::Value = serde_json::from_str(&resp.content).unwrap();
+        assert_eq!(
+            parts,
+            serde_json::json!([
+                {
+                    "name": "title",
+                    "filename": null,
+                    "contentType": null,
+                    "size": 13,
+                    "sha256": hex::encode(Sha256::digest(b"Holiday photo")),
+                },
+                {
+                    "name": "photo",
+                    "filename": "beach.png",
+                    "contentType": "image/png",
+                    "size": 200_000,
+                    "sha256": hex::encode(Sha256::digest(&contents)),
+                },
+            ])
+        );
     }
 
     #[tokio::test]
-    async fn test_e2e_authentication() {
+    async fn test_e2e_timing_metrics_accuracy() {
         let server_url = start_test_server().await;
         let service = RelayService::new();
 
-        // Test without auth header - should fail
         let request = Request {
-            url: format!("{}/auth", server_url),
+            url: format!("{}/hello", server_url),
             method: RequestMethod::GET,
             headers: HashMap::new(),
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
-        assert_eq!(response.response.as_ref().unwrap().status_code, 401);
+        let perf = response.response.unwrap().performance;
 
-        // Test with correct auth header - should succeed
-        let mut headers = HashMap::new();
-        headers.insert(
-            "authorization".to_string(),
-            serde_json::Value::String("Bearer secret-token".to_string()),
-        );
+        // All metrics should be captured
+        assert!(perf.duration > 0.0, "Duration should be positive");
+        assert!(perf.latency >= 0.0, "Latency should be non-negative");
+        assert!(perf.processing_time >= 0.0, "Processing time should be non-negative");
+        assert!(perf.transfer_time >= 0.0, "Transfer time should be non-negative");
 
-        let request = Request {
-            url: format!("{}/auth", server_url),
-            method: RequestMethod::GET,
-            headers,
-            body: RequestBody {
-                content_type: None,
-                content: None,
-            },
-            params: HashMap::new(),
-        };
+        // Duration should be reasonable (less than 1 second for local server)
+        assert!(perf.duration < 1000.0, "Duration should be under 1 second for local request");
 
-        let response = service.relay_http_request(request).await.unwrap();
-        assert_eq!(response.response.unwrap().status_code, 200);
+        // Latency should be non-negative (may be 0 for reused connections)
+        assert!(perf.latency >= 0.0, "Latency should be non-negative");
     }
 
     #[tokio::test]
-    async fn test_e2e_timing_metrics_accuracy() {
+    async fn test_e2e_connection_phases_and_reuse() {
         let server_url = start_test_server().await;
         let service = RelayService::new();
 
@@ -1224,24 +6057,30 @@ mod e2e_tests {
             body: RequestBody {
                 content_type: None,
                 content: None,
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
+        // First request opens a new connection
+        let response = service.relay_http_request(request.clone()).await.unwrap();
+        let perf = response.response.unwrap().performance;
+        assert!(!perf.connection_reused, "First request should open a new connection");
+        assert_eq!(perf.dns, 0.0, "IP literal hosts should skip DNS");
+        assert!(perf.tcp_connect > 0.0, "TCP connect should be measured");
+        assert_eq!(perf.tls_handshake, 0.0, "Plain HTTP has no TLS handshake");
+        assert_eq!(perf.latency, perf.dns + perf.tcp_connect + perf.tls_handshake);
+        assert!(perf.ttfb > 0.0);
+        assert_eq!(perf.download, perf.transfer_time);
+
+        // Second request reuses the pooled connection
         let response = service.relay_http_request(request).await.unwrap();
         let perf = response.response.unwrap().performance;
-
-        // All metrics should be captured
-        assert!(perf.duration > 0.0, "Duration should be positive");
-        assert!(perf.latency >= 0.0, "Latency should be non-negative");
-        assert!(perf.processing_time >= 0.0, "Processing time should be non-negative");
-        assert!(perf.transfer_time >= 0.0, "Transfer time should be non-negative");
-
-        // Duration should be reasonable (less than 1 second for local server)
-        assert!(perf.duration < 1000.0, "Duration should be under 1 second for local request");
-
-        // Latency should be non-negative (may be 0 for reused connections)
-        assert!(perf.latency >= 0.0, "Latency should be non-negative");
+        assert!(perf.connection_reused, "Second request should reuse the pooled connection");
+        assert_eq!(perf.tcp_connect, 0.0);
+        assert_eq!(perf.latency, 0.0);
+        assert!(perf.ttfb > 0.0);
     }
 
     #[tokio::test]
@@ -1258,8 +6097,10 @@ mod e2e_tests {
             body: RequestBody {
                 content_type: Some("text/plain".to_string()),
                 content: Some(test_body.to_string()),
+                ..Default::default()
             },
             params: HashMap::new(),
+            ..Default::default()
         };
 
         let response = service.relay_http_request(request).await.unwrap();
@@ -0,0 +1,539 @@
+//! Outgoing request bodies, held in memory, streamed from a local file or
+//! assembled from `multipart/form-data` parts.
+//!
+//! A [`BodySource`] describes where the bytes come from and can be opened any
+//! number of times, e.g. to answer an authentication challenge. Each send
+//! opens a fresh [`RelayBody`], which reports an exact size so hyper sends a
+//! `Content-Length`, and reports upload progress as chunks are written.
+
+use crate::multipart::MultipartBody;
+use crate::types::{BodyEncoding, RequestBody, UploadProgress};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::Stream;
+use http_body_util::Full;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use pin_project::pin_project;
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncReadExt;
+use tokio_util::io::ReaderStream;
+use url::form_urlencoded;
+
+/// Size of the chunks read from a file body.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Receives upload progress for request bodies.
+pub type ProgressCallback = Arc<dyn Fn(UploadProgress) + Send + Sync>;
+
+/// Where the bytes of a request body come from.
+#[derive(Debug, Clone)]
+pub enum BodySource {
+    Bytes(Bytes),
+    /// An `application/x-www-form-urlencoded` form
+    Form(Bytes),
+    File(FileSegment),
+    Multipart(MultipartBody),
+}
+
+/// A piece of a body: bytes in memory or a whole file.
+#[derive(Debug, Clone)]
+pub enum Segment {
+    Bytes(Bytes),
+    File(FileSegment),
+}
+
+/// A file whose `length` was taken when the request was prepared.
+#[derive(Debug, Clone)]
+pub struct FileSegment {
+    pub path: PathBuf,
+    pub length: u64,
+}
+
+impl FileSegment {
+    pub async fn new(path: &str) -> Result<Self> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| anyhow!("Failed to read request body file {}: {}", path, e))?;
+        if !metadata.is_file() {
+            return Err(anyhow!("Request body file {} is not a file", path));
+        }
+        Ok(Self {
+            path: PathBuf::from(path),
+            length: metadata.len(),
+        })
+    }
+}
+
+impl BodySource {
+    /// The body described by the request, or `None` when it has none.
+    /// Requests setting more than one body source are rejected rather than
+    /// silently dropping all but one of them.
+    pub async fn from_request(body: &RequestBody) -> Result<Option<Self>> {
+        let sources: Vec<&str> = [
+            (
+                "content",
+                body.content.as_deref().is_some_and(|c| !c.is_empty()),
+            ),
+            (
+                "filePath",
+                body.file_path.as_deref().is_some_and(|p| !p.is_empty()),
+            ),
+            ("multipart", body.multipart.is_some()),
+            ("form", body.form.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect();
+        if sources.len() > 1 {
+            return Err(anyhow!(
+                "Request body sets more than one of {}; only one body source is allowed",
+                sources.join(", ")
+            ));
+        }
+
+        if let Some(path) = body.file_path.as_deref().filter(|path| !path.is_empty()) {
+            return Ok(Some(Self::File(FileSegment::new(path).await?)));
+        }
+        if let Some(parts) = &body.multipart {
+            return Ok(Some(Self::Multipart(MultipartBody::encode(parts).await?)));
+        }
+        if let Some(fields) = &body.form {
+            let form = form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(
+                    fields
+                        .iter()
+                        .filter(|field| field.enabled)
+                        .map(|field| (&field.name, &field.value)),
+                )
+                .finish();
+            return Ok((!form.is_empty()).then(|| Self::Form(Bytes::from(form))));
+        }
+
+        let Some(content) = body
+            .content
+            .as_deref()
+            .filter(|content| !content.is_empty())
+        else {
+            return Ok(None);
+        };
+        let bytes = match body.encoding {
+            BodyEncoding::Text => Bytes::copy_from_slice(content.as_bytes()),
+            BodyEncoding::Base64 => STANDARD
+                .decode(content)
+                .map_err(|e| anyhow!("Invalid base64 request body: {}", e))?
+                .into(),
+        };
+        Ok(Some(Self::Bytes(bytes)))
+    }
+
+    /// Size of the body in bytes.
+    pub(crate) fn len(&self) -> u64 {
+        self.segments().iter().map(Segment::len).sum()
+    }
+
+    /// The `Content-Type` to send, given the one the request declared.
+    /// Multipart bodies always announce their boundary.
+    pub fn content_type(&self, declared: Option<&str>) -> Option<String> {
+        match self {
+            Self::Multipart(multipart) => Some(multipart.content_type()),
+            Self::Form(_) => Some(
+                declared
+                    .unwrap_or("application/x-www-form-urlencoded")
+                    .to_string(),
+            ),
+            _ => declared.map(str::to_string),
+        }
+    }
+
+    fn segments(&self) -> Vec<Segment> {
+        match self {
+            Self::Bytes(bytes) | Self::Form(bytes) => vec![Segment::Bytes(bytes.clone())],
+            Self::File(file) => vec![Segment::File(file.clone())],
+            Self::Multipart(multipart) => multipart.segments.clone(),
+        }
+    }
+
+    /// SHA-256 of the body, streaming files rather than loading them.
+    pub async fn sha256(&self) -> Result<[u8; 32]> {
+        let mut hasher = Sha256::new();
+        for segment in self.segments() {
+            match segment {
+                Segment::Bytes(bytes) => hasher.update(&bytes),
+                Segment::File(file) => {
+                    let mut file = open_file(&file.path).await?;
+                    let mut buffer = vec![0; FILE_CHUNK_SIZE];
+                    loop {
+                        let read = file.read(&mut buffer).await?;
+                        if read == 0 {
+                            break;
+                        }
+                        hasher.update(&buffer[..read]);
+                    }
+                }
+            }
+        }
+        Ok(hasher.finalize().into())
+    }
+
+    /// The whole body in memory, for the rare cases that need it at once.
+    pub async fn to_bytes(&self) -> Result<Bytes> {
+        if let Self::Bytes(bytes) | Self::Form(bytes) = self {
+            return Ok(bytes.clone());
+        }
+        let mut body = Vec::new();
+        for segment in self.segments() {
+            match segment {
+                Segment::Bytes(bytes) => body.extend_from_slice(&bytes),
+                Segment::File(file) => {
+                    body.extend(tokio::fs::read(&file.path).await.map_err(|e| {
+                        anyhow!(
+                            "Failed to read request body file {}: {}",
+                            file.path.display(),
+                            e
+                        )
+                    })?)
+                }
+            }
+        }
+        Ok(body.into())
+    }
+
+    /// Opens the body for one send.
+    pub async fn open(&self, progress: Option<Progress>) -> Result<RelayBody> {
+        let kind = match self {
+            Self::Bytes(bytes) | Self::Form(bytes) => Kind::Full(Full::new(bytes.clone())),
+            _ => {
+                let mut open = VecDeque::new();
+                for segment in self.segments() {
+                    open.push_back(match segment {
+                        Segment::Bytes(bytes) => OpenSegment::Bytes(bytes),
+                        Segment::File(file) => OpenSegment::File {
+                            stream: ReaderStream::with_capacity(
+                                open_file(&file.path).await?,
+                                FILE_CHUNK_SIZE,
+                            ),
+                            remaining: file.length,
+                        },
+                    });
+                }
+                Kind::Segments(open)
+            }
+        };
+        Ok(RelayBody {
+            kind,
+            sent: 0,
+            total: self.len(),
+            progress,
+        })
+    }
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        match self {
+            Self::Bytes(bytes) => bytes.len() as u64,
+            Self::File(file) => file.length,
+        }
+    }
+}
+
+async fn open_file(path: &Path) -> Result<tokio::fs::File> {
+    tokio::fs::File::open(path)
+        .await
+        .map_err(|e| anyhow!("Failed to open request body file {}: {}", path.display(), e))
+}
+
+fn file_changed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Request body file changed while uploading",
+    )
+}
+
+/// Where to report the progress of one request's body.
+pub struct Progress {
+    pub id: Option<String>,
+    pub callback: ProgressCallback,
+}
+
+/// Body of a request sent by the relay.
+#[pin_project]
+pub struct RelayBody {
+    #[pin]
+    kind: Kind,
+    sent: u64,
+    total: u64,
+    progress: Option<Progress>,
+}
+
+#[pin_project(project = KindProj)]
+enum Kind {
+    Full(#[pin] Full<Bytes>),
+    Segments(VecDeque<OpenSegment>),
+}
+
+enum OpenSegment {
+    Bytes(Bytes),
+    File {
+        stream: ReaderStream<tokio::fs::File>,
+        /// Bytes still expected, as announced in Content-Length
+        remaining: u64,
+    },
+}
+
+impl From<Bytes> for RelayBody {
+    fn from(bytes: Bytes) -> Self {
+        Self {
+            total: bytes.len() as u64,
+            kind: Kind::Full(Full::new(bytes)),
+            sent: 0,
+            progress: None,
+        }
+    }
+}
+
+impl Body for RelayBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.project();
+        let chunk = match this.kind.project() {
+            KindProj::Full(full) => match ready!(full.poll_frame(cx)) {
+                Some(Ok(frame)) => frame.into_data().ok(),
+                Some(Err(never)) => match never {},
+                None => None,
+            },
+            KindProj::Segments(segments) => loop {
+                match segments.front_mut() {
+                    None => break None,
+                    Some(OpenSegment::Bytes(bytes)) => {
+                        let bytes = std::mem::take(bytes);
+                        segments.pop_front();
+                        if !bytes.is_empty() {
+                            break Some(bytes);
+                        }
+                    }
+                    Some(OpenSegment::File { stream, remaining }) => {
+                        match ready!(Pin::new(stream).poll_next(cx)).transpose()? {
+                            // The file no longer has the length sent as Content-Length
+                            Some(chunk) if chunk.len() as u64 > *remaining => {
+                                return Poll::Ready(Some(Err(file_changed())))
+                            }
+                            None if *remaining > 0 => {
+                                return Poll::Ready(Some(Err(file_changed())))
+                            }
+                            Some(chunk) => {
+                                *remaining -= chunk.len() as u64;
+                                break Some(chunk);
+                            }
+                            None => {
+                                segments.pop_front();
+                            }
+                        }
+                    }
+                }
+            },
+        };
+        let Some(chunk) = chunk else {
+            return Poll::Ready(None);
+        };
+
+        *this.sent += chunk.len() as u64;
+        if let Some(progress) = this.progress {
+            (progress.callback)(UploadProgress {
+                id: progress.id.clone(),
+                sent: *this.sent,
+                total: *this.total,
+            });
+        }
+        Poll::Ready(Some(Ok(Frame::data(chunk))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            Kind::Full(full) => full.is_end_stream(),
+            Kind::Segments(segments) => segments.is_empty(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.total.saturating_sub(self.sent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::FormField;
+    use http_body_util::BodyExt;
+    use std::sync::Mutex;
+
+    fn request_body(content: &str, encoding: BodyEncoding) -> RequestBody {
+        RequestBody {
+            content: Some(content.to_string()),
+            encoding,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_text_and_base64_content() {
+        let text = BodySource::from_request(&request_body("hello", BodyEncoding::Text))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(text.to_bytes().await.unwrap(), "hello");
+
+        let bytes = BodySource::from_request(&request_body("AAH/", BodyEncoding::Base64))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(bytes.to_bytes().await.unwrap(), &b"\x00\x01\xff"[..]);
+
+        let error = BodySource::from_request(&request_body("not base64!", BodyEncoding::Base64))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Invalid base64 request body"));
+
+        let empty = BodySource::from_request(&RequestBody::default())
+            .await
+            .unwrap();
+        assert!(empty.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_file_body_streams_with_progress() {
+        let contents: Vec<u8> = (0..FILE_CHUNK_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let path = std::env::temp_dir().join(format!("relay-body-{}", std::process::id()));
+        tokio::fs::write(&path, &contents).await.unwrap();
+
+        let source = BodySource::from_request(&RequestBody {
+            file_path: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(source.len(), contents.len() as u64);
+        assert_eq!(
+            source.sha256().await.unwrap(),
+            <[u8; 32]>::from(Sha256::digest(&contents))
+        );
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let callback: ProgressCallback = {
+            let reports = reports.clone();
+            Arc::new(move |progress| reports.lock().unwrap().push(progress))
+        };
+        let body = source
+            .open(Some(Progress {
+                id: Some("upload".to_string()),
+                callback,
+            }))
+            .await
+            .unwrap();
+        assert_eq!(body.size_hint().exact(), Some(contents.len() as u64));
+        let streamed = body.collect().await.unwrap().to_bytes();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert_eq!(streamed, contents);
+        let reports = reports.lock().unwrap();
+        assert!(reports.len() >= 2);
+        assert_eq!(
+            reports.last().unwrap(),
+            &UploadProgress {
+                id: Some("upload".to_string()),
+                sent: contents.len() as u64,
+                total: contents.len() as u64,
+            }
+        );
+        assert!(reports.windows(2).all(|pair| pair[0].sent < pair[1].sent));
+    }
+
+    #[tokio::test]
+    async fn test_form_fields_encoding() {
+        let field = |name: &str, value: &str, enabled: bool| FormField {
+            id: name.to_string(),
+            name: name.to_string(),
+            value: value.to_string(),
+            enabled,
+        };
+        let body = RequestBody {
+            form: Some(vec![
+                field("q", "rust & tauri", true),
+                field("disabled", "x", false),
+                field("emoji*", "~-_.😀", true),
+            ]),
+            ..Default::default()
+        };
+        let source = BodySource::from_request(&body).await.unwrap().unwrap();
+        assert_eq!(
+            source.to_bytes().await.unwrap(),
+            "q=rust+%26+tauri&emoji*=%7E-_.%F0%9F%98%80"
+        );
+        assert_eq!(
+            source.content_type(None).as_deref(),
+            Some("application/x-www-form-urlencoded")
+        );
+        assert_eq!(
+            source
+                .content_type(Some("application/x-www-form-urlencoded; charset=utf-8"))
+                .as_deref(),
+            Some("application/x-www-form-urlencoded; charset=utf-8")
+        );
+
+        // Forms without enabled fields have no body
+        let body = RequestBody {
+            form: Some(vec![field("disabled", "x", false)]),
+            ..Default::default()
+        };
+        assert!(BodySource::from_request(&body).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_multiple_body_sources_are_rejected() {
+        let body = RequestBody {
+            content: Some("q=1".to_string()),
+            form: Some(Vec::new()),
+            ..Default::default()
+        };
+        let error = BodySource::from_request(&body).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Request body sets more than one of content, form; only one body source is allowed"
+        );
+
+        // Empty content and file paths don't count as a source
+        let body = RequestBody {
+            content: Some(String::new()),
+            file_path: Some(String::new()),
+            form: Some(Vec::new()),
+            ..Default::default()
+        };
+        assert!(BodySource::from_request(&body).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_missing_file_is_an_error() {
+        let error = BodySource::from_request(&RequestBody {
+            file_path: Some("/nonexistent/upload.bin".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Failed to read request body file /nonexistent/upload.bin"));
+    }
+}
@@ -0,0 +1,153 @@
+//! Parser for the `Server-Timing` response header.
+//!
+//! See https://w3c.github.io/server-timing/#the-server-timing-header-field
+//!
+//! ```text
+//! Server-Timing: db;dur=53, app;dur=47.2, cache;desc="Cache Read";dur=23.2
+//! ```
+//!
+//! Each header instance holds a comma separated list of metrics. A metric is a
+//! name followed by `;`-separated params, of which only `dur` and `desc` are
+//! meaningful. Malformed params are ignored rather than failing the metric.
+
+use crate::header_parse::{split_outside_quotes, unquote};
+use crate::types::ServerTimingMetric;
+
+/// Parses every `Server-Timing` header value into a flat list of metrics.
+pub fn parse_server_timing<'a, I>(values: I) -> Vec<ServerTimingMetric>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    values
+        .into_iter()
+        .flat_map(|value| split_outside_quotes(value, ','))
+        .filter_map(|entry| parse_metric(&entry))
+        .collect()
+}
+
+/// Derives server processing time (ms) from the metrics: an explicit `total`
+/// metric wins, otherwise the durations of all metrics are summed. Returns
+/// `None` when no metric carries a duration.
+pub fn processing_time(metrics: &[ServerTimingMetric]) -> Option<f64> {
+    if let Some(total) = metrics
+        .iter()
+        .find(|m| m.name.eq_ignore_ascii_case("total"))
+        .and_then(|m| m.duration)
+    {
+        return Some(total);
+    }
+
+    let durations: Vec<f64> = metrics.iter().filter_map(|m| m.duration).collect();
+    if durations.is_empty() {
+        None
+    } else {
+        Some(durations.iter().sum())
+    }
+}
+
+fn parse_metric(entry: &str) -> Option<ServerTimingMetric> {
+    let mut parts = split_outside_quotes(entry, ';').into_iter();
+    let name = parts.next()?.trim().to_string();
+    if name.is_empty() || !name.chars().all(is_token_char) {
+        return None;
+    }
+
+    let mut metric = ServerTimingMetric {
+        name,
+        duration: None,
+        description: None,
+    };
+    let mut seen_dur = false;
+    let mut seen_desc = false;
+
+    for param in parts {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key.trim(), unquote(value.trim())),
+            None => (param.trim(), String::new()),
+        };
+
+        // Only the first occurrence of each param counts
+        if key.eq_ignore_ascii_case("dur") && !seen_dur {
+            seen_dur = true;
+            metric.duration = value.parse::<f64>().ok().filter(|d| d.is_finite());
+        } else if key.eq_ignore_ascii_case("desc") && !seen_desc {
+            seen_desc = true;
+            metric.description = Some(value);
+        }
+    }
+
+    Some(metric)
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_metrics() {
+        let metrics = parse_server_timing(["db;dur=53, app;dur=47.2"]);
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].name, "db");
+        assert_eq!(metrics[0].duration, Some(53.0));
+        assert_eq!(metrics[1].name, "app");
+        assert_eq!(metrics[1].duration, Some(47.2));
+        assert_eq!(processing_time(&metrics), Some(100.2));
+    }
+
+    #[test]
+    fn test_parse_multiple_header_instances() {
+        let metrics = parse_server_timing(["db;dur=10", "cache;dur=2.5, app;dur=20"]);
+
+        let names: Vec<&str> = metrics.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, vec!["db", "cache", "app"]);
+    }
+
+    #[test]
+    fn test_parse_descriptions() {
+        let metrics =
+            parse_server_timing([r#"cache;desc="Cache Read, \"hot\"";dur=23.2, edge;desc=fra1"#]);
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(
+            metrics[0].description.as_deref(),
+            Some(r#"Cache Read, "hot""#)
+        );
+        assert_eq!(metrics[0].duration, Some(23.2));
+        assert_eq!(metrics[1].description.as_deref(), Some("fra1"));
+        assert_eq!(metrics[1].duration, None);
+    }
+
+    #[test]
+    fn test_parse_missing_and_invalid_durations() {
+        let metrics = parse_server_timing(["miss, db;dur=abc, app;dur=5;dur=9"]);
+
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].name, "miss");
+        assert_eq!(metrics[0].duration, None);
+        assert_eq!(metrics[1].duration, None);
+        // The first dur param wins
+        assert_eq!(metrics[2].duration, Some(5.0));
+    }
+
+    #[test]
+    fn test_parse_skips_malformed_entries() {
+        let metrics = parse_server_timing([" , ;dur=1, bad name;dur=2, ok;dur=3"]);
+
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].name, "ok");
+    }
+
+    #[test]
+    fn test_processing_time_prefers_total() {
+        let metrics = parse_server_timing(["db;dur=53, app;dur=47.2, total;dur=120"]);
+        assert_eq!(processing_time(&metrics), Some(120.0));
+
+        let metrics = parse_server_timing(["miss, hit;desc=edge"]);
+        assert_eq!(processing_time(&metrics), None);
+    }
+}
@@ -0,0 +1,197 @@
+//! Parsing of `text/event-stream` bodies (Server-Sent Events).
+//!
+//! Follows the event stream interpretation of the WHATWG HTML standard
+//! (https://html.spec.whatwg.org/multipage/server-sent-events.html): lines
+//! end with CRLF, LF or CR, an empty line dispatches the event, and events
+//! left incomplete when the stream ends are dropped.
+
+/// Default delay before reconnecting, until the server sets one with `retry`.
+pub const DEFAULT_RECONNECTION_TIME: u64 = 3000;
+
+/// An event dispatched by the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedEvent {
+    /// The last event ID at the time of dispatch
+    pub id: String,
+    /// Event type, `message` unless the server named one
+    pub event: String,
+    pub data: String,
+}
+
+/// Incremental parser fed with body chunks as they arrive.
+#[derive(Debug, Default)]
+pub struct EventStreamParser {
+    line: Vec<u8>,
+    /// The previous chunk ended with CR, so a leading LF belongs to it
+    after_cr: bool,
+    started: bool,
+    data: String,
+    event: String,
+    last_event_id: String,
+    retry: Option<u64>,
+}
+
+impl EventStreamParser {
+    /// Continues a stream whose last event ID was `last_event_id`, which
+    /// survives reconnections.
+    pub fn resume(last_event_id: String) -> Self {
+        Self {
+            last_event_id,
+            ..Default::default()
+        }
+    }
+
+    /// Parses a chunk, returning the events it completed.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<ParsedEvent> {
+        let mut events = Vec::new();
+        for &byte in chunk {
+            if std::mem::take(&mut self.after_cr) && byte == b'\n' {
+                continue;
+            }
+            match byte {
+                b'\r' | b'\n' => {
+                    self.after_cr = byte == b'\r';
+                    let line = std::mem::take(&mut self.line);
+                    events.extend(self.process_line(&line));
+                }
+                _ => self.line.push(byte),
+            }
+        }
+        events
+    }
+
+    pub fn last_event_id(&self) -> &str {
+        &self.last_event_id
+    }
+
+    /// A reconnection time in milliseconds set by the server since the last
+    /// call.
+    pub fn take_retry(&mut self) -> Option<u64> {
+        self.retry.take()
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<ParsedEvent> {
+        let mut line = String::from_utf8_lossy(line).into_owned();
+        if !std::mem::replace(&mut self.started, true) && line.starts_with('\u{feff}') {
+            line.remove(0);
+        }
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line.as_str(), ""),
+        };
+        match field {
+            "event" => self.event = value.to_string(),
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.last_event_id = value.to_string(),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<ParsedEvent> {
+        let event = std::mem::take(&mut self.event);
+        let mut data = std::mem::take(&mut self.data);
+        if data.is_empty() {
+            return None;
+        }
+        data.pop();
+        Some(ParsedEvent {
+            id: self.last_event_id.clone(),
+            event: if event.is_empty() {
+                "message".to_string()
+            } else {
+                event
+            },
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(id: &str, event: &str, data: &str) -> ParsedEvent {
+        ParsedEvent {
+            id: id.to_string(),
+            event: event.to_string(),
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fields_and_dispatch() {
+        let mut parser = EventStreamParser::default();
+        let events = parser.feed(
+            b": comment\n\
+              data: first\n\
+              data:second line\n\
+              \n\
+              event: update\n\
+              id: 7\n\
+              data: {\"n\": 1}\n\
+              unknown: ignored\n\
+              \n",
+        );
+        assert_eq!(
+            events,
+            [
+                event("", "message", "first\nsecond line"),
+                event("7", "update", "{\"n\": 1}"),
+            ]
+        );
+        assert_eq!(parser.last_event_id(), "7");
+
+        // The last event ID carries over to later events, and events without
+        // data aren't dispatched
+        let events = parser.feed(b"event: empty\n\ndata\n\ndata: after\n\n");
+        assert_eq!(
+            events,
+            [event("7", "message", ""), event("7", "message", "after")]
+        );
+    }
+
+    #[test]
+    fn test_line_endings_across_chunks() {
+        let mut parser = EventStreamParser::default();
+        let mut events = Vec::new();
+        for chunk in [
+            &b"\xef\xbb\xbfdata: a\r"[..],
+            b"\ndata: b\r\r",
+            b"data: c\n",
+            b"\ndata: unfinished",
+        ] {
+            events.extend(parser.feed(chunk));
+        }
+        assert_eq!(
+            events,
+            [event("", "message", "a\nb"), event("", "message", "c")]
+        );
+    }
+
+    #[test]
+    fn test_retry_and_id_rules() {
+        let mut parser = EventStreamParser::resume("41".to_string());
+        let events = parser.feed(b"retry: 1500\nretry: soon\nid: bad\0id\ndata: x\n\n");
+        assert_eq!(events, [event("41", "message", "x")]);
+        assert_eq!(parser.take_retry(), Some(1500));
+        assert_eq!(parser.take_retry(), None);
+
+        // An empty id resets the last event ID
+        parser.feed(b"id\ndata: y\n\n");
+        assert_eq!(parser.last_event_id(), "");
+    }
+}
@@ -0,0 +1,153 @@
+//! Flow control for events streamed to the frontend.
+//!
+//! Sending on a Tauri channel never waits for the webview, so forwarding the
+//! relay's events as fast as they come would let the IPC queue grow with the
+//! download. Instead each forwarded event spends a credit, and the frontend
+//! hands credits back as it handles events. Once it falls behind by all of
+//! its credits, forwarding stops, the relay's event buffer fills up and the
+//! relay stops reading the response.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, Semaphore};
+
+/// Tracks the credits of streams that carry a client-supplied id.
+///
+/// The registry is shared between clones, so credits can be granted through
+/// any clone of it.
+#[derive(Clone, Default)]
+pub struct StreamCreditRegistry {
+    streams: Arc<Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl StreamCreditRegistry {
+    /// Opens the stream `id` with `initial` credits. Opening an id that is
+    /// already open replaces the older stream, so grants go to the most
+    /// recent one.
+    pub fn open(&self, id: String, initial: usize) -> StreamCredits {
+        let semaphore = Arc::new(Semaphore::new(initial));
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(id.clone(), semaphore.clone());
+        StreamCredits {
+            registry: self.clone(),
+            id,
+            semaphore,
+        }
+    }
+
+    /// Hands `count` credits back to the stream `id`. Returns `false` if no
+    /// such stream is open.
+    pub fn grant(&self, id: &str, count: usize) -> bool {
+        let streams = self.streams.lock().unwrap();
+        let Some(semaphore) = streams.get(id) else {
+            return false;
+        };
+        let room = Semaphore::MAX_PERMITS - semaphore.available_permits();
+        semaphore.add_permits(count.min(room));
+        true
+    }
+}
+
+/// The credits of one stream, closed when dropped.
+pub struct StreamCredits {
+    registry: StreamCreditRegistry,
+    id: String,
+    semaphore: Arc<Semaphore>,
+}
+
+impl StreamCredits {
+    /// Waits for a credit and spends it. Returns at once after
+    /// [`Self::release`].
+    pub async fn spend(&self) {
+        if let Ok(permit) = self.semaphore.acquire().await {
+            permit.forget();
+        }
+    }
+
+    /// Stops limiting the stream, e.g. once the relay is done reading and
+    /// only its buffered events are left to forward.
+    pub fn release(&self) {
+        self.semaphore.close();
+    }
+}
+
+impl Drop for StreamCredits {
+    fn drop(&mut self) {
+        let mut streams = self.registry.streams.lock().unwrap();
+        // Leave the entry alone if a newer stream re-used the id
+        if streams
+            .get(&self.id)
+            .is_some_and(|semaphore| Arc::ptr_eq(semaphore, &self.semaphore))
+        {
+            streams.remove(&self.id);
+        }
+    }
+}
+
+/// Passes events from `received` to `send`, spending a credit on each when
+/// the stream has `credits`. Ends once the sender is dropped and every
+/// buffered event was passed on, or when `send` fails.
+pub async fn forward<T>(
+    mut received: mpsc::Receiver<T>,
+    credits: Option<Arc<StreamCredits>>,
+    mut send: impl FnMut(T) -> bool,
+) {
+    while let Some(event) = received.recv().await {
+        if let Some(credits) = &credits {
+            credits.spend().await;
+        }
+        if !send(event) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_spending_waits_for_granted_credits() {
+        let registry = StreamCreditRegistry::default();
+        let credits = registry.open("stream-1".to_string(), 1);
+
+        credits.spend().await;
+        assert!(timeout(Duration::from_millis(50), credits.spend())
+            .await
+            .is_err());
+
+        assert!(registry.grant("stream-1", 2));
+        credits.spend().await;
+        credits.spend().await;
+        assert!(!registry.grant("stream-2", 1));
+    }
+
+    #[tokio::test]
+    async fn test_release_stops_limiting() {
+        let registry = StreamCreditRegistry::default();
+        let credits = registry.open("stream-1".to_string(), 0);
+
+        credits.release();
+        for _ in 0..3 {
+            timeout(Duration::from_millis(50), credits.spend())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_drop_closes_only_its_own_stream() {
+        let registry = StreamCreditRegistry::default();
+        let first = registry.open("stream-1".to_string(), 0);
+        let second = registry.open("stream-1".to_string(), 0);
+
+        drop(first);
+        assert!(registry.grant("stream-1", 0));
+        drop(second);
+        assert!(!registry.grant("stream-1", 0));
+    }
+}
@@ -0,0 +1,216 @@
+//! Timeout enforcement for the phases of a relayed request.
+//!
+//! Connect and TLS handshake timeouts are enforced inside the connector stack,
+//! which only ever sees the target `Uri`. The relay hands the effective
+//! settings of the current request to the connector through the
+//! [`CONNECT_TIMEOUTS`] task-local, which is read when hyper asks the connector
+//! for a new connection.
+
+use crate::types::{RelayErrorKind, TimeoutSettings};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+tokio::task_local! {
+    /// Connection phase timeouts of the request currently being sent.
+    pub static CONNECT_TIMEOUTS: ConnectTimeouts;
+}
+
+impl TimeoutSettings {
+    /// Fills unset values from `defaults`.
+    pub fn or(&self, defaults: &TimeoutSettings) -> TimeoutSettings {
+        TimeoutSettings {
+            connect: self.connect.or(defaults.connect),
+            tls_handshake: self.tls_handshake.or(defaults.tls_handshake),
+            first_byte: self.first_byte.or(defaults.first_byte),
+            body_idle: self.body_idle.or(defaults.body_idle),
+            total: self.total.or(defaults.total),
+        }
+    }
+}
+
+/// Converts a timeout setting in milliseconds to a duration, treating `0` as
+/// "no timeout".
+pub fn limit(millis: Option<u64>) -> Option<Duration> {
+    millis.filter(|ms| *ms > 0).map(Duration::from_millis)
+}
+
+/// The subset of [`TimeoutSettings`] that applies while establishing a
+/// connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectTimeouts {
+    pub connect: Option<Duration>,
+    pub tls_handshake: Option<Duration>,
+}
+
+impl ConnectTimeouts {
+    /// Returns the timeouts of the request being sent, or no timeouts when
+    /// called outside of a request scope.
+    pub fn current() -> Self {
+        CONNECT_TIMEOUTS
+            .try_with(|timeouts| *timeouts)
+            .unwrap_or_default()
+    }
+}
+
+impl From<&TimeoutSettings> for ConnectTimeouts {
+    fn from(settings: &TimeoutSettings) -> Self {
+        Self {
+            connect: limit(settings.connect),
+            tls_handshake: limit(settings.tls_handshake),
+        }
+    }
+}
+
+/// A request phase that did not finish within its timeout.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutError {
+    pub kind: RelayErrorKind,
+    pub after: Duration,
+}
+
+impl TimeoutError {
+    pub fn new(kind: RelayErrorKind, after: Duration) -> Self {
+        Self { kind, after }
+    }
+
+    /// Finds a timeout anywhere in the source chain of `error`, e.g. one raised
+    /// by the connector and wrapped by hyper.
+    pub fn find(error: &(dyn Error + 'static)) -> Option<TimeoutError> {
+        let mut current = Some(error);
+        while let Some(err) = current {
+            if let Some(timeout) = err.downcast_ref::<TimeoutError>() {
+                return Some(*timeout);
+            }
+            current = err.source();
+        }
+        None
+    }
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let phase = match self.kind {
+            RelayErrorKind::ConnectTimeout => "Connecting to the server",
+            RelayErrorKind::TlsHandshakeTimeout => "TLS handshake",
+            RelayErrorKind::FirstByteTimeout => "Waiting for the first response byte",
+            RelayErrorKind::BodyIdleTimeout => "Waiting for more of the response body",
+            RelayErrorKind::TotalTimeout => "Request",
+            // Other kinds aren't timeouts
+            _ => "Request",
+        };
+        write!(f, "{} timed out after {}ms", phase, self.after.as_millis())
+    }
+}
+
+impl Error for TimeoutError {}
+
+/// Runs `future` to completion, failing with a [`TimeoutError`] of `kind` if
+/// it takes longer than `limit`. A `None` limit never times out.
+pub async fn with_timeout<F, T>(
+    limit: Option<Duration>,
+    kind: RelayErrorKind,
+    future: F,
+) -> Result<T, TimeoutError>
+where
+    F: Future<Output = T>,
+{
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future)
+            .await
+            .map_err(|_| TimeoutError::new(kind, limit)),
+        None => Ok(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_with_timeout_reports_kind() {
+        let result = with_timeout(
+            Some(Duration::from_millis(10)),
+            RelayErrorKind::FirstByteTimeout,
+            tokio::time::sleep(Duration::from_secs(5)),
+        )
+        .await;
+
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, RelayErrorKind::FirstByteTimeout);
+        assert_eq!(
+            error.to_string(),
+            "Waiting for the first response byte timed out after 10ms"
+        );
+
+        let result = with_timeout(None, RelayErrorKind::TotalTimeout, async { 42 }).await;
+        assert_eq!(result.unwrap(), 42);
+    }
+
+    #[test]
+    fn test_find_walks_source_chain() {
+        #[derive(Debug)]
+        struct Wrapper(TimeoutError);
+
+        impl fmt::Display for Wrapper {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "client error (Connect)")
+            }
+        }
+
+        impl Error for Wrapper {
+            fn source(&self) -> Option<&(dyn Error + 'static)> {
+                Some(&self.0)
+            }
+        }
+
+        let wrapped = Wrapper(TimeoutError::new(
+            RelayErrorKind::ConnectTimeout,
+            Duration::from_secs(1),
+        ));
+        let found = TimeoutError::find(&wrapped).unwrap();
+        assert_eq!(found.kind, RelayErrorKind::ConnectTimeout);
+
+        let io_error = std::io::Error::other("boom");
+        assert!(TimeoutError::find(&io_error).is_none());
+    }
+
+    #[test]
+    fn test_request_settings_override_defaults() {
+        let defaults = TimeoutSettings {
+            connect: Some(30_000),
+            first_byte: Some(60_000),
+            ..Default::default()
+        };
+        let request = TimeoutSettings {
+            connect: Some(500),
+            first_byte: Some(0),
+            total: Some(10_000),
+            ..Default::default()
+        };
+
+        let merged = request.or(&defaults);
+        assert_eq!(merged.connect, Some(500));
+        assert_eq!(merged.total, Some(10_000));
+        assert_eq!(merged.tls_handshake, None);
+
+        // 0 disables a timeout that would otherwise be inherited
+        assert_eq!(limit(merged.first_byte), None);
+        assert_eq!(limit(merged.connect), Some(Duration::from_millis(500)));
+    }
+
+    #[tokio::test]
+    async fn test_current_reads_task_local() {
+        assert!(ConnectTimeouts::current().connect.is_none());
+
+        let timeouts = ConnectTimeouts {
+            connect: Some(Duration::from_millis(250)),
+            tls_handshake: None,
+        };
+        let connect = CONNECT_TIMEOUTS
+            .scope(timeouts, async { ConnectTimeouts::current().connect })
+            .await;
+        assert_eq!(connect, Some(Duration::from_millis(250)));
+    }
+}
@@ -0,0 +1,496 @@
+//! Connector stack instrumentation.
+//!
+//! The relay's hyper client is built on top of three layers that each record
+//! how long their phase of connection establishment took:
+//!
+//! 1. [`TimingResolver`] resolves host names through hickory and measures DNS.
+//! 2. [`TimingConnector`] opens the TCP connection and measures the handshake.
+//! 3. [`TlsTimingConnector`] performs the rustls handshake on top of the TCP
+//!    stream and measures it.
+//!
+//! Connections through a proxy report the proxy's DNS lookup, and a TCP
+//! connect phase that includes setting up the tunnel.
+//!
+//! The measurements travel with the connection as a [`ConnectionTimings`]
+//! extra, which hyper copies into the extensions of every response served by
+//! that connection. The first response to claim them gets the real phases;
+//! later responses on the same pooled connection are reported as reused.
+
+use crate::proxy::{self, Proxy, ProxyError, ProxyKind};
+use crate::timeouts::{with_timeout, ConnectTimeouts, TimeoutError};
+use crate::tls::TlsError;
+use crate::types::RelayErrorKind;
+use hickory_resolver::TokioAsyncResolver;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper::Uri;
+use hyper_rustls::MaybeHttpsStream;
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::rt::TokioIo;
+use pin_project::pin_project;
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tower::Service;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type BoxFuture<T> = Pin<Box<dyn Future<Output = Result<T, BoxError>> + Send>>;
+
+/// Phase durations of a freshly established connection.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectPhases {
+    pub dns: Duration,
+    pub tcp_connect: Duration,
+    pub tls_handshake: Duration,
+    /// When the connection became ready to carry a request.
+    pub ready_at: Instant,
+}
+
+#[derive(Debug)]
+struct ConnectionMetrics {
+    phases: Mutex<ConnectPhases>,
+    claimed: AtomicBool,
+}
+
+/// Timing measurements attached to a pooled connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionTimings(Arc<ConnectionMetrics>);
+
+impl ConnectionTimings {
+    fn new(phases: ConnectPhases) -> Self {
+        Self(Arc::new(ConnectionMetrics {
+            phases: Mutex::new(phases),
+            claimed: AtomicBool::new(false),
+        }))
+    }
+
+    fn record_tls_handshake(&self, tls_handshake: Duration, ready_at: Instant) {
+        let mut phases = self.0.phases.lock().unwrap();
+        phases.tls_handshake = tls_handshake;
+        phases.ready_at = ready_at;
+    }
+
+    fn phases(&self) -> ConnectPhases {
+        *self.0.phases.lock().unwrap()
+    }
+
+    /// Returns the connection phases for the first response served by this
+    /// connection, or `None` if the connection was already used (i.e. reused
+    /// from the pool).
+    pub fn claim(&self) -> Option<ConnectPhases> {
+        if self.0.claimed.swap(true, Ordering::AcqRel) {
+            None
+        } else {
+            Some(self.phases())
+        }
+    }
+}
+
+/// DNS resolver that reports how long each lookup took.
+#[derive(Clone)]
+pub struct TimingResolver {
+    resolver: Arc<TokioAsyncResolver>,
+}
+
+impl TimingResolver {
+    pub fn new() -> Self {
+        // Fall back to the built-in upstream config if the system one can't be read
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .unwrap_or_else(|_| TokioAsyncResolver::tokio(Default::default(), Default::default()));
+
+        Self {
+            resolver: Arc::new(resolver),
+        }
+    }
+
+    /// Resolves `host` to a list of addresses. IP literals are returned as-is
+    /// without touching the network and report a zero DNS duration.
+    pub async fn resolve(&self, host: &str) -> io::Result<(Vec<IpAddr>, Duration)> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok((vec![ip], Duration::ZERO));
+        }
+
+        let start = Instant::now();
+        let lookup = self
+            .resolver
+            .lookup_ip(host)
+            .await
+            .map_err(io::Error::other)?;
+        let addrs: Vec<IpAddr> = lookup.iter().collect();
+
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No addresses found for {}", host),
+            ));
+        }
+
+        Ok((addrs, start.elapsed()))
+    }
+}
+
+impl Default for TimingResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// TCP stream carrying the timings of the connection it belongs to.
+#[pin_project]
+pub struct TimedStream {
+    #[pin]
+    inner: TokioIo<TcpStream>,
+    timings: ConnectionTimings,
+    /// Connected to an HTTP proxy that forwards requests
+    forwarding: bool,
+}
+
+impl TimedStream {
+    fn new(stream: TcpStream, dns: Duration, tcp_connect: Duration, forwarding: bool) -> Self {
+        let timings = ConnectionTimings::new(ConnectPhases {
+            dns,
+            tcp_connect,
+            tls_handshake: Duration::ZERO,
+            ready_at: Instant::now(),
+        });
+        Self {
+            inner: TokioIo::new(stream),
+            timings,
+            forwarding,
+        }
+    }
+
+    pub fn timings(&self) -> &ConnectionTimings {
+        &self.timings
+    }
+}
+
+impl Connection for TimedStream {
+    fn connected(&self) -> Connected {
+        self.inner
+            .connected()
+            .proxy(self.forwarding)
+            .extra(self.timings.clone())
+    }
+}
+
+impl Read for TimedStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        self.project().inner.poll_read(cx, buf)
+    }
+}
+
+impl Write for TimedStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        self.project().inner.poll_write_vectored(cx, bufs)
+    }
+}
+
+/// TCP connector that resolves through [`TimingResolver`] and measures the
+/// DNS and TCP connect phases.
+#[derive(Clone, Default)]
+pub struct TimingConnector {
+    resolver: TimingResolver,
+    proxy: Option<Proxy>,
+    /// Whether plain HTTP requests are forwarded by an HTTP proxy rather than
+    /// tunnelled, which only works for HTTP/1 requests
+    forward_http: bool,
+}
+
+impl TimingConnector {
+    pub fn new(resolver: TimingResolver) -> Self {
+        Self {
+            resolver,
+            proxy: None,
+            forward_http: false,
+        }
+    }
+
+    /// A connector connecting through `proxy`.
+    pub fn with_proxy(&self, proxy: Proxy, forward_http: bool) -> Self {
+        Self {
+            resolver: self.resolver.clone(),
+            proxy: Some(proxy),
+            forward_http,
+        }
+    }
+}
+
+impl Service<Uri> for TimingConnector {
+    type Response = TimedStream;
+    type Error = BoxError;
+    type Future = BoxFuture<TimedStream>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let connector = self.clone();
+        let timeouts = ConnectTimeouts::current();
+
+        Box::pin(async move {
+            with_timeout(
+                timeouts.connect,
+                RelayErrorKind::ConnectTimeout,
+                connector.connect(dst),
+            )
+            .await?
+        })
+    }
+}
+
+impl TimingConnector {
+    async fn connect(self, dst: Uri) -> Result<TimedStream, BoxError> {
+        let host = dst
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "URI has no host"))?;
+        let port = dst.port_u16().unwrap_or(match dst.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+
+        let Some(proxy) = &self.proxy else {
+            let (stream, dns, tcp_connect) = self.open(host, port).await?;
+            return Ok(TimedStream::new(stream, dns, tcp_connect, false));
+        };
+
+        let (mut stream, mut dns, mut tcp_connect) = self
+            .open(&proxy.host, proxy.port)
+            .await
+            .map_err(|e| ProxyError::new(format!("Failed to connect to proxy {}: {}", proxy, e)))?;
+        let forwarding =
+            self.forward_http && proxy.kind == ProxyKind::Http && dst.scheme_str() == Some("http");
+        if !forwarding {
+            let target = match proxy.kind {
+                ProxyKind::Socks5 => {
+                    let (addrs, lookup) = self.resolver.resolve(host).await?;
+                    dns += lookup;
+                    addrs[0].to_string()
+                }
+                _ => host.to_string(),
+            };
+            let tunnel_start = Instant::now();
+            proxy::tunnel(&mut stream, proxy, &target, port).await?;
+            tcp_connect += tunnel_start.elapsed();
+        }
+        Ok(TimedStream::new(stream, dns, tcp_connect, forwarding))
+    }
+
+    /// Resolves `host` and connects to the first address that accepts.
+    async fn open(&self, host: &str, port: u16) -> io::Result<(TcpStream, Duration, Duration)> {
+        let (addrs, dns) = self.resolver.resolve(host).await?;
+
+        let connect_start = Instant::now();
+        let mut last_error = None;
+        for ip in addrs {
+            match TcpStream::connect(SocketAddr::new(ip, port)).await {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    return Ok((stream, dns, connect_start.elapsed()));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        Err(last_error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No address to connect to")))
+    }
+}
+
+/// Connector that performs the rustls handshake on top of a [`TimedStream`]
+/// for `https` URIs and measures how long it took.
+#[derive(Clone)]
+pub struct TlsTimingConnector {
+    tcp: TimingConnector,
+    tls_config: Arc<ClientConfig>,
+}
+
+impl TlsTimingConnector {
+    pub fn new(tcp: TimingConnector, tls_config: Arc<ClientConfig>) -> Self {
+        Self { tcp, tls_config }
+    }
+}
+
+impl Service<Uri> for TlsTimingConnector {
+    type Response = MaybeHttpsStream<TimedStream>;
+    type Error = BoxError;
+    type Future = BoxFuture<MaybeHttpsStream<TimedStream>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.tcp.poll_ready(cx)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let timeouts = ConnectTimeouts::current();
+        let tls_config = self.tls_config.clone();
+
+        let server_name = match dst.scheme_str() {
+            Some("http") => None,
+            Some("https") => {
+                let host = dst.host().unwrap_or_default();
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                match ServerName::try_from(host.to_string()) {
+                    Ok(name) => Some((name, host.to_string())),
+                    Err(e) => return Box::pin(async move { Err(e.into()) }),
+                }
+            }
+            other => {
+                let message = format!("Unsupported scheme {}", other.unwrap_or("(none)"));
+                return Box::pin(async move {
+                    Err(io::Error::new(io::ErrorKind::InvalidInput, message).into())
+                });
+            }
+        };
+
+        let connecting = self.tcp.call(dst);
+
+        Box::pin(async move {
+            let tcp = connecting.await?;
+            let Some((server_name, host)) = server_name else {
+                return Ok(MaybeHttpsStream::Http(tcp));
+            };
+
+            let handshake_start = Instant::now();
+            let tls = with_timeout(
+                timeouts.tls_handshake,
+                RelayErrorKind::TlsHandshakeTimeout,
+                TlsConnector::from(tls_config).connect(server_name, TokioIo::new(tcp)),
+            )
+            .await?
+            .map_err(|e| TlsError::handshake(&host, e))?;
+
+            let ready_at = Instant::now();
+            tls.get_ref()
+                .0
+                .inner()
+                .timings()
+                .record_tls_handshake(ready_at.duration_since(handshake_start), ready_at);
+
+            Ok(MaybeHttpsStream::from(tls))
+        })
+    }
+}
+
+/// The error a request fails with when its connection couldn't be made:
+/// timeouts, proxy and TLS failures keep their kind, anything else is
+/// reported as is.
+pub fn connect_error(error: &(dyn std::error::Error + 'static)) -> anyhow::Error {
+    if let Some(timeout) = TimeoutError::find(error) {
+        anyhow::Error::new(timeout)
+    } else if let Some(proxy) = ProxyError::find(error) {
+        anyhow::Error::new(proxy)
+    } else if let Some(tls) = TlsError::find(error) {
+        anyhow::Error::new(tls)
+    } else {
+        anyhow::anyhow!("Request failed: {}", error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolver_skips_dns_for_ip_literals() {
+        let resolver = TimingResolver::new();
+
+        let (addrs, dns) = resolver.resolve("127.0.0.1").await.unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+        assert_eq!(dns, Duration::ZERO);
+
+        let (addrs, _) = resolver.resolve("[::1]").await.unwrap();
+        assert_eq!(addrs, vec!["::1".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_connector_records_tcp_phase() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut connector = TimingConnector::default();
+        let stream = connector
+            .call(format!("http://{}/", addr).parse().unwrap())
+            .await
+            .unwrap();
+
+        let timings = stream.timings().clone();
+        let phases = timings.claim().expect("first claim should return phases");
+        assert_eq!(phases.dns, Duration::ZERO);
+        assert_eq!(phases.tls_handshake, Duration::ZERO);
+
+        // Subsequent claims mean the connection is being reused
+        assert!(timings.claim().is_none());
+    }
+
+    // Relies on Linux dropping SYNs once a listener's accept queue is full
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_connector_connect_timeout() {
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let listener = socket.listen(1).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Fill the accept queue without ever accepting
+        let mut fillers = Vec::new();
+        for _ in 0..4 {
+            if let Ok(Ok(stream)) =
+                tokio::time::timeout(Duration::from_millis(100), TcpStream::connect(addr)).await
+            {
+                fillers.push(stream);
+            }
+        }
+
+        let timeouts = ConnectTimeouts {
+            connect: Some(Duration::from_millis(200)),
+            tls_handshake: None,
+        };
+        let mut connector = TimingConnector::default();
+        let uri: Uri = format!("http://{}/", addr).parse().unwrap();
+        let result = crate::timeouts::CONNECT_TIMEOUTS
+            .scope(timeouts, async { connector.call(uri).await })
+            .await;
+
+        let error = result.err().expect("connect should time out");
+        let timeout = crate::timeouts::TimeoutError::find(error.as_ref()).unwrap();
+        assert_eq!(timeout.kind, RelayErrorKind::ConnectTimeout);
+    }
+}
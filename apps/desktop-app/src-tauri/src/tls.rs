@@ -0,0 +1,548 @@
+//! Server certificate verification and client certificates.
+//!
+//! Workspaces can trust CAs of their own on top of the system's, accept
+//! certificates by their SHA-256 fingerprint, e.g. self-signed ones, or skip
+//! verification altogether. Requests can present a client certificate from
+//! PEM files or a PKCS#12 bundle. Handshakes that fail are reported as
+//! [`TlsError`]s saying what was wrong with the certificate, and settings or
+//! certificates that can't be used fail the requests using them rather than
+//! the app.
+
+use crate::types::{ClientCertificate, RelayErrorKind, TlsSettings};
+use p12_keystore::KeyStore;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{VerifierBuilderError, WebPkiServerVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    AlertDescription, CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore,
+    SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// The CAs trusted by the system. Certificates that can't be loaded are
+/// skipped; without any, requests fail with `TLS_UNKNOWN_ISSUER`.
+pub fn native_roots() -> RootCertStore {
+    let mut roots = RootCertStore::empty();
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    roots
+}
+
+/// A client configuration verifying servers as `settings` say, trusting
+/// `roots` and the CA files of the settings, and presenting
+/// `client_certificate` to servers asking for one.
+pub fn client_config(
+    roots: &RootCertStore,
+    settings: &TlsSettings,
+    client_certificate: Option<&ClientCertificate>,
+) -> Result<ClientConfig, TlsError> {
+    let builder = ClientConfig::builder();
+    let provider = builder.crypto_provider().clone();
+
+    let mut roots = roots.clone();
+    for path in &settings.ca_files {
+        let certs = CertificateDer::pem_file_iter(path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| TlsError::config(format!("Failed to load CA file {}: {}", path, e)))?;
+        if certs.is_empty() {
+            return Err(TlsError::config(format!(
+                "No certificates in CA file {}",
+                path
+            )));
+        }
+        for cert in certs {
+            roots.add(cert).map_err(|e| {
+                TlsError::config(format!("Invalid certificate in CA file {}: {}", path, e))
+            })?;
+        }
+    }
+    let pins = settings
+        .pinned_fingerprints
+        .iter()
+        .map(|fingerprint| parse_fingerprint(fingerprint))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let builder = if pins.is_empty() && !settings.skip_verification {
+        builder.with_root_certificates(roots)
+    } else {
+        // Without any CA to check them against, only pinned certificates pass
+        let webpki =
+            match WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+                .build()
+            {
+                Ok(webpki) => Some(webpki),
+                Err(VerifierBuilderError::NoRootAnchors) => None,
+                Err(e) => return Err(TlsError::config(format!("Invalid TLS settings: {}", e))),
+            };
+        let verifier = TrustingVerifier {
+            webpki,
+            provider,
+            pins,
+            skip_verification: settings.skip_verification,
+        };
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+    };
+
+    let Some(client_certificate) = client_certificate else {
+        return Ok(builder.with_no_client_auth());
+    };
+    let (chain, key) = load_client_certificate(client_certificate)?;
+    builder
+        .with_client_auth_cert(chain, key)
+        .map_err(|e| TlsError::config(format!("Invalid client certificate: {}", e)))
+}
+
+/// Reads the certificate chain and private key of a client certificate.
+fn load_client_certificate(
+    certificate: &ClientCertificate,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), TlsError> {
+    match certificate {
+        ClientCertificate::Pem {
+            cert_file,
+            key_file,
+        } => {
+            let chain = CertificateDer::pem_file_iter(cert_file)
+                .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| {
+                    TlsError::config(format!(
+                        "Failed to load client certificate {}: {}",
+                        cert_file, e
+                    ))
+                })?;
+            if chain.is_empty() {
+                return Err(TlsError::config(format!(
+                    "No certificates in client certificate file {}",
+                    cert_file
+                )));
+            }
+            let key = PrivateKeyDer::from_pem_file(key_file).map_err(|e| {
+                TlsError::config(format!("Failed to load private key {}: {}", key_file, e))
+            })?;
+            Ok((chain, key))
+        }
+        ClientCertificate::Pkcs12 { file, password } => {
+            let bundle = std::fs::read(file).map_err(|e| {
+                TlsError::config(format!("Failed to read PKCS#12 file {}: {}", file, e))
+            })?;
+            let store = KeyStore::from_pkcs12(&bundle, password.as_deref().unwrap_or_default())
+                .map_err(|e| {
+                    TlsError::config(format!("Failed to open PKCS#12 file {}: {}", file, e))
+                })?;
+            let (_, key_chain) = store.private_key_chain().ok_or_else(|| {
+                TlsError::config(format!("No private key in PKCS#12 file {}", file))
+            })?;
+            let chain = key_chain
+                .chain()
+                .iter()
+                .map(|cert| CertificateDer::from(cert.as_der().to_vec()))
+                .collect();
+            // Key bags hold PKCS#8 private keys
+            let key = PrivateKeyDer::Pkcs8(key_chain.key().to_vec().into());
+            Ok((chain, key))
+        }
+    }
+}
+
+/// Parses a SHA-256 fingerprint in hex, with or without separating colons.
+pub fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32], TlsError> {
+    let digits: String = fingerprint
+        .trim()
+        .trim_start_matches("sha256/")
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect();
+    hex::decode(&digits)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| TlsError::config(format!("Invalid SHA-256 fingerprint {}", fingerprint)))
+}
+
+pub fn fingerprint(cert: &CertificateDer<'_>) -> [u8; 32] {
+    Sha256::digest(cert.as_ref()).into()
+}
+
+/// Verifies certificates like the default verifier, but accepts pinned ones
+/// or, when verification is skipped, any. Handshake signatures are verified
+/// either way, so the server still proves it holds the certificate's key.
+#[derive(Debug)]
+struct TrustingVerifier {
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    provider: Arc<CryptoProvider>,
+    pins: Vec<[u8; 32]>,
+    skip_verification: bool,
+}
+
+impl ServerCertVerifier for TrustingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.skip_verification || self.pins.contains(&fingerprint(end_entity)) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        match &self.webpki {
+            Some(webpki) => webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            ),
+            None => Err(rustls::Error::InvalidCertificate(
+                CertificateError::UnknownIssuer,
+            )),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// A TLS handshake that failed, or TLS settings that can't be used.
+#[derive(Debug, Clone)]
+pub struct TlsError {
+    pub kind: RelayErrorKind,
+    message: String,
+}
+
+impl TlsError {
+    pub fn config(message: String) -> Self {
+        Self {
+            kind: RelayErrorKind::TlsConfig,
+            message,
+        }
+    }
+
+    /// Describes a handshake with `host` that failed with `error`.
+    pub fn handshake(host: &str, error: io::Error) -> Self {
+        let rustls_error = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<rustls::Error>());
+        Self {
+            kind: rustls_error.map_or(RelayErrorKind::TlsHandshakeFailed, Self::kind_of),
+            message: format!("TLS handshake with {} failed: {}", host, error),
+        }
+    }
+
+    fn kind_of(error: &rustls::Error) -> RelayErrorKind {
+        match error {
+            rustls::Error::InvalidCertificate(certificate_error) => match certificate_error {
+                CertificateError::UnknownIssuer => RelayErrorKind::TlsUnknownIssuer,
+                CertificateError::Expired
+                | CertificateError::ExpiredContext { .. }
+                | CertificateError::NotValidYet
+                | CertificateError::NotValidYetContext { .. } => {
+                    RelayErrorKind::TlsCertificateExpired
+                }
+                CertificateError::NotValidForName
+                | CertificateError::NotValidForNameContext { .. } => {
+                    RelayErrorKind::TlsNameMismatch
+                }
+                _ => RelayErrorKind::TlsInvalidCertificate,
+            },
+            // The only certificate a client sends is its own
+            rustls::Error::AlertReceived(
+                AlertDescription::CertificateRequired
+                | AlertDescription::BadCertificate
+                | AlertDescription::UnsupportedCertificate
+                | AlertDescription::CertificateRevoked
+                | AlertDescription::CertificateExpired
+                | AlertDescription::CertificateUnknown
+                | AlertDescription::UnknownCA,
+            ) => RelayErrorKind::TlsClientCertificateRejected,
+            _ => RelayErrorKind::TlsHandshakeFailed,
+        }
+    }
+
+    /// Finds a TLS error anywhere in the source chain of `error`, e.g. one
+    /// raised by the connector and wrapped by hyper. Servers checking client
+    /// certificates may only refuse them after the handshake, so TLS failures
+    /// of established connections count too.
+    pub fn find(error: &(dyn Error + 'static)) -> Option<TlsError> {
+        let mut current = Some(error);
+        while let Some(err) = current {
+            if let Some(tls_error) = err.downcast_ref::<TlsError>() {
+                return Some(tls_error.clone());
+            }
+            // I/O errors don't report what they wrap as their source
+            let rustls_error = err
+                .downcast_ref::<io::Error>()
+                .and_then(io::Error::get_ref)
+                .and_then(|inner| inner.downcast_ref::<rustls::Error>());
+            if let Some(rustls_error) = rustls_error {
+                return Some(Self {
+                    kind: Self::kind_of(rustls_error),
+                    message: format!("TLS connection failed: {}", rustls_error),
+                });
+            }
+            current = err.source();
+        }
+        None
+    }
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for TlsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprints() {
+        let expected: [u8; 32] = core::array::from_fn(|i| i as u8 * 8);
+        let hex = hex::encode(expected);
+        assert_eq!(parse_fingerprint(&hex).unwrap(), expected);
+        let with_colons = hex
+            .to_uppercase()
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| std::str::from_utf8(pair).unwrap())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(parse_fingerprint(&with_colons).unwrap(), expected);
+        assert_eq!(
+            parse_fingerprint("sha256/abcd").unwrap_err().to_string(),
+            "Invalid SHA-256 fingerprint sha256/abcd"
+        );
+        assert_eq!(
+            fingerprint(&CertificateDer::from(b"certificate".to_vec())),
+            <[u8; 32]>::from(Sha256::digest(b"certificate"))
+        );
+    }
+
+    #[test]
+    fn test_unusable_settings() {
+        let error = |settings: TlsSettings| {
+            let error = client_config(&RootCertStore::empty(), &settings, None).unwrap_err();
+            assert_eq!(error.kind, RelayErrorKind::TlsConfig);
+            error.to_string()
+        };
+        assert!(error(TlsSettings {
+            ca_files: vec!["/nonexistent/ca.pem".to_string()],
+            ..Default::default()
+        })
+        .starts_with("Failed to load CA file /nonexistent/ca.pem: I/O error"));
+
+        let path = std::env::temp_dir().join(format!("relay-empty-ca-{}.pem", std::process::id()));
+        std::fs::write(&path, "not a certificate\n").unwrap();
+        let message = error(TlsSettings {
+            ca_files: vec![path.display().to_string()],
+            ..Default::default()
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            message,
+            format!("No certificates in CA file {}", path.display())
+        );
+
+        assert_eq!(
+            error(TlsSettings {
+                pinned_fingerprints: vec!["zz".to_string()],
+                ..Default::default()
+            }),
+            "Invalid SHA-256 fingerprint zz"
+        );
+        // Neither skipping verification nor pinning needs any CA
+        let usable =
+            |settings: TlsSettings| client_config(&RootCertStore::empty(), &settings, None);
+        assert!(usable(TlsSettings {
+            skip_verification: true,
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(usable(TlsSettings {
+            pinned_fingerprints: vec![hex::encode([0; 32])],
+            ..Default::default()
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn test_handshake_errors_are_classified() {
+        let handshake = |error: rustls::Error| {
+            TlsError::handshake(
+                "example.com",
+                io::Error::new(io::ErrorKind::InvalidData, error),
+            )
+        };
+        let cases = [
+            (
+                CertificateError::UnknownIssuer,
+                RelayErrorKind::TlsUnknownIssuer,
+            ),
+            (
+                CertificateError::Expired,
+                RelayErrorKind::TlsCertificateExpired,
+            ),
+            (
+                CertificateError::NotValidYet,
+                RelayErrorKind::TlsCertificateExpired,
+            ),
+            (
+                CertificateError::NotValidForName,
+                RelayErrorKind::TlsNameMismatch,
+            ),
+            (
+                CertificateError::BadSignature,
+                RelayErrorKind::TlsInvalidCertificate,
+            ),
+        ];
+        for (certificate_error, kind) in cases {
+            assert_eq!(
+                handshake(rustls::Error::InvalidCertificate(certificate_error)).kind,
+                kind
+            );
+        }
+        let error = handshake(rustls::Error::AlertReceived(
+            rustls::AlertDescription::HandshakeFailure,
+        ));
+        assert_eq!(error.kind, RelayErrorKind::TlsHandshakeFailed);
+        assert_eq!(
+            error.to_string(),
+            "TLS handshake with example.com failed: received fatal alert: HandshakeFailure"
+        );
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(
+            TlsError::handshake("example.com", reset).kind,
+            RelayErrorKind::TlsHandshakeFailed
+        );
+
+        // Alerts refusing the client certificate may arrive after the handshake
+        let alert = rustls::Error::AlertReceived(rustls::AlertDescription::CertificateRequired);
+        let error = anyhow::Error::new(io::Error::new(io::ErrorKind::InvalidData, alert))
+            .context("connection error");
+        let error = TlsError::find(&*error).unwrap();
+        assert_eq!(error.kind, RelayErrorKind::TlsClientCertificateRejected);
+        assert_eq!(
+            error.to_string(),
+            "TLS connection failed: received fatal alert: CertificateRequired"
+        );
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        let error = anyhow::Error::new(reset).context("connection error");
+        assert!(TlsError::find(&*error).is_none());
+    }
+
+    #[test]
+    fn test_client_certificate_files() {
+        let directory =
+            std::env::temp_dir().join(format!("relay-client-certificates-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = |name: &str| directory.join(name).display().to_string();
+        let key = rcgen::KeyPair::generate().unwrap();
+        let params = rcgen::CertificateParams::new(vec!["client".to_string()]).unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        std::fs::write(path("client.pem"), cert.pem()).unwrap();
+        std::fs::write(path("client.key"), key.serialize_pem()).unwrap();
+        std::fs::write(
+            path("other.key"),
+            rcgen::KeyPair::generate().unwrap().serialize_pem(),
+        )
+        .unwrap();
+
+        let config = |client_certificate: ClientCertificate| {
+            client_config(
+                &RootCertStore::empty(),
+                &TlsSettings::default(),
+                Some(&client_certificate),
+            )
+            .map(|config| config.client_auth_cert_resolver.has_certs())
+            .map_err(|e| e.to_string())
+        };
+        let pem = |cert_file: &str, key_file: &str| ClientCertificate::Pem {
+            cert_file: path(cert_file),
+            key_file: path(key_file),
+        };
+        assert_eq!(config(pem("client.pem", "client.key")), Ok(true));
+        assert_eq!(
+            config(pem("client.key", "client.key")),
+            Err(format!(
+                "No certificates in client certificate file {}",
+                path("client.key")
+            ))
+        );
+        assert!(config(pem("client.pem", "client.pem"))
+            .unwrap_err()
+            .starts_with(&format!(
+                "Failed to load private key {}",
+                path("client.pem")
+            )));
+        assert!(config(pem("client.pem", "other.key"))
+            .unwrap_err()
+            .starts_with("Invalid client certificate"));
+
+        let pkcs12 = |file: &str| ClientCertificate::Pkcs12 {
+            file: path(file),
+            password: None,
+        };
+        assert!(config(pkcs12("missing.p12"))
+            .unwrap_err()
+            .starts_with(&format!(
+                "Failed to read PKCS#12 file {}",
+                path("missing.p12")
+            )));
+        let mut store = KeyStore::new();
+        store.add_entry(
+            "client",
+            p12_keystore::KeyStoreEntry::Certificate(
+                p12_keystore::Certificate::from_der(cert.der()).unwrap(),
+            ),
+        );
+        std::fs::write(path("client.p12"), store.writer("").write().unwrap()).unwrap();
+        assert_eq!(
+            config(pkcs12("client.p12")),
+            Err(format!(
+                "No private key in PKCS#12 file {}",
+                path("client.p12")
+            ))
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -20,11 +20,69 @@ pub struct HeaderSchema {
 pub type RequestHeaders = HashMap<String, HeaderSchema>;
 pub type ResponseHeaders = HashMap<String, HeaderSchema>;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A request body. `content`, `filePath`, `multipart` and `form` are
+/// alternative sources; a request may set at most one of them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct RequestBody {
     #[serde(rename = "contentType")]
     pub content_type: Option<String>,
     pub content: Option<String>,
+    /// How `content` is encoded; `BASE64` carries arbitrary bytes
+    #[serde(default)]
+    pub encoding: BodyEncoding,
+    /// Local file streamed as the body
+    #[serde(rename = "filePath", default)]
+    pub file_path: Option<String>,
+    /// Parts of a `multipart/form-data` body
+    #[serde(default)]
+    pub multipart: Option<Vec<MultipartPart>>,
+    /// Fields of an `application/x-www-form-urlencoded` body
+    #[serde(default)]
+    pub form: Option<Vec<FormField>>,
+}
+
+/// A key/value entry of a url-encoded form body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormField {
+    pub id: String,
+    pub name: String,
+    pub value: String,
+    /// Disabled fields are kept in the request but not sent
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+/// One part of a `multipart/form-data` body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartPart {
+    pub name: String,
+    #[serde(flatten)]
+    pub value: MultipartValue,
+    /// Defaults to `application/octet-stream` for files and none for text
+    #[serde(rename = "contentType", default)]
+    pub content_type: Option<String>,
+    /// Additional headers of the part
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum MultipartValue {
+    Text {
+        value: String,
+    },
+    File {
+        #[serde(rename = "filePath")]
+        file_path: String,
+        /// Defaults to the name of the file
+        #[serde(default)]
+        filename: Option<String>,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,9 +101,10 @@ pub enum AuthorizationType {
     Custom,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Authorization {
+    #[default]
     #[serde(rename = "NONE")]
     None,
     #[serde(rename = "BASIC")]
@@ -60,19 +119,118 @@ pub enum Authorization {
     #[serde(rename = "API_KEY")]
     ApiKey {
         key: String,
+        /// Header or query parameter name the key is sent as
+        #[serde(default = "default_api_key_name")]
+        name: String,
+        #[serde(default)]
+        location: ApiKeyLocation,
     },
     #[serde(rename = "OAUTH2")]
     OAuth2 {
+        /// Pre-obtained access token, used when no `flow` is configured
+        #[serde(default)]
         token: String,
+        /// Obtains (and caches) the token from an authorization server instead
+        #[serde(default)]
+        flow: Option<OAuth2Flow>,
     },
     #[serde(rename = "CUSTOM")]
     Custom {
         token: String,
+        /// Sent as `Authorization: <scheme> <token>`, or just the token if unset
+        #[serde(default)]
+        scheme: Option<String>,
     },
+    /// HTTP Digest (RFC 7616), answered after the server's 401 challenge
+    #[serde(rename = "DIGEST")]
+    Digest {
+        username: String,
+        password: String,
+    },
+    /// AWS Signature Version 4, computed over the final request
+    #[serde(rename = "AWS_SIGV4")]
+    AwsSigV4 {
+        #[serde(rename = "accessKey")]
+        access_key: String,
+        #[serde(rename = "secretKey")]
+        secret_key: String,
+        #[serde(rename = "sessionToken", default)]
+        session_token: Option<String>,
+        region: String,
+        service: String,
+        /// Sign with `UNSIGNED-PAYLOAD` instead of hashing the body
+        #[serde(rename = "unsignedPayload", default)]
+        unsigned_payload: bool,
+    },
+}
+
+fn default_api_key_name() -> String {
+    "X-API-Key".to_string()
 }
 
+/// How the backend obtains an OAuth 2.0 access token.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OAuth2Flow {
+    #[serde(rename = "tokenUrl")]
+    pub token_url: String,
+    #[serde(rename = "clientId")]
+    pub client_id: String,
+    #[serde(rename = "clientSecret", default)]
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub scope: Option<String>,
+    #[serde(rename = "clientAuthentication", default)]
+    pub client_authentication: OAuth2ClientAuthentication,
+    pub grant: OAuth2Grant,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum OAuth2Grant {
+    #[serde(rename = "CLIENT_CREDENTIALS")]
+    ClientCredentials,
+    #[serde(rename = "PASSWORD")]
+    Password { username: String, password: String },
+    #[serde(rename = "REFRESH_TOKEN")]
+    RefreshToken {
+        #[serde(rename = "refreshToken")]
+        refresh_token: String,
+    },
+    /// Authorization code with PKCE, redirected to a loopback listener
+    #[serde(rename = "AUTHORIZATION_CODE")]
+    AuthorizationCode {
+        #[serde(rename = "authorizationUrl")]
+        authorization_url: String,
+        /// Port of the loopback redirect listener, random if unset
+        #[serde(rename = "redirectPort", default)]
+        redirect_port: Option<u16>,
+    },
+}
+
+/// Where client credentials are sent in token requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OAuth2ClientAuthentication {
+    /// HTTP Basic authorization header
+    #[default]
+    #[serde(rename = "BASIC")]
+    Basic,
+    /// `client_id` and `client_secret` form parameters
+    #[serde(rename = "BODY")]
+    Body,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiKeyLocation {
+    #[default]
+    #[serde(rename = "HEADER")]
+    Header,
+    #[serde(rename = "QUERY")]
+    Query,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub enum RequestMethod {
+    #[default]
     GET,
     POST,
     PUT,
@@ -84,6 +242,13 @@ pub enum RequestMethod {
 
 // RequestMethod conversion is now handled directly in relay.rs
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerTimingMetric {
+    pub name: String,
+    pub duration: Option<f64>,
+    pub description: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponsePerformance {
     pub duration: f64,
@@ -92,19 +257,193 @@ pub struct ResponsePerformance {
     pub processing_time: f64,
     #[serde(rename = "transferTime")]
     pub transfer_time: f64,
+    /// Body bytes received, before content decoding
     #[serde(rename = "transferSize")]
     pub transfer_size: usize,
+    /// Body bytes after content decoding
+    #[serde(rename = "decodedSize")]
+    pub decoded_size: usize,
     #[serde(rename = "transferEncoding")]
     pub transfer_encoding: String,
+    pub dns: f64,
+    #[serde(rename = "tcpConnect")]
+    pub tcp_connect: f64,
+    #[serde(rename = "tlsHandshake")]
+    pub tls_handshake: f64,
+    pub ttfb: f64,
+    pub download: f64,
+    #[serde(rename = "connectionReused")]
+    pub connection_reused: bool,
+    #[serde(rename = "serverTiming")]
+    pub server_timing: Vec<ServerTimingMetric>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Request {
     pub url: String,
     pub method: RequestMethod,
     pub headers: HashMap<String, serde_json::Value>,
     pub body: RequestBody,
     pub params: HashMap<String, serde_json::Value>,
+    /// Client-supplied id used to cancel the request while it is in flight
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Overrides the service-wide timeouts for this request
+    #[serde(default)]
+    pub timeouts: TimeoutSettings,
+    #[serde(default)]
+    pub authorization: Authorization,
+    /// Return the body exactly as received instead of decoding its
+    /// `Content-Encoding`
+    #[serde(rename = "rawBody", default)]
+    pub raw_body: bool,
+    /// Most bytes of a streamed body kept for the final response
+    #[serde(rename = "retainLimit", default)]
+    pub retain_limit: Option<usize>,
+    /// Most bytes a compressed body may decode to. Streamed bodies are
+    /// decoded chunk by chunk, so the limit applies to each chunk
+    #[serde(rename = "decodedLimit", default)]
+    pub decoded_limit: Option<usize>,
+    #[serde(rename = "redirectPolicy", default)]
+    pub redirect_policy: RedirectPolicy,
+    /// Workspace whose cookie jar and TLS settings the request uses, the
+    /// default ones if unset
+    #[serde(rename = "workspaceId", default)]
+    pub workspace_id: Option<String>,
+    #[serde(default)]
+    pub proxy: ProxySetting,
+    /// Presented to servers that ask the client to authenticate
+    #[serde(rename = "clientCertificate", default)]
+    pub client_certificate: Option<ClientCertificate>,
+}
+
+/// A cookie in a jar (RFC 6265).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercase, without a leading dot
+    pub domain: String,
+    /// Sent to `domain` only, not to its subdomains
+    #[serde(rename = "hostOnly", default)]
+    pub host_only: bool,
+    pub path: String,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(rename = "httpOnly", default)]
+    pub http_only: bool,
+    #[serde(rename = "sameSite", default)]
+    pub same_site: Option<String>,
+    /// Expiry in seconds since the Unix epoch; session cookies have none
+    #[serde(default)]
+    pub expires: Option<i64>,
+    /// Milliseconds since the Unix epoch, ordering cookies with equally long
+    /// paths
+    #[serde(rename = "createdAt", default)]
+    pub created_at: i64,
+}
+
+/// Which redirects are followed. Redirects that aren't are returned as the
+/// response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RedirectPolicy {
+    #[default]
+    Off,
+    /// Follows up to `maxRedirects` redirects, failing the request after that
+    Follow {
+        #[serde(rename = "maxRedirects", default = "default_max_redirects")]
+        max_redirects: u32,
+    },
+    /// Like `Follow`, but stops at the first redirect to another origin
+    SameOrigin {
+        #[serde(rename = "maxRedirects", default = "default_max_redirects")]
+        max_redirects: u32,
+    },
+}
+
+fn default_max_redirects() -> u32 {
+    10
+}
+
+/// How the server certificates of a workspace's requests are verified.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TlsSettings {
+    /// PEM files of CAs trusted on top of the system's
+    #[serde(rename = "caFiles", default)]
+    pub ca_files: Vec<String>,
+    /// SHA-256 fingerprints, in hex, of certificates trusted whoever signed
+    /// them, e.g. self-signed ones
+    #[serde(rename = "pinnedFingerprints", default)]
+    pub pinned_fingerprints: Vec<String>,
+    /// Accepts any certificate. Only for servers that can't be verified
+    /// otherwise
+    #[serde(rename = "skipVerification", default)]
+    pub skip_verification: bool,
+}
+
+/// A certificate and private key the client authenticates with (mutual TLS).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ClientCertificate {
+    /// PEM files of the certificate chain, leaf first, and of the private
+    /// key, which may be the same file
+    Pem {
+        #[serde(rename = "certFile")]
+        cert_file: String,
+        #[serde(rename = "keyFile")]
+        key_file: String,
+    },
+    /// A PKCS#12 bundle holding both
+    Pkcs12 {
+        file: String,
+        #[serde(default)]
+        password: Option<String>,
+    },
+}
+
+/// Which proxy requests go through.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ProxySetting {
+    /// Requests use the service-wide setting, which uses the `HTTP_PROXY`,
+    /// `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` environment variables
+    #[default]
+    Default,
+    /// Connects directly, whatever the environment says
+    Direct,
+    /// Goes through an `http://`, `socks5://` or `socks5h://` proxy
+    Custom {
+        url: String,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+        /// Hosts reached directly, in the format of `NO_PROXY`
+        #[serde(rename = "noProxy", default)]
+        no_proxy: Option<String>,
+    },
+}
+
+/// Timeouts in milliseconds for each phase of a request. Unset values fall
+/// back to the service-wide settings; `0` disables the timeout.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeoutSettings {
+    /// DNS resolution and TCP connect
+    #[serde(default)]
+    pub connect: Option<u64>,
+    #[serde(default, rename = "tlsHandshake")]
+    pub tls_handshake: Option<u64>,
+    /// From sending the request until the response headers arrive. Includes
+    /// connection setup, which is also bounded by `connect` and `tlsHandshake`.
+    #[serde(default, rename = "firstByte")]
+    pub first_byte: Option<u64>,
+    /// Longest allowed gap between two chunks of the response body
+    #[serde(default, rename = "bodyIdle")]
+    pub body_idle: Option<u64>,
+    /// Overall deadline for the whole request
+    #[serde(default)]
+    pub total: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -114,8 +453,401 @@ pub struct Response {
     pub content_type: Option<String>,
     #[serde(rename = "statusCode")]
     pub status_code: u16,
+    /// The body as text, or base64 encoded bytes for binary bodies
     pub content: String,
+    #[serde(rename = "bodyEncoding")]
+    pub body_encoding: BodyEncoding,
+    /// MIME type sniffed from the leading bytes of a binary body
+    #[serde(rename = "detectedMimeType")]
+    pub detected_mime_type: Option<String>,
+    /// Charset the text body was decoded from
+    pub charset: Option<String>,
+    /// Whether `content` holds only the start of a streamed body
+    #[serde(rename = "bodyTruncated")]
+    pub body_truncated: bool,
     pub performance: ResponsePerformance,
+    /// Every round trip made to obtain the response when the authorization
+    /// scheme needs more than one (e.g. a Digest challenge and its answer)
+    #[serde(rename = "authExchanges")]
+    pub auth_exchanges: Vec<AuthExchange>,
+    /// The redirects followed to reach the response, in order
+    pub redirects: Vec<RedirectHop>,
+}
+
+/// A redirect response and the request it answered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedirectHop {
+    pub method: String,
+    pub url: String,
+    #[serde(rename = "statusCode")]
+    pub status_code: u16,
+    pub headers: ResponseHeaders,
+    /// Where the redirect pointed, resolved against `url`
+    pub location: String,
+    /// Time from sending the request until the redirect was fully read (ms)
+    pub duration: f64,
+}
+
+/// How `Response::content` and `RequestBody::content` represent a body.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum BodyEncoding {
+    #[default]
+    Text,
+    Base64,
+}
+
+/// One request/response round trip of a multi-step authorization.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthExchange {
+    pub method: String,
+    pub url: String,
+    #[serde(rename = "requestHeaders")]
+    pub request_headers: ResponseHeaders,
+    #[serde(rename = "statusCode")]
+    pub status_code: u16,
+    pub headers: ResponseHeaders,
+    /// Time from sending the request until its response was fully read (ms)
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RelayErrorKind {
+    ConnectTimeout,
+    TlsHandshakeTimeout,
+    FirstByteTimeout,
+    BodyIdleTimeout,
+    TotalTimeout,
+    /// The proxy couldn't be reached or refused to connect to the server
+    Proxy,
+    /// The server's certificate isn't signed by a trusted CA, e.g. because
+    /// it is self-signed
+    TlsUnknownIssuer,
+    /// The server's certificate has expired or isn't valid yet
+    TlsCertificateExpired,
+    /// The server's certificate is for another host name
+    TlsNameMismatch,
+    /// The server's certificate is invalid for another reason
+    TlsInvalidCertificate,
+    /// The server refused the client certificate, or the lack of one
+    TlsClientCertificateRejected,
+    /// The TLS handshake failed for a reason other than the certificate
+    TlsHandshakeFailed,
+    /// The TLS settings can't be used, e.g. a CA file is missing
+    TlsConfig,
+}
+
+/// Upload progress of a request body, reported as chunks are handed to the
+/// connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadProgress {
+    /// Id of the request, when the client supplied one
+    pub id: Option<String>,
+    pub sent: u64,
+    pub total: u64,
+}
+
+/// Progress of a streamed response, pushed to the frontend as it happens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum StreamEvent {
+    /// The response headers arrived
+    Headers {
+        #[serde(rename = "statusCode")]
+        status_code: u16,
+        headers: ResponseHeaders,
+    },
+    /// A piece of the body as received, base64 encoded
+    Chunk { data: String, offset: u64 },
+    /// The request finished, failed or was cancelled
+    Complete(Box<RelayResponse>),
+}
+
+/// Activity of a Server-Sent Events connection, pushed to the frontend as it
+/// happens. Timestamps are milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EventStreamEvent {
+    /// The stream was (re)opened
+    Open {
+        #[serde(rename = "statusCode")]
+        status_code: u16,
+        headers: ResponseHeaders,
+        timestamp: i64,
+    },
+    /// An event sent by the server
+    Message {
+        /// The last event ID when the event was dispatched, empty if none
+        id: String,
+        event: String,
+        data: String,
+        timestamp: i64,
+    },
+    /// The connection was lost and will be reopened after `delay`
+    /// milliseconds, resuming from `lastEventId`
+    Reconnecting {
+        reason: String,
+        delay: u64,
+        #[serde(rename = "lastEventId")]
+        last_event_id: String,
+        timestamp: i64,
+    },
+    /// The stream ended for good: closed by the server, failed or cancelled
+    Closed(Box<RelayResponse>),
+}
+
+/// A frame the frontend sends on an open WebSocket session. Binary payloads
+/// are base64 encoded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WebSocketMessage {
+    Text { data: String },
+    Binary { data: String },
+    Ping {
+        #[serde(default)]
+        data: String,
+    },
+}
+
+/// Activity of a WebSocket session, pushed to the frontend as it happens.
+/// Binary payloads are base64 encoded and timestamps are milliseconds since
+/// the Unix epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WebSocketEvent {
+    /// The upgrade succeeded
+    Open {
+        #[serde(rename = "statusCode")]
+        status_code: u16,
+        headers: ResponseHeaders,
+        /// Subprotocol selected by the server
+        protocol: Option<String>,
+        timestamp: i64,
+    },
+    Text {
+        data: String,
+        timestamp: i64,
+    },
+    Binary {
+        data: String,
+        timestamp: i64,
+    },
+    Ping {
+        data: String,
+        timestamp: i64,
+    },
+    Pong {
+        data: String,
+        timestamp: i64,
+    },
+    /// The server sent a close frame, or answered ours
+    Close {
+        code: Option<u16>,
+        reason: String,
+        timestamp: i64,
+    },
+    /// The session ended: closed, failed or cancelled
+    Closed(Box<RelayResponse>),
+}
+
+/// How a GraphQL operation is sent over HTTP.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GraphQLMethod {
+    /// A JSON body
+    #[default]
+    POST,
+    /// Query string parameters, e.g. for cacheable queries
+    GET,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GraphQLRequest {
+    pub url: String,
+    pub query: String,
+    /// Values of the operation's variables, an object
+    #[serde(default)]
+    pub variables: Option<serde_json::Value>,
+    /// Operation to run when the document holds several
+    #[serde(rename = "operationName", default)]
+    pub operation_name: Option<String>,
+    #[serde(default)]
+    pub method: GraphQLMethod,
+    #[serde(default)]
+    pub headers: HashMap<String, serde_json::Value>,
+    /// Client-supplied id used to cancel the request while it is in flight
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub timeouts: TimeoutSettings,
+    #[serde(default)]
+    pub authorization: Authorization,
+}
+
+/// A GraphQL response body split into its parts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GraphQLResult {
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+    #[serde(default)]
+    pub errors: Vec<GraphQLError>,
+    #[serde(default)]
+    pub extensions: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GraphQLError {
+    #[serde(default)]
+    pub message: String,
+    /// Positions in the query the error refers to
+    #[serde(default)]
+    pub locations: Vec<GraphQLLocation>,
+    /// Response field the error occurred in, as names and list indices
+    #[serde(default)]
+    pub path: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub extensions: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphQLLocation {
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphQLRelayResponse {
+    pub status: String,
+    pub response: Option<Response>,
+    /// The response body split into `data` and `errors`, unless it isn't a
+    /// GraphQL response
+    pub graphql: Option<GraphQLResult>,
+    pub message: Option<String>,
+    #[serde(rename = "errorKind")]
+    pub error_kind: Option<RelayErrorKind>,
+    pub timestamp: String,
+}
+
+/// The introspected schema of a GraphQL endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphQLSchema {
+    pub url: String,
+    /// The `__schema` field of the introspection result
+    pub schema: serde_json::Value,
+    #[serde(rename = "fetchedAt")]
+    pub fetched_at: String,
+}
+
+/// Where the descriptors of a gRPC service come from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum GrpcSchemaSource {
+    /// `.proto` files, with the directories their imports are looked up in
+    ProtoFiles {
+        files: Vec<String>,
+        #[serde(rename = "importPaths", default)]
+        import_paths: Vec<String>,
+    },
+    /// The server's reflection service
+    Reflection,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcRequest {
+    /// Server address including the scheme, e.g. `http://localhost:50051`
+    pub url: String,
+    /// Fully-qualified service name, unused when listing services
+    #[serde(default)]
+    pub service: String,
+    #[serde(default)]
+    pub method: String,
+    pub schema: GrpcSchemaSource,
+    /// Request messages in the protobuf JSON mapping. Unary and
+    /// server-streaming methods take exactly one.
+    #[serde(default)]
+    pub messages: Vec<serde_json::Value>,
+    /// Custom metadata, sent as request headers
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+    /// Client-supplied id used to cancel the call while it is in flight
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub timeouts: TimeoutSettings,
+    #[serde(default)]
+    pub authorization: Authorization,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcResponse {
+    /// HTTP status of the response
+    #[serde(rename = "statusCode")]
+    pub status_code: u16,
+    #[serde(rename = "grpcStatus")]
+    pub grpc_status: u32,
+    /// Name of the status code, e.g. `NOT_FOUND`
+    #[serde(rename = "grpcStatusName")]
+    pub grpc_status_name: String,
+    #[serde(rename = "grpcMessage")]
+    pub grpc_message: String,
+    pub headers: ResponseHeaders,
+    pub trailers: ResponseHeaders,
+    /// Response messages in the protobuf JSON mapping
+    pub messages: Vec<serde_json::Value>,
+    /// Time from sending the call until its status arrived (ms)
+    pub duration: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcRelayResponse {
+    pub status: String,
+    pub response: Option<GrpcResponse>,
+    pub message: Option<String>,
+    #[serde(rename = "errorKind")]
+    pub error_kind: Option<RelayErrorKind>,
+    pub timestamp: String,
+}
+
+/// Progress of a gRPC call, pushed to the frontend as it happens.
+/// Timestamps are milliseconds since the Unix epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum GrpcEvent {
+    /// Response headers (initial metadata) arrived
+    Headers {
+        headers: ResponseHeaders,
+        timestamp: i64,
+    },
+    Message {
+        data: serde_json::Value,
+        timestamp: i64,
+    },
+    /// The call ended, successfully or not
+    Complete(Box<GrpcRelayResponse>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcServiceSchema {
+    /// Fully-qualified service name
+    pub name: String,
+    pub methods: Vec<GrpcMethodSchema>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrpcMethodSchema {
+    pub name: String,
+    #[serde(rename = "clientStreaming")]
+    pub client_streaming: bool,
+    #[serde(rename = "serverStreaming")]
+    pub server_streaming: bool,
+    #[serde(rename = "inputType")]
+    pub input_type: String,
+    #[serde(rename = "outputType")]
+    pub output_type: String,
+    /// The input message with every field at its default, as a starting
+    /// point for requests
+    #[serde(rename = "requestTemplate")]
+    pub request_template: serde_json::Value,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -123,5 +855,7 @@ pub struct RelayResponse {
     pub status: String,
     pub response: Option<Response>,
     pub message: Option<String>,
+    #[serde(rename = "errorKind")]
+    pub error_kind: Option<RelayErrorKind>,
     pub timestamp: String,
 }
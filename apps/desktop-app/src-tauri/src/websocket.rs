@@ -0,0 +1,305 @@
+//! WebSocket sessions (RFC 6455).
+//!
+//! The upgrade goes through the relay's own connector, so sessions resolve,
+//! time out and verify certificates like any other request. Open sessions are
+//! registered by request id, letting later commands send frames on them.
+
+use crate::timing::{connect_error, TimedStream, TlsTimingConnector};
+use crate::types::{WebSocketEvent, WebSocketMessage};
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Utc;
+use futures::stream::SplitSink;
+use futures::SinkExt;
+use hyper::header::HeaderMap;
+use hyper::Uri;
+use hyper_rustls::MaybeHttpsStream;
+use hyper_util::rt::TokioIo;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Response as UpgradeResponse;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+use tower::Service;
+
+/// Control frames carry at most this many payload bytes.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+pub type WebSocket = WebSocketStream<TokioIo<MaybeHttpsStream<TimedStream>>>;
+
+type Sink = Arc<tokio::sync::Mutex<SplitSink<WebSocket, Message>>>;
+
+/// Opens a connection to a `ws` or `wss` URI and performs the upgrade,
+/// sending `headers` along with the handshake's own.
+pub async fn connect(
+    mut connector: TlsTimingConnector,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<(WebSocket, UpgradeResponse)> {
+    let transport = match uri.scheme_str() {
+        Some("ws") => "http",
+        Some("wss") => "https",
+        other => {
+            return Err(anyhow!(
+                "Unsupported WebSocket scheme {}",
+                other.unwrap_or("(none)")
+            ))
+        }
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.scheme = Some(transport.parse()?);
+    let stream = connector
+        .call(Uri::from_parts(parts)?)
+        .await
+        .map_err(|e| connect_error(e.as_ref()))?;
+
+    let mut request = uri.clone().into_client_request()?;
+    for name in headers.keys() {
+        // The handshake headers are generated for each upgrade
+        if request.headers().contains_key(name) {
+            continue;
+        }
+        for value in headers.get_all(name) {
+            request.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+
+    tokio_tungstenite::client_async(request, TokioIo::new(stream))
+        .await
+        .map_err(|e| match e {
+            WsError::Http(response) => anyhow!(
+                "WebSocket upgrade failed with status {}",
+                response.status().as_u16()
+            ),
+            e => anyhow!("WebSocket handshake failed: {}", e),
+        })
+}
+
+/// The frame to send for a message from the frontend.
+pub fn outgoing_frame(message: WebSocketMessage) -> Result<Message> {
+    let decode = |data: String| {
+        STANDARD
+            .decode(data)
+            .map_err(|e| anyhow!("Invalid base64 WebSocket payload: {}", e))
+    };
+    Ok(match message {
+        WebSocketMessage::Text { data } => Message::Text(data),
+        WebSocketMessage::Binary { data } => Message::Binary(decode(data)?),
+        WebSocketMessage::Ping { data } => {
+            let payload = decode(data)?;
+            if payload.len() > MAX_CONTROL_PAYLOAD {
+                return Err(anyhow!(
+                    "Ping payloads are limited to {} bytes",
+                    MAX_CONTROL_PAYLOAD
+                ));
+            }
+            Message::Ping(payload)
+        }
+    })
+}
+
+/// The close frame starting the closing handshake. Only `1000` (normal
+/// closure) and the `3000`-`4999` range may be sent by an application.
+pub fn close_frame(code: Option<u16>, reason: &str) -> Result<Message> {
+    let Some(code) = code else {
+        if !reason.is_empty() {
+            return Err(anyhow!("A close reason needs a close code"));
+        }
+        return Ok(Message::Close(None));
+    };
+    if code != 1000 && !(3000..5000).contains(&code) {
+        return Err(anyhow!("Invalid close code {}", code));
+    }
+    // The code takes two bytes of the control frame's payload
+    if reason.len() > MAX_CONTROL_PAYLOAD - 2 {
+        return Err(anyhow!(
+            "Close reasons are limited to {} bytes",
+            MAX_CONTROL_PAYLOAD - 2
+        ));
+    }
+    Ok(Message::Close(Some(CloseFrame {
+        code: CloseCode::from(code),
+        reason: reason.to_string().into(),
+    })))
+}
+
+/// The event reporting a received frame. Raw frames are never read, so they
+/// have none.
+pub fn incoming_event(message: Message) -> Option<WebSocketEvent> {
+    let timestamp = Utc::now().timestamp_millis();
+    Some(match message {
+        Message::Text(data) => WebSocketEvent::Text { data, timestamp },
+        Message::Binary(data) => WebSocketEvent::Binary {
+            data: STANDARD.encode(data),
+            timestamp,
+        },
+        Message::Ping(data) => WebSocketEvent::Ping {
+            data: STANDARD.encode(data),
+            timestamp,
+        },
+        Message::Pong(data) => WebSocketEvent::Pong {
+            data: STANDARD.encode(data),
+            timestamp,
+        },
+        Message::Close(frame) => WebSocketEvent::Close {
+            code: frame.as_ref().map(|frame| u16::from(frame.code)),
+            reason: frame
+                .map(|frame| frame.reason.into_owned())
+                .unwrap_or_default(),
+            timestamp,
+        },
+        Message::Frame(_) => return None,
+    })
+}
+
+#[derive(Default)]
+struct Entries {
+    next_generation: AtomicU64,
+    sinks: Mutex<HashMap<String, (u64, Sink)>>,
+}
+
+/// Open sessions by id, shared between clones of the relay.
+#[derive(Clone, Default)]
+pub struct WebSocketSessions {
+    entries: Arc<Entries>,
+}
+
+impl WebSocketSessions {
+    /// Registers the sending half of a session under `id` and returns a guard
+    /// that unregisters it when dropped. A session opened with an id that is
+    /// already in use replaces the older one.
+    pub fn register(&self, id: String, sink: SplitSink<WebSocket, Message>) -> SessionGuard {
+        let generation = self.entries.next_generation.fetch_add(1, Ordering::Relaxed);
+        self.entries.sinks.lock().unwrap().insert(
+            id.clone(),
+            (generation, Arc::new(tokio::sync::Mutex::new(sink))),
+        );
+        SessionGuard {
+            sessions: self.clone(),
+            id,
+            generation,
+        }
+    }
+
+    /// Sends a frame on the session registered under `id`.
+    pub async fn send(&self, id: &str, message: Message) -> Result<()> {
+        let sink = self
+            .entries
+            .sinks
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|(_, sink)| sink.clone())
+            .ok_or_else(|| anyhow!("No open WebSocket session with id {}", id))?;
+        let mut sink = sink.lock().await;
+        sink.send(message)
+            .await
+            .map_err(|e| anyhow!("Failed to send WebSocket frame: {}", e))
+    }
+}
+
+/// Removes a session from the registry once it ends.
+pub struct SessionGuard {
+    sessions: WebSocketSessions,
+    id: String,
+    generation: u64,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        let mut sinks = self.sessions.entries.sinks.lock().unwrap();
+        // Leave the entry alone if a newer session re-used the id
+        if matches!(sinks.get(&self.id), Some((generation, _)) if *generation == self.generation) {
+            sinks.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outgoing_frames() {
+        let frame = |message| outgoing_frame(message).map_err(|e| e.to_string());
+        assert_eq!(
+            frame(WebSocketMessage::Text {
+                data: "hello".to_string()
+            }),
+            Ok(Message::Text("hello".to_string()))
+        );
+        assert_eq!(
+            frame(WebSocketMessage::Binary {
+                data: "AAEC".to_string()
+            }),
+            Ok(Message::Binary(vec![0, 1, 2]))
+        );
+        assert_eq!(
+            frame(WebSocketMessage::Ping {
+                data: String::new()
+            }),
+            Ok(Message::Ping(Vec::new()))
+        );
+
+        assert!(frame(WebSocketMessage::Binary {
+            data: "not base64!".to_string()
+        })
+        .unwrap_err()
+        .starts_with("Invalid base64 WebSocket payload"));
+        assert_eq!(
+            frame(WebSocketMessage::Ping {
+                data: STANDARD.encode([0; 126])
+            }),
+            Err("Ping payloads are limited to 125 bytes".to_string())
+        );
+    }
+
+    #[test]
+    fn test_close_frame_rules() {
+        assert_eq!(close_frame(None, "").unwrap(), Message::Close(None));
+        let Message::Close(Some(frame)) = close_frame(Some(4001), "done").unwrap() else {
+            panic!("expected a close frame with a code");
+        };
+        assert_eq!(u16::from(frame.code), 4001);
+        assert_eq!(frame.reason, "done");
+        assert!(close_frame(Some(1000), &"x".repeat(123)).is_ok());
+
+        let error = |code, reason: &str| close_frame(code, reason).unwrap_err().to_string();
+        assert_eq!(error(None, "bye"), "A close reason needs a close code");
+        // Reserved and protocol-level codes can't be sent by applications
+        assert_eq!(error(Some(1006), ""), "Invalid close code 1006");
+        assert_eq!(error(Some(2000), ""), "Invalid close code 2000");
+        assert_eq!(
+            error(Some(1000), &"x".repeat(124)),
+            "Close reasons are limited to 123 bytes"
+        );
+    }
+
+    #[test]
+    fn test_incoming_events() {
+        let WebSocketEvent::Binary { data, .. } =
+            incoming_event(Message::Binary(vec![0xff, 0])).unwrap()
+        else {
+            panic!("expected a binary event");
+        };
+        assert_eq!(data, "/wA=");
+
+        let close = incoming_event(Message::Close(Some(CloseFrame {
+            code: CloseCode::Away,
+            reason: "restarting".into(),
+        })));
+        assert!(matches!(
+            close,
+            Some(WebSocketEvent::Close { code: Some(1001), ref reason, .. }) if reason == "restarting"
+        ));
+        assert!(matches!(
+            incoming_event(Message::Close(None)),
+            Some(WebSocketEvent::Close { code: None, ref reason, .. }) if reason.is_empty()
+        ));
+    }
+}
@@ -143,21 +143,38 @@ export async function relayHTTPRequest(
 
     // Calculate timing metrics
     const duration = responseEndTime - clientStartTime;
+    const transferSize = new TextEncoder().encode(responseBody).length;
 
     const response: Response = {
       headers: processedHeaders,
       contentType,
       statusCode: fetchResponse.status,
       content: responseBody,
+      bodyEncoding: "TEXT",
+      detectedMimeType: null,
+      charset: null,
+      bodyTruncated: false,
       performance: {
         duration,
         latency: responseStartTime - clientStartTime,
         processingTime: 0,
         transferTime: responseEndTime - responseStartTime,
-        transferSize: new TextEncoder().encode(responseBody).length,
+        transferSize,
+        // fetch hands back the body already decoded
+        decodedSize: transferSize,
         transferEncoding:
           fetchResponse.headers.get("transfer-encoding") || "identity",
+        // fetch exposes no per-phase timings
+        dns: 0,
+        tcpConnect: 0,
+        tlsHandshake: 0,
+        ttfb: responseStartTime - clientStartTime,
+        download: responseEndTime - responseStartTime,
+        connectionReused: false,
+        serverTiming: [],
       },
+      authExchanges: [],
+      redirects: [],
     };
 
     return response;
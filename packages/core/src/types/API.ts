@@ -27,6 +27,9 @@ export interface RequestBody {
   content?: string;
 }
 
+// How a response's content represents its body
+export type BodyEncoding = "TEXT" | "BASE64";
+
 export const AuthorizationTypes = {
   NONE: "NONE",
   BASIC: "BASIC",
@@ -88,13 +91,30 @@ export const RequestMethods = {
 export type RequestMethodType = keyof typeof RequestMethods;
 export type RequestMethod = (typeof RequestMethods)[RequestMethodType];
 
+// One metric of the `Server-Timing` response header
+export interface ServerTimingMetric {
+  name: string;
+  duration: number | null; // ms
+  description: string | null;
+}
+
 export type ResponsePerformance = {
   duration: number;
   latency: number;
   processingTime: number;
   transferTime: number;
   transferSize: number;
+  // Body size after content decoding
+  decodedSize: number;
   transferEncoding: string;
+  // Request phases (ms); dns, tcpConnect and tlsHandshake are 0 on a reused connection
+  dns: number;
+  tcpConnect: number;
+  tlsHandshake: number;
+  ttfb: number;
+  download: number;
+  connectionReused: boolean;
+  serverTiming: ServerTimingMetric[];
 };
 
 export interface Request {
@@ -109,6 +129,38 @@ export interface Response {
   headers: ResponseHeaders;
   contentType: string | null;
   statusCode: number;
+  // Text, or base64 encoded bytes for binary bodies
   content: string;
+  bodyEncoding: BodyEncoding;
+  // MIME type sniffed from the leading bytes of a binary body
+  detectedMimeType: string | null;
+  // Charset the text body was decoded from
+  charset: string | null;
+  // Whether content holds only the start of a streamed body
+  bodyTruncated: boolean;
   performance: ResponsePerformance;
+  // Round trips of a multi-step authorization, e.g. a Digest challenge
+  authExchanges: AuthExchange[];
+  // Redirects followed to reach the response, in order
+  redirects: RedirectHop[];
+}
+
+// A redirect response and the request it answered
+export interface RedirectHop {
+  method: string;
+  url: string;
+  statusCode: number;
+  headers: ResponseHeaders;
+  location: string; // resolved against url
+  duration: number; // ms
+}
+
+// One request/response round trip of a multi-step authorization
+export interface AuthExchange {
+  method: string;
+  url: string;
+  requestHeaders: ResponseHeaders;
+  statusCode: number;
+  headers: ResponseHeaders;
+  duration: number; // ms
 }
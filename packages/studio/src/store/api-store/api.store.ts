@@ -286,14 +286,28 @@ const useApiStore = create<APIStoreState>()((set, get) => ({
           contentType: "text/plain",
           statusCode: 0,
           content: response.message || "Request failed",
+          bodyEncoding: "TEXT" as const,
+          detectedMimeType: null,
+          charset: null,
+          bodyTruncated: false,
           performance: {
             duration: 0,
             latency: 0,
             processingTime: 0,
             transferTime: 0,
             transferSize: 0,
+            decodedSize: 0,
             transferEncoding: "identity",
+            dns: 0,
+            tcpConnect: 0,
+            tlsHandshake: 0,
+            ttfb: 0,
+            download: 0,
+            connectionReused: false,
+            serverTiming: [],
           },
+          authExchanges: [],
+          redirects: [],
         };
 
         set((state) => ({
@@ -329,14 +343,28 @@ const useApiStore = create<APIStoreState>()((set, get) => ({
         contentType: "text/plain",
         statusCode: 0,
         content: `Request Error: ${errorMessage}`,
+        bodyEncoding: "TEXT" as const,
+        detectedMimeType: null,
+        charset: null,
+        bodyTruncated: false,
         performance: {
           duration: 0,
           latency: 0,
           processingTime: 0,
           transferTime: 0,
           transferSize: 0,
+          decodedSize: 0,
           transferEncoding: "identity",
+          dns: 0,
+          tcpConnect: 0,
+          tlsHandshake: 0,
+          ttfb: 0,
+          download: 0,
+          connectionReused: false,
+          serverTiming: [],
         },
+        authExchanges: [],
+        redirects: [],
       };
 
       set((state) => ({